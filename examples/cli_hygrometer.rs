@@ -0,0 +1,160 @@
+use otus_tokio_devices::client::{Client, Delivery};
+use otus_tokio_devices::device::Device;
+use otus_tokio_devices::humidity::Humidity;
+use otus_tokio_devices::hygrometer::Hygrometer;
+use otus_tokio_devices::reading::Stamped;
+
+use color_eyre::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures::{FutureExt, StreamExt};
+use ratatui::{
+    DefaultTerminal, Frame,
+    layout::Rect,
+    widgets::{Block, Borders, Gauge, Paragraph},
+};
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    let client = Client::from_env().map_err(|e| color_eyre::eyre::eyre!(e))?;
+
+    let terminal = ratatui::init();
+    let result = App::new(client).run(terminal).await;
+    ratatui::restore();
+    result
+}
+
+#[derive(Debug)]
+pub struct App {
+    running: bool,
+    event_stream: EventStream,
+
+    level: f32,
+    client: Client,
+    reply: String,
+}
+
+impl App {
+    /// Construct a new instance of [`App`].
+    pub fn new(client: Client) -> Self {
+        Self {
+            running: bool::default(),
+            event_stream: EventStream::default(),
+            level: 50.0,
+            client,
+            reply: String::default(),
+        }
+    }
+
+    /// Run the application's main loop.
+    pub async fn run(mut self, mut terminal: DefaultTerminal) -> Result<()> {
+        self.running = true;
+        while self.running {
+            terminal.draw(|frame| self.draw(frame))?;
+            self.handle_crossterm_events().await?;
+        }
+        Ok(())
+    }
+
+    /// Renders the user interface.
+    ///
+    /// This is where you add new widgets. See the following resources for more information:
+    /// - <https://docs.rs/ratatui/latest/ratatui/widgets/index.html>
+    /// - <https://github.com/ratatui/ratatui/tree/master/examples>
+    fn draw(&mut self, frame: &mut Frame) {
+        // Создаем элемент Gauge (шкала)
+        let gauge = Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title(
+                "Управление гигрометром. Нажмите [\"+\"/\"-\"] для изменения значений. Esc - выход",
+            ))
+            .label(format!(
+                "Влажность: {:.1} % из {} %",
+                self.level,
+                Humidity::MAX_HUMIDITY
+            ))
+            .ratio(Humidity::ratio(self.level).into());
+
+        let area = Rect {
+            height: frame.area().height.saturating_sub(2),
+            ..frame.area()
+        };
+        frame.render_widget(gauge, area);
+
+        let status = Rect {
+            y: area.y + area.height,
+            height: 1,
+            ..frame.area()
+        };
+        frame.render_widget(
+            Paragraph::new(format!("Ответ сервера: {}", self.reply)),
+            status,
+        )
+    }
+
+    /// Reads the crossterm events and updates the state of [`App`].
+    async fn handle_crossterm_events(&mut self) -> Result<()> {
+        tokio::select! {
+            event = self.event_stream.next().fuse() => {
+                if let Some(Ok(evt)) = event {
+                    match evt {
+                        Event::Key(key)
+                            if key.kind == KeyEventKind::Press
+                                => self.on_key_event(key).await,
+                        Event::Mouse(_) => {}
+                        Event::Resize(_, _) => {}
+                        _ => {}
+                    }
+                }
+            }
+            _ = tokio::time::sleep(tokio::time::Duration::from_millis(20)) => {
+                // Sleep for a short duration to avoid busy waiting.
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the key events and updates the state of [`App`].
+    async fn on_key_event(&mut self, key: KeyEvent) {
+        match (key.modifiers, key.code) {
+            (_, KeyCode::Esc | KeyCode::Char('q'))
+            | (KeyModifiers::CONTROL, KeyCode::Char('c') | KeyCode::Char('C')) => self.quit(),
+            // Add other key handlers here.
+            (_, KeyCode::Char('+')) => self.increase_level().await,
+            (_, KeyCode::Char('-')) => self.decrease_level().await,
+            _ => {}
+        }
+    }
+
+    /// Set running to false to quit the application.
+    fn quit(&mut self) {
+        self.running = false;
+    }
+
+    async fn increase_level(&mut self) {
+        if self.level < Humidity::MAX_HUMIDITY {
+            self.level += Humidity::GRADUATION;
+        }
+
+        self.notify().await
+    }
+
+    async fn decrease_level(&mut self) {
+        if self.level > Humidity::MIN_HUMIDITY {
+            self.level -= Humidity::GRADUATION;
+        }
+
+        self.notify().await
+    }
+
+    async fn notify(&mut self) {
+        let hygrometer = Hygrometer::new(Humidity::new(self.level));
+
+        self.reply = match self.client.report(Stamped::now(hygrometer.data())).await {
+            Ok(Delivery::Delivered(reply)) => reply.to_string(),
+            Ok(Delivery::Queued { queued, error }) => {
+                format!("нет связи ({}), в очереди: {}", error, queued)
+            }
+            Ok(Delivery::Dropped) => "очередь полна, показание потеряно".into(),
+            Err(e) => format!("ошибка: {}", e),
+        };
+    }
+}
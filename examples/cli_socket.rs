@@ -1,6 +1,7 @@
-use std::{io::Write, net::TcpStream};
-
+use otus_tokio_devices::client::{Client, Delivery};
+use otus_tokio_devices::device::Device;
 use otus_tokio_devices::power::Power;
+use otus_tokio_devices::reading::Stamped;
 use otus_tokio_devices::socket::Socket;
 
 use color_eyre::Result;
@@ -9,13 +10,15 @@ use futures::{FutureExt, StreamExt};
 use ratatui::{
     DefaultTerminal, Frame,
     layout::Rect,
-    widgets::{Block, Borders, Gauge},
+    widgets::{Block, Borders, Gauge, Paragraph},
 };
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
+    let client = Client::from_env().map_err(|e| color_eyre::eyre::eyre!(e))?;
+
     let terminal = ratatui::init();
-    let result = App::new().run(terminal).await;
+    let result = App::new(client).run(terminal).await;
     ratatui::restore();
     result
 }
@@ -27,23 +30,23 @@ pub struct App {
     event_stream: EventStream,
 
     level: f32,
+    on: bool,
+    client: Client,
+    reply: String,
 }
 
-impl Default for App {
-    fn default() -> Self {
+impl App {
+    /// Construct a new instance of [`App`].
+    pub fn new(client: Client) -> Self {
         Self {
             running: bool::default(),
             event_stream: EventStream::default(),
             level: 1500.0,
+            on: true,
+            client,
+            reply: String::default(),
         }
     }
-}
-
-impl App {
-    /// Construct a new instance of [`App`].
-    pub fn new() -> Self {
-        Self::default()
-    }
 
     /// Run the application's main loop.
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> Result<()> {
@@ -64,38 +67,47 @@ impl App {
         // Создаем элемент Gauge (шкала)
         let gauge = Gauge::default()
             .block(Block::default().borders(Borders::ALL).title(
-                "Управление розеткой. Нажмите [\"+\"/\"-\"] для изменения значений. Esc - выход",
+                "Управление розеткой. Нажмите [\"+\"/\"-\"] для изменения значений, \"o\" - вкл/выкл. Esc - выход",
             ))
-            .label(format!(
-                "Мощность {:.1} W из {} W",
-                self.level,
-                Power::MAX_POWER
-            ))
-            .ratio(Power::ratio(self.level).into());
+            .label(match self.on {
+                true => format!("Мощность {:.1} W из {} W", self.level, Power::MAX_POWER),
+                false => "Выключена".to_string(),
+            })
+            .ratio(match self.on {
+                true => Power::ratio(self.level).into(),
+                false => 0.0,
+            });
 
         let area = Rect {
             height: frame.area().height.saturating_sub(2),
             ..frame.area()
         };
-        frame.render_widget(gauge, area)
+        frame.render_widget(gauge, area);
+
+        let status = Rect {
+            y: area.y + area.height,
+            height: 1,
+            ..frame.area()
+        };
+        frame.render_widget(
+            Paragraph::new(format!("Ответ сервера: {}", self.reply)),
+            status,
+        )
     }
 
     /// Reads the crossterm events and updates the state of [`App`].
     async fn handle_crossterm_events(&mut self) -> Result<()> {
         tokio::select! {
             event = self.event_stream.next().fuse() => {
-                match event {
-                    Some(Ok(evt)) => {
-                        match evt {
-                            Event::Key(key)
-                                if key.kind == KeyEventKind::Press
-                                    => self.on_key_event(key).await,
-                            Event::Mouse(_) => {}
-                            Event::Resize(_, _) => {}
-                            _ => {}
-                        }
+                if let Some(Ok(evt)) = event {
+                    match evt {
+                        Event::Key(key)
+                            if key.kind == KeyEventKind::Press
+                                => self.on_key_event(key).await,
+                        Event::Mouse(_) => {}
+                        Event::Resize(_, _) => {}
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
             _ = tokio::time::sleep(tokio::time::Duration::from_millis(20)) => {
@@ -113,6 +125,7 @@ impl App {
             // Add other key handlers here.
             (_, KeyCode::Char('+')) => self.increase_level().await,
             (_, KeyCode::Char('-')) => self.decrease_level().await,
+            (_, KeyCode::Char('o')) => self.toggle().await,
             _ => {}
         }
     }
@@ -138,11 +151,25 @@ impl App {
         self.notify().await
     }
 
-    async fn notify(&mut self) {
-        let socket = Socket::new(Power::new(self.level));
+    async fn toggle(&mut self) {
+        self.on = !self.on;
+
+        self.notify().await
+    }
 
-        let mut tcp_stream = TcpStream::connect("localhost:8080").expect("Unable to connect");
+    async fn notify(&mut self) {
+        let socket = match self.on {
+            true => Socket::on(Power::new(self.level)),
+            false => Socket::off(),
+        };
 
-        tcp_stream.write_all(socket.to_string().as_bytes()).unwrap();
+        self.reply = match self.client.report(Stamped::now(socket.data())).await {
+            Ok(Delivery::Delivered(reply)) => reply.to_string(),
+            Ok(Delivery::Queued { queued, error }) => {
+                format!("нет связи ({}), в очереди: {}", error, queued)
+            }
+            Ok(Delivery::Dropped) => "очередь полна, показание потеряно".into(),
+            Err(e) => format!("ошибка: {}", e),
+        };
     }
 }
@@ -1,5 +1,6 @@
-use std::{io::Write, net::TcpStream};
-
+use otus_tokio_devices::client::{Client, Delivery};
+use otus_tokio_devices::device::Device;
+use otus_tokio_devices::reading::Stamped;
 use otus_tokio_devices::temperature::Temperature;
 use otus_tokio_devices::termometer::Termometer;
 
@@ -9,13 +10,15 @@ use futures::{FutureExt, StreamExt};
 use ratatui::{
     DefaultTerminal, Frame,
     layout::Rect,
-    widgets::{Block, Borders, Gauge},
+    widgets::{Block, Borders, Gauge, Paragraph},
 };
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
+    let client = Client::from_env().map_err(|e| color_eyre::eyre::eyre!(e))?;
+
     let terminal = ratatui::init();
-    let result = App::new().run(terminal).await;
+    let result = App::new(client).run(terminal).await;
     ratatui::restore();
     result
 }
@@ -26,23 +29,21 @@ pub struct App {
     event_stream: EventStream,
 
     level: f32,
+    client: Client,
+    reply: String,
 }
 
-impl Default for App {
-    fn default() -> Self {
+impl App {
+    /// Construct a new instance of [`App`].
+    pub fn new(client: Client) -> Self {
         Self {
             running: bool::default(),
             event_stream: EventStream::default(),
             level: f32::default(),
+            client,
+            reply: String::default(),
         }
     }
-}
-
-impl App {
-    /// Construct a new instance of [`App`].
-    pub fn new() -> Self {
-        Self::default()
-    }
 
     /// Run the application's main loop.
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> Result<()> {
@@ -76,25 +77,32 @@ impl App {
             height: frame.area().height.saturating_sub(2),
             ..frame.area()
         };
-        frame.render_widget(gauge, area)
+        frame.render_widget(gauge, area);
+
+        let status = Rect {
+            y: area.y + area.height,
+            height: 1,
+            ..frame.area()
+        };
+        frame.render_widget(
+            Paragraph::new(format!("Ответ сервера: {}", self.reply)),
+            status,
+        )
     }
 
     /// Reads the crossterm events and updates the state of [`App`].
     async fn handle_crossterm_events(&mut self) -> Result<()> {
         tokio::select! {
             event = self.event_stream.next().fuse() => {
-                match event {
-                    Some(Ok(evt)) => {
-                        match evt {
-                            Event::Key(key)
-                                if key.kind == KeyEventKind::Press
-                                    => self.on_key_event(key).await,
-                            Event::Mouse(_) => {}
-                            Event::Resize(_, _) => {}
-                            _ => {}
-                        }
+                if let Some(Ok(evt)) = event {
+                    match evt {
+                        Event::Key(key)
+                            if key.kind == KeyEventKind::Press
+                                => self.on_key_event(key).await,
+                        Event::Mouse(_) => {}
+                        Event::Resize(_, _) => {}
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
             _ = tokio::time::sleep(tokio::time::Duration::from_millis(20)) => {
@@ -140,10 +148,13 @@ impl App {
     async fn notify(&mut self) {
         let termometer = Termometer::new(Temperature::new(self.level));
 
-        let mut tcp_stream = TcpStream::connect("localhost:8080").expect("Unable to connect");
-
-        tcp_stream
-            .write_all(termometer.to_string().as_bytes())
-            .unwrap();
+        self.reply = match self.client.report(Stamped::now(termometer.data())).await {
+            Ok(Delivery::Delivered(reply)) => reply.to_string(),
+            Ok(Delivery::Queued { queued, error }) => {
+                format!("нет связи ({}), в очереди: {}", error, queued)
+            }
+            Ok(Delivery::Dropped) => "очередь полна, показание потеряно".into(),
+            Err(e) => format!("ошибка: {}", e),
+        };
     }
 }
@@ -0,0 +1,366 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json, Router,
+    extract::{self, ConnectInfo, WebSocketUpgrade, rejection::JsonRejection},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc::Sender},
+};
+
+use crate::{
+    event::DeviceEvent,
+    poll::{self, Poller},
+    reading::Reading,
+    sensor_data::SensorData,
+    socket::SocketCommand,
+    state::State,
+    stream::{self, Filter, StreamConfig},
+};
+
+/// JSON API over HTTP:
+///
+/// - `GET /devices` lists the devices with their live data,
+/// - `GET /devices/<device>` gives a single one, e.g. `/devices/kitchen/kettle`
+///   or `/devices/Termometer`,
+/// - `GET /history?device=<device>&from=<time>&to=<time>` lists the stored
+///   readings, every parameter is optional and times are RFC 3339,
+/// - `POST /commands` sends a command to a polled socket, e.g.
+///   `{"device": "kitchen/kettle", "command": "power 1500"}`, see
+///   [`Api::with_token`],
+/// - `GET /stream?kind=<kinds>&device=<devices>&room=<rooms>` upgrades to a
+///   WebSocket streaming the readings, see [`stream::run`].
+///
+/// Commands go only to the devices of [`State::poll`].
+#[derive(Debug, Clone)]
+pub struct Api {
+    state: Arc<Mutex<State>>,
+    tx: Sender<Arc<DeviceEvent>>,
+    /// Token of the clients allowed to send commands.
+    token: Option<Arc<str>>,
+    /// Events published to the stream clients, the stream is off without them.
+    events: Option<broadcast::Sender<Arc<DeviceEvent>>>,
+    stream: StreamConfig,
+}
+
+impl Api {
+    pub fn new(state: Arc<Mutex<State>>, tx: Sender<Arc<DeviceEvent>>) -> Self {
+        Self {
+            state,
+            tx,
+            token: None,
+            events: None,
+            stream: StreamConfig::default(),
+        }
+    }
+
+    /// Takes commands only with `Authorization: Bearer <token>`. Without a
+    /// token only clients on the same host may send them.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token.map(Arc::from);
+        self
+    }
+
+    /// Streams the readings published on `events`, see [`stream::fan_out`].
+    pub fn with_stream(
+        mut self,
+        events: broadcast::Sender<Arc<DeviceEvent>>,
+        config: StreamConfig,
+    ) -> Self {
+        self.events = Some(events);
+        self.stream = config;
+        self
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/devices", get(devices))
+            .route("/devices/{*device}", get(device))
+            .route("/history", get(history))
+            .route("/commands", post(command))
+            .route("/stream", get(subscribe))
+            .with_state(self)
+    }
+
+    fn allowed(&self, peer: SocketAddr, headers: &HeaderMap) -> bool {
+        let Some(token) = &self.token else {
+            return peer.ip().is_loopback();
+        };
+
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|sent| sent == &**token)
+    }
+}
+
+/// Device with its live data, by the same key as [`State::violations`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceStatus {
+    pub device: String,
+    pub kind: &'static str,
+    pub value: f32,
+    pub unit: &'static str,
+    /// Relay state of sockets.
+    pub on: Option<bool>,
+    /// `online`, `stale` or `offline`.
+    pub presence: String,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl DeviceStatus {
+    /// `None` for a device the state does not know.
+    pub fn new(state: &State, device: &str, now: DateTime<Utc>) -> Option<Self> {
+        let data = state.device_data(device)?;
+
+        Some(Self {
+            device: device.to_string(),
+            kind: data.kind(),
+            value: data.value().unwrap_or_default(),
+            unit: data.unit(),
+            on: relay(&data),
+            presence: state.presence(device, now).to_string(),
+            last_seen: state.last_seen(device),
+        })
+    }
+}
+
+/// Stored reading.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sample {
+    /// `<room>/<device>` path of house devices, the kind of standalone ones.
+    pub device: String,
+    pub kind: &'static str,
+    pub value: f32,
+    pub unit: &'static str,
+    pub on: Option<bool>,
+    /// Value as sent by the device, when calibration changed it.
+    pub raw: Option<f32>,
+    /// See [`Reading::time`].
+    pub time: DateTime<Utc>,
+    pub received: DateTime<Utc>,
+}
+
+impl From<&Reading> for Sample {
+    fn from(reading: &Reading) -> Self {
+        let device = match &reading.device {
+            Some(path) if path.contains('/') => path.clone(),
+            _ => reading.data.kind().to_string(),
+        };
+
+        Self {
+            device,
+            kind: reading.data.kind(),
+            value: reading.data.value().unwrap_or_default(),
+            unit: reading.data.unit(),
+            on: relay(&reading.data),
+            raw: reading.raw,
+            time: reading.time(),
+            received: reading.received,
+        }
+    }
+}
+
+fn relay(data: &SensorData) -> Option<bool> {
+    match data {
+        SensorData::Socket(socket) => Some(socket.on),
+        _ => None,
+    }
+}
+
+/// Body of `POST /commands`, the command as a socket takes it: `on`, `off` or
+/// `power <watts>`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CommandRequest {
+    pub device: String,
+    pub command: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    device: Option<String>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+/// Answered with its status and `{"error": "<reason>"}`.
+#[derive(Debug)]
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let ApiError(status, error) = self;
+        (status, Json(serde_json::json!({ "error": error }))).into_response()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError(rejection.status(), rejection.body_text())
+    }
+}
+
+fn unknown(device: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("unknown device {}", device))
+}
+
+/// Serves the API until the listener fails.
+///
+/// The router needs the address of the client to tell the local ones, see
+/// [`Api::with_token`].
+pub async fn serve(listener: TcpListener, api: Api) -> std::io::Result<()> {
+    let service = api
+        .router()
+        .into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, service).await
+}
+
+async fn devices(extract::State(api): extract::State<Api>) -> Json<Vec<DeviceStatus>> {
+    let state = api.state.lock().unwrap();
+    let now = Utc::now();
+
+    let devices = state
+        .devices()
+        .iter()
+        .filter_map(|device| DeviceStatus::new(&state, device, now))
+        .collect();
+
+    Json(devices)
+}
+
+async fn device(
+    extract::State(api): extract::State<Api>,
+    extract::Path(device): extract::Path<String>,
+) -> Result<Json<DeviceStatus>, ApiError> {
+    let state = api.state.lock().unwrap();
+
+    DeviceStatus::new(&state, &device, Utc::now())
+        .map(Json)
+        .ok_or_else(|| unknown(&device))
+}
+
+async fn history(
+    extract::State(api): extract::State<Api>,
+    extract::Query(query): extract::Query<HistoryQuery>,
+) -> Result<Json<Vec<Sample>>, ApiError> {
+    let state = api.state.lock().unwrap();
+    if let Some(device) = &query.device
+        && state.device_data(device).is_none()
+    {
+        return Err(unknown(device));
+    }
+
+    let from = query.from.unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let to = query.to.unwrap_or(DateTime::<Utc>::MAX_UTC);
+    let samples = state
+        .history()
+        .range(from, to)
+        .map(Sample::from)
+        .filter(|sample| query.device.as_ref().is_none_or(|d| *d == sample.device))
+        .collect();
+
+    Ok(Json(samples))
+}
+
+/// Sends the command to the device the way a controller would and stores the
+/// state it reports back, see [`Poller::command`].
+async fn command(
+    extract::State(api): extract::State<Api>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    request: Result<Json<CommandRequest>, JsonRejection>,
+) -> Result<Json<DeviceStatus>, ApiError> {
+    if !api.allowed(peer, &headers) {
+        tracing::warn!(%peer, "command refused");
+        return Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            "commands need the API token".into(),
+        ));
+    }
+    let Json(request) = request?;
+    let device = request.device;
+    let command = match request.command.parse::<SocketCommand>() {
+        Ok(command) => command,
+        Err(e) => return Err(ApiError(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+
+    let config = {
+        let state = api.state.lock().unwrap();
+        match state.device_data(&device) {
+            Some(SensorData::Socket(_)) => {}
+            Some(data) => {
+                return Err(ApiError(
+                    StatusCode::BAD_REQUEST,
+                    format!("{} is a {}, not a socket", device, data.kind()),
+                ));
+            }
+            None => return Err(unknown(&device)),
+        }
+        match state.poll(&device) {
+            Some(config) => config.clone(),
+            None => {
+                return Err(ApiError(
+                    StatusCode::CONFLICT,
+                    format!("{} is not polled, the server cannot reach it", device),
+                ));
+            }
+        }
+    };
+
+    let _ = api
+        .tx
+        .send(Arc::new(DeviceEvent::Command(format!(
+            "{} {}",
+            device, command
+        ))))
+        .await;
+    tracing::info!(device, %command, "command");
+
+    let stamped = Poller::new(config).command(command).await.map_err(|e| {
+        tracing::warn!(device, error = format!("{:#}", e), "command failed");
+        ApiError(StatusCode::BAD_GATEWAY, format!("{:#}", e))
+    })?;
+
+    let reading = Reading::new(stamped.message, stamped.time).with_device(Some(device.clone()));
+    let (event, status) = {
+        let mut state = api.state.lock().unwrap();
+        let event = poll::store(&mut state, reading);
+        (event, DeviceStatus::new(&state, &device, Utc::now()))
+    };
+
+    let result = match &event {
+        DeviceEvent::Rejected { reason, .. } => {
+            Err(ApiError(StatusCode::UNPROCESSABLE_ENTITY, reason.clone()))
+        }
+        _ => status.map(Json).ok_or_else(|| unknown(&device)),
+    };
+    let _ = api.tx.send(Arc::new(event)).await;
+
+    result
+}
+
+async fn subscribe(
+    extract::State(api): extract::State<Api>,
+    extract::Query(filter): extract::Query<Filter>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let Some(events) = &api.events else {
+        return Err(ApiError(StatusCode::NOT_FOUND, "stream is off".into()));
+    };
+    let events = events.subscribe();
+
+    Ok(
+        upgrade
+            .on_upgrade(move |socket| stream::run(socket, api.state, events, filter, api.stream)),
+    )
+}
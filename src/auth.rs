@@ -0,0 +1,88 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Message signed with the pre-shared key of a device:
+/// `Auth <device> <counter> <hmac> <message>`.
+///
+/// The HMAC-SHA256 covers `<device> <counter> <message>`; the counter must grow
+/// with every message so a captured frame cannot be replayed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signed {
+    pub device: String,
+    pub counter: u64,
+    mac: String,
+    pub message: String,
+}
+
+impl Signed {
+    pub fn new(device: &str, key: &[u8], counter: u64, message: &str) -> Self {
+        let mac = hex::encode(
+            Self::mac(device, key, counter, message)
+                .finalize()
+                .into_bytes(),
+        );
+
+        Self {
+            device: device.to_string(),
+            counter,
+            mac,
+            message: message.to_string(),
+        }
+    }
+
+    /// Checks the signature in constant time.
+    pub fn verify(&self, key: &[u8]) -> bool {
+        let Ok(mac) = hex::decode(&self.mac) else {
+            return false;
+        };
+
+        Self::mac(&self.device, key, self.counter, &self.message)
+            .verify_slice(&mac)
+            .is_ok()
+    }
+
+    fn mac(device: &str, key: &[u8], counter: u64, message: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(format!("{} {} {}", device, counter, message).as_bytes());
+        mac
+    }
+}
+
+impl Display for Signed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Auth {} {} {} {}",
+            self.device, self.counter, self.mac, self.message
+        )
+    }
+}
+
+impl FromStr for Signed {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(r"^Auth(\s)+(\S+)(\s)+(\d+)(\s)+([0-9a-fA-F]+)(\s)+(.+)$").unwrap();
+
+        match re.captures(s.trim()) {
+            Some(caps) => {
+                if let Ok(counter) = caps[4].parse::<u64>() {
+                    return Ok(Self {
+                        device: caps[2].to_string(),
+                        counter,
+                        mac: caps[6].to_string(),
+                        message: caps[8].to_string(),
+                    });
+                }
+
+                Err("cannot parse counter from string".into())
+            }
+            None => Err("does not look like signed message".into()),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::{Arc, Mutex};
+
+use otus_tokio_devices::emulator::{self, SocketEmulator};
+use tokio::net::TcpListener;
+
+/// Smart socket emulator: `emulator [address] [watts]`.
+///
+/// Listens for controller commands, `localhost:7000` and 1000 W by default.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "localhost:7000".into());
+    let power = match std::env::args().nth(2) {
+        Some(power) => power.parse()?,
+        None => 1000.0,
+    };
+
+    let emulator = SocketEmulator::new(power).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    let listener = TcpListener::bind(&address).await?;
+    println!(
+        "Socket of {} W listening on {}, Ctrl+C to stop",
+        power, address
+    );
+
+    let emulator = Arc::new(Mutex::new(emulator));
+    tokio::select! {
+        result = emulator::serve(listener, emulator) => result?,
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    Ok(())
+}
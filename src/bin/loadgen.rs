@@ -0,0 +1,33 @@
+use otus_tokio_devices::load::{self, LoadConfig};
+
+/// Load generator: `loadgen [config.toml]`.
+///
+/// Prints the results per transport and writes the JSON summary to the
+/// configured file or stdout.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = match std::env::args().nth(1) {
+        Some(path) => LoadConfig::load(path)?,
+        None => LoadConfig::default(),
+    };
+
+    eprintln!(
+        "Loading {} with {} TCP and {} UDP connections at {} msg/s each for {} s",
+        config.server,
+        config.tcp_connections,
+        config.udp_connections,
+        config.rate,
+        config.duration_s
+    );
+    let summary = load::run(&config).await?;
+    eprintln!("TCP: {}", summary.tcp);
+    eprintln!("UDP: {}", summary.udp);
+
+    let json = serde_json::to_string_pretty(&summary)?;
+    match &config.summary {
+        Some(path) => std::fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+
+    Ok(())
+}
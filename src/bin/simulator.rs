@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use otus_tokio_devices::{room::Room, simulator::SimulatorConfig, socket::SocketCommand};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing_subscriber::EnvFilter;
+
+/// Headless device simulator: `simulator [config.toml]`.
+///
+/// Heaters of the simulated rooms take commands from stdin, one per line:
+/// `<heater id> on`, `<heater id> off` or `<heater id> power <watts>`.
+///
+/// Every message with its reply is logged to stdout, `RUST_LOG` picks the level.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .init();
+
+    let config = match std::env::args().nth(1) {
+        Some(path) => SimulatorConfig::load(path)?,
+        None => SimulatorConfig::default(),
+    };
+
+    let devices = config.devices();
+    println!(
+        "Simulating {} devices and {} rooms against {}, Ctrl+C to stop",
+        devices.len(),
+        config.rooms.len(),
+        config.server
+    );
+
+    let mut tasks = vec![];
+    for device in devices {
+        let client = device.client(&config.server);
+        tasks.push(tokio::spawn(device.run(client)));
+    }
+
+    let mut heaters = HashMap::new();
+    for room in config.rooms() {
+        let (termometer, heater) = room.clients(&config.server);
+        heaters.insert(
+            room.room().lock().unwrap().config().heater.clone(),
+            room.room(),
+        );
+        tasks.push(tokio::spawn(room.run(termometer, heater)));
+    }
+    if !heaters.is_empty() {
+        tasks.push(tokio::spawn(commands(heaters)));
+    }
+
+    tokio::signal::ctrl_c().await?;
+
+    for task in tasks {
+        task.abort();
+    }
+
+    Ok(())
+}
+
+/// Reads heater commands from stdin.
+async fn commands(heaters: HashMap<String, Arc<Mutex<Room>>>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Some((id, command)) = line.trim().split_once(' ') else {
+            continue;
+        };
+
+        match (heaters.get(id), SocketCommand::from_str(command)) {
+            (Some(room), Ok(command)) => room.lock().unwrap().command(command),
+            (None, _) => eprintln!("unknown heater {}", id),
+            (_, Err(e)) => eprintln!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Deserializer};
+
+/// Correction of raw device readings, configured per device:
+///
+/// ```toml
+/// calibration = { offset = -1.5, scale = 1.02 }
+/// # or a piecewise-linear table of (raw, true) points
+/// calibration = { points = [[0.0, 0.8], [50.0, 49.0], [100.0, 98.5]] }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Calibration {
+    /// Points sorted by the raw value, see [`Calibration::table`].
+    Table {
+        #[serde(deserialize_with = "sorted")]
+        points: Vec<(f32, f32)>,
+    },
+    Linear {
+        #[serde(default)]
+        offset: f32,
+        #[serde(default = "one")]
+        scale: f32,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn sorted<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(f32, f32)>, D::Error> {
+    let mut points = Vec::<(f32, f32)>::deserialize(deserializer)?;
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(points)
+}
+
+impl Calibration {
+    /// Table of (raw, true) points in any order.
+    pub fn table(mut points: Vec<(f32, f32)>) -> Self {
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Calibration::Table { points }
+    }
+
+    /// Corrected value for a raw reading.
+    ///
+    /// A table interpolates between the neighbouring points and extends the outer
+    /// segments beyond its ends.
+    pub fn apply(&self, raw: f32) -> f32 {
+        match self {
+            Calibration::Linear { offset, scale } => raw * scale + offset,
+            Calibration::Table { points } => match points.as_slice() {
+                [] => raw,
+                [(x, y)] => raw + (y - x),
+                _ => {
+                    let i = points
+                        .windows(2)
+                        .position(|w| raw <= w[1].0)
+                        .unwrap_or(points.len() - 2);
+                    let ((x0, y0), (x1, y1)) = (points[i], points[i + 1]);
+
+                    if x1 == x0 {
+                        return y0;
+                    }
+
+                    y0 + (raw - x0) * (y1 - y0) / (x1 - x0)
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::{
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use chrono::Utc;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{TlsConnector, rustls::pki_types::ServerName};
+
+use crate::{
+    auth::Signed, outbox::Outbox, protocol::Message, reading::Stamped, reply::Reply,
+    sensor_data::SensorData, tls,
+};
+
+/// Most queued readings sent in one batch frame.
+const BATCH_SIZE: usize = 100;
+/// Longest batch frame, well under the default [`crate::limits::LimitsConfig::max_frame`] to
+/// leave room for the address and the signature.
+const BATCH_BYTES: usize = 8192;
+/// First wait before flushing again after a [`Reply::Busy`], doubled on every
+/// one in a row up to [`BUSY_BACKOFF_MAX`].
+const BUSY_BACKOFF: Duration = Duration::from_millis(100);
+const BUSY_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Byte stream the client talks over: plain TCP or TLS.
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync> Transport for T {}
+
+/// Device side connection to the server.
+///
+/// Connects lazily on the first message and reconnects after a failure.
+pub struct Client {
+    addr: String,
+    tls: Option<(TlsConnector, ServerName<'static>)>,
+    stream: Option<BufReader<Box<dyn Transport>>>,
+    signer: Option<Signer>,
+    outbox: Option<Outbox>,
+    address: Option<String>,
+    busy: Option<Backoff>,
+}
+
+/// When to flush the outbox again after the server answered [`Reply::Busy`].
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    until: Instant,
+    delay: Duration,
+    /// Readings in the next batch: a batch the rate limit can never take is
+    /// split until it can.
+    limit: usize,
+}
+
+/// What became of a reading passed to [`Client::report`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    /// The server answered, to the reading alone or to the last batch carrying it.
+    Delivered(Reply),
+    /// The server is unreachable or busy, the reading waits with `queued` others.
+    Queued { queued: usize, error: String },
+    /// The outbox was full and gave the reading up, see [`crate::outbox::DropPolicy`].
+    Dropped,
+}
+
+impl Display for Delivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Delivery::Delivered(reply) => write!(f, "{}", reply),
+            Delivery::Queued { queued, error } => write!(f, "{} queued: {}", queued, error),
+            Delivery::Dropped => write!(f, "outbox full, dropped"),
+        }
+    }
+}
+
+/// Device id and key used to sign every message, with the replay counter.
+struct Signer {
+    device: String,
+    key: Vec<u8>,
+    counter: u64,
+}
+
+impl std::fmt::Debug for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("addr", &self.addr)
+            .field("tls", &self.tls.is_some())
+            .field("connected", &self.stream.is_some())
+            .field("device", &self.signer.as_ref().map(|s| &s.device))
+            .field("outbox", &self.outbox)
+            .field("address", &self.address)
+            .field("busy", &self.busy.is_some())
+            .finish()
+    }
+}
+
+impl Client {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            tls: None,
+            stream: None,
+            signer: None,
+            outbox: None,
+            address: None,
+            busy: None,
+        }
+    }
+
+    /// Addresses every message to a house device, `<room>/<device>`.
+    pub fn with_address(mut self, path: impl Into<String>) -> Self {
+        self.address = Some(path.into());
+        self
+    }
+
+    /// Keeps readings passed to [`Client::report`] while the server is unreachable.
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    pub fn outbox(&self) -> Option<&Outbox> {
+        self.outbox.as_ref()
+    }
+
+    /// Signs every message with the pre-shared key of the device, see [`Signed`].
+    ///
+    /// The counter starts from the current time in microseconds, so it keeps
+    /// growing across restarts of the device.
+    pub fn with_key(mut self, device: impl Into<String>, key: impl Into<Vec<u8>>) -> Self {
+        let counter = Utc::now().timestamp_micros().max(0) as u64;
+        self.signer = Some(Signer {
+            device: device.into(),
+            key: key.into(),
+            counter,
+        });
+        self
+    }
+
+    /// Talks to the server over TLS, checking its certificate against `server_name`.
+    pub fn with_tls(mut self, connector: TlsConnector, server_name: &str) -> anyhow::Result<Self> {
+        let server_name = ServerName::try_from(server_name.to_string())?;
+        self.tls = Some((connector, server_name));
+        Ok(self)
+    }
+
+    /// Builds a client from environment variables:
+    ///
+    /// - `SERVER_ADDR` - server address, `localhost:8080` by default;
+    /// - `TLS_CA` - CA of the server certificate, switches TLS on;
+    /// - `TLS_CERT`, `TLS_KEY` - client certificate and key for mutual TLS;
+    /// - `TLS_SERVER_NAME` - name in the server certificate, host of `SERVER_ADDR` by default;
+    /// - `DEVICE_ID`, `DEVICE_KEY` - id and pre-shared key to sign messages with;
+    /// - `DEVICE_PATH` - `<room>/<device>` address of the device in the house;
+    /// - `OUTBOX_CAPACITY` - readings kept while offline, [`Outbox::DEFAULT_CAPACITY`] by default;
+    /// - `OUTBOX_FILE`, `OUTBOX_MEMORY` - spill file and readings kept in memory before spilling.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let addr = std::env::var("SERVER_ADDR").unwrap_or_else(|_| "localhost:8080".into());
+
+        let capacity = match std::env::var("OUTBOX_CAPACITY") {
+            Ok(capacity) => capacity.parse()?,
+            Err(_) => Outbox::DEFAULT_CAPACITY,
+        };
+        let mut outbox = Outbox::new(capacity);
+        if let Ok(path) = std::env::var("OUTBOX_FILE") {
+            let memory = match std::env::var("OUTBOX_MEMORY") {
+                Ok(memory) => memory.parse()?,
+                Err(_) => BATCH_SIZE,
+            };
+            outbox = outbox.with_spill(path, memory)?;
+        }
+
+        let mut client = Self::new(addr.clone()).with_outbox(outbox);
+        if let Ok(path) = std::env::var("DEVICE_PATH") {
+            client = client.with_address(path);
+        }
+
+        if let (Ok(device), Ok(key)) = (std::env::var("DEVICE_ID"), std::env::var("DEVICE_KEY")) {
+            client = client.with_key(device, key);
+        }
+
+        let Ok(ca) = std::env::var("TLS_CA") else {
+            return Ok(client);
+        };
+
+        let cert = std::env::var("TLS_CERT").ok();
+        let key = std::env::var("TLS_KEY").ok();
+        let identity = match (&cert, &key) {
+            (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+            _ => None,
+        };
+
+        let server_name = std::env::var("TLS_SERVER_NAME").unwrap_or_else(|_| {
+            let host = addr
+                .rsplit_once(':')
+                .map_or(addr.as_str(), |(host, _)| host);
+            host.to_string()
+        });
+
+        client.with_tls(tls::connector(ca, identity)?, &server_name)
+    }
+
+    /// Sends a message and waits for the server [`Reply`].
+    pub async fn send(&mut self, message: &impl Display) -> anyhow::Result<Reply> {
+        let result = self.exchange(message, None).await;
+        if result.is_err() {
+            self.stream = None;
+        }
+
+        result
+    }
+
+    /// Sends a reading, keeping it in the outbox if the server is unreachable.
+    ///
+    /// Once the server is back, queued readings go first, oldest first, in batches
+    /// with their original timestamps; the new reading goes with the last batch.
+    /// A reading may be delivered twice if the connection breaks before the reply.
+    /// Without an outbox this is [`Client::send`].
+    pub async fn report(&mut self, reading: Stamped<SensorData>) -> anyhow::Result<Delivery> {
+        let Some(outbox) = self.outbox.as_mut() else {
+            let reply = self.send(&Message::Reading(reading)).await?;
+            return Ok(Delivery::Delivered(reply));
+        };
+
+        if outbox.is_empty() {
+            let error = match self.send(&Message::Reading(reading.clone())).await {
+                Ok(Reply::Busy(reason)) => self.back_off(&reason, BATCH_SIZE),
+                Ok(reply) => return Ok(Delivery::Delivered(reply)),
+                Err(e) => e,
+            };
+            let queued = match self.outbox.as_mut() {
+                Some(outbox) => outbox.push(reading)?,
+                None => false,
+            };
+            return Ok(match queued {
+                true => self.queued(error),
+                false => Delivery::Dropped,
+            });
+        }
+
+        // Очередь разбираем и тогда, когда новое показание в неё не влезло
+        let queued = outbox.push(reading)?;
+        match self.flush().await {
+            Ok(Some(reply)) if queued => Ok(Delivery::Delivered(reply)),
+            Ok(_) => Ok(Delivery::Dropped),
+            Err(_) if !queued => Ok(Delivery::Dropped),
+            Err(e) => Ok(self.queued(e)),
+        }
+    }
+
+    /// Delivers the queued readings in batches, returning the reply to the last one.
+    ///
+    /// The server takes a batch as a whole, so a rejected batch is split in halves
+    /// until the readings it refuses are alone; those are dropped, sending them
+    /// again would not help. A batch the server is too busy for stays queued and
+    /// the outbox is not flushed again until a backoff passes.
+    pub async fn flush(&mut self) -> anyhow::Result<Option<Reply>> {
+        let now = Instant::now();
+        if let Some(busy) = self.busy
+            && now < busy.until
+        {
+            let wait = busy.until - now;
+            return Err(anyhow!("server busy, next try in {} ms", wait.as_millis()));
+        }
+
+        let mut last = None;
+        let mut limit = self.busy.map_or(BATCH_SIZE, |b| b.limit);
+
+        while let Some(outbox) = self.outbox.as_mut() {
+            let batch = fit(outbox.peek(limit)?, BATCH_BYTES);
+            if batch.is_empty() {
+                break;
+            }
+
+            let count = batch.len();
+            let reply = self.send(&Message::Batch(batch)).await?;
+            if let Reply::Busy(reason) = &reply {
+                return Err(self.back_off(reason, (count / 2).max(1)));
+            }
+            self.busy = None;
+            if let Reply::Nack(_) = reply
+                && count > 1
+            {
+                limit = count / 2;
+                continue;
+            }
+
+            if let Some(outbox) = self.outbox.as_mut() {
+                outbox.remove(count);
+            }
+            if let Reply::Nack(reason) = &reply {
+                tracing::warn!(reason, "queued reading refused, dropped");
+                limit = BATCH_SIZE;
+            }
+            last = Some(reply);
+        }
+
+        Ok(last)
+    }
+
+    /// Holds the outbox back after a [`Reply::Busy`], waiting twice as long as
+    /// the last time, and returns the error to report. The next batch takes at
+    /// most `limit` readings.
+    fn back_off(&mut self, reason: &str, limit: usize) -> anyhow::Error {
+        let delay = self
+            .busy
+            .map_or(BUSY_BACKOFF, |b| (b.delay * 2).min(BUSY_BACKOFF_MAX));
+        self.busy = Some(Backoff {
+            until: Instant::now() + delay,
+            delay,
+            limit,
+        });
+
+        anyhow!("server busy: {}", reason)
+    }
+
+    fn queued(&self, error: anyhow::Error) -> Delivery {
+        Delivery::Queued {
+            queued: self.outbox.as_ref().map_or(0, Outbox::len),
+            error: error.to_string(),
+        }
+    }
+
+    /// Same as [`Client::send`], but writes the frame in `chunk` byte pieces with
+    /// `delay` between them, like a device on a poor link.
+    pub async fn send_slowly(
+        &mut self,
+        message: &impl Display,
+        chunk: usize,
+        delay: Duration,
+    ) -> anyhow::Result<Reply> {
+        let result = self.exchange(message, Some((chunk.max(1), delay))).await;
+        if result.is_err() {
+            self.stream = None;
+        }
+
+        result
+    }
+
+    /// Drops the connection without a goodbye; the next message reconnects.
+    pub fn disconnect(&mut self) {
+        self.stream = None;
+    }
+
+    /// Writes only the first half of the frame and drops the connection,
+    /// like a device losing power in the middle of a message.
+    pub async fn abort(&mut self, message: &impl Display) -> anyhow::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(BufReader::new(self.connect().await?));
+        }
+
+        let frame = self.frame(message);
+        let half = &frame.as_bytes()[..frame.len() / 2];
+        let result = self
+            .stream
+            .as_mut()
+            .unwrap()
+            .get_mut()
+            .write_all(half)
+            .await;
+        self.stream = None;
+
+        Ok(result?)
+    }
+
+    async fn connect(&self) -> anyhow::Result<Box<dyn Transport>> {
+        let tcp = TcpStream::connect(&self.addr).await?;
+
+        match &self.tls {
+            Some((connector, server_name)) => {
+                let stream = connector.connect(server_name.clone(), tcp).await?;
+                Ok(Box::new(stream))
+            }
+            None => Ok(Box::new(tcp)),
+        }
+    }
+
+    /// Newline terminated frame for a message, addressed and signed if the client
+    /// is set up so.
+    fn frame(&mut self, message: &impl Display) -> String {
+        let message = match &self.address {
+            Some(path) => format!("{} {}", path, message),
+            None => message.to_string(),
+        };
+
+        let frame = match self.signer.as_mut() {
+            Some(signer) => {
+                signer.counter += 1;
+                Signed::new(&signer.device, &signer.key, signer.counter, &message).to_string()
+            }
+            None => message,
+        };
+
+        format!("{}\n", frame)
+    }
+
+    async fn exchange(
+        &mut self,
+        message: &impl Display,
+        slowly: Option<(usize, Duration)>,
+    ) -> anyhow::Result<Reply> {
+        if self.stream.is_none() {
+            self.stream = Some(BufReader::new(self.connect().await?));
+        }
+
+        let frame = self.frame(message);
+        let stream = self.stream.as_mut().unwrap();
+        match slowly {
+            Some((chunk, delay)) => {
+                for piece in frame.as_bytes().chunks(chunk) {
+                    stream.get_mut().write_all(piece).await?;
+                    stream.get_mut().flush().await?;
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            None => stream.get_mut().write_all(frame.as_bytes()).await?,
+        }
+
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("connection closed by server"));
+        }
+        for _ in 0..Reply::continuation(&line) {
+            if stream.read_line(&mut line).await? == 0 {
+                return Err(anyhow!("connection closed by server"));
+            }
+        }
+
+        Reply::from_str(&line).map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// Oldest readings of the batch whose frame takes no more than `budget` bytes,
+/// at least one.
+fn fit(mut batch: Vec<Stamped<SensorData>>, budget: usize) -> Vec<Stamped<SensorData>> {
+    let mut size = "Batch ".len();
+    let fits = batch
+        .iter()
+        .position(|reading| {
+            size += reading.to_string().len() + "; ".len();
+            size > budget
+        })
+        .unwrap_or(batch.len());
+
+    batch.truncate(fits.max(1));
+    batch
+}
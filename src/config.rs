@@ -0,0 +1,130 @@
+use std::{collections::HashMap, path::Path, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{
+    house::HouseConfig, limits::LimitsConfig, logging::LogConfig, poll::PollConfig,
+    presence::PresenceConfig, registry::DeviceConfig, report::ReportConfig, stream::StreamConfig,
+};
+
+/// Server settings, read from a TOML file given as the first argument.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Plain TCP listener address.
+    pub listen: String,
+    /// UDP listener address, off by default.
+    pub udp: Option<String>,
+    /// Address of the Prometheus endpoint `/metrics`, off by default.
+    pub metrics: Option<String>,
+    /// Address of the JSON API, see [`crate::api::Api`], off by default.
+    pub api: Option<String>,
+    /// Token `POST /commands` of the API requires as `Authorization: Bearer
+    /// <token>`. Without it only clients on the same host may send commands.
+    pub api_token: Option<String>,
+    /// Live stream of the readings served by the API.
+    pub stream: StreamConfig,
+    /// File with every accepted reading, replayed into history on start, off
+    /// by default.
+    pub journal: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    /// Refuse unsigned messages from connections not authenticated by TLS.
+    pub require_auth: bool,
+    /// File with the last accepted counter of every signing device, so that
+    /// signed messages cannot be replayed after a restart. Off by default and
+    /// unused while no device has a key.
+    pub counters: Option<PathBuf>,
+    /// Known devices by id.
+    pub devices: HashMap<String, DeviceConfig>,
+    /// Rooms and their devices, addressed as `<room>/<device>`.
+    pub house: Option<HouseConfig>,
+    /// Status report written on a schedule.
+    pub report: Option<ReportConfig>,
+    /// Default staleness timeouts of the devices.
+    pub presence: PresenceConfig,
+    /// Devices the server queries itself, next to the ones pushing readings.
+    pub poll: Vec<PollConfig>,
+    /// Connection limits, timeouts and rate limits.
+    pub limits: LimitsConfig,
+    /// Drop readings changing less than the graduation of the device, a device
+    /// may set its own `deadband` in the registry.
+    pub deadband: bool,
+    pub log: LogConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "localhost:8080".into(),
+            udp: None,
+            metrics: None,
+            api: None,
+            api_token: None,
+            stream: StreamConfig::default(),
+            journal: None,
+            tls: None,
+            require_auth: false,
+            counters: None,
+            devices: HashMap::new(),
+            house: None,
+            report: None,
+            presence: PresenceConfig::default(),
+            poll: vec![],
+            limits: LimitsConfig::default(),
+            deadband: false,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config {}", path.display()))?;
+
+        toml::from_str(&text).with_context(|| format!("bad config {}", path.display()))
+    }
+}
+
+/// Polls the config file and passes the new settings to `on_change` every time
+/// the file is modified. Broken files are reported and skipped.
+pub async fn watch(path: PathBuf, period: Duration, mut on_change: impl FnMut(ServerConfig)) {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    let mut last = modified(&path);
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let current = modified(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        match ServerConfig::load(&path) {
+            Ok(config) => {
+                tracing::info!(path = %path.display(), "config reloaded");
+                on_change(config)
+            }
+            Err(e) => tracing::warn!(error = ?e, "config is not reloaded"),
+        }
+    }
+}
+
+/// TLS listener settings.
+#[derive(Debug, Deserialize)]
+pub struct TlsConfig {
+    pub listen: String,
+    /// Server certificate chain, PEM.
+    pub cert: PathBuf,
+    /// Server private key, PEM.
+    pub key: PathBuf,
+    /// CA for client certificates, PEM. Enables mutual TLS.
+    pub client_ca: Option<PathBuf>,
+    /// Client certificate subject common name to device id.
+    #[serde(default)]
+    pub devices: HashMap<String, String>,
+}
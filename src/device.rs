@@ -0,0 +1,268 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::{LazyLock, RwLock},
+};
+
+use crate::{
+    hygrometer::Hygrometer, sensor_data::SensorData, socket::Socket, termometer::Termometer,
+};
+
+/// Common shape of every device: a single reading within a range, sent as a line
+/// of text that starts with the device kind.
+///
+/// A new kind only needs [`register`] to be parsed, stored, placed in the house
+/// and shown: its readings travel as [`SensorData::Measurement`].
+pub trait Device:
+    Display + FromStr<Err = Box<dyn Error>> + Default + Debug + Send + Sync + 'static
+{
+    /// Device kind, also the first word of its messages.
+    const KIND: &'static str;
+    /// Unit of the reading.
+    const UNIT: &'static str;
+    /// Smallest change of the reading the device can tell.
+    const GRADUATION: f32;
+
+    /// Id of this device.
+    fn id(&self) -> &str;
+
+    fn set_id(&mut self, id: &str);
+
+    /// Current reading.
+    fn reading(&self) -> f32;
+
+    /// Sets the reading, ignoring values outside of [`Device::range`].
+    fn set_reading(&mut self, value: f32);
+
+    /// Values the device can report.
+    fn range() -> RangeInclusive<f32>;
+
+    /// The reading as it travels inside the server.
+    fn data(&self) -> SensorData {
+        SensorData::Measurement {
+            kind: Self::KIND,
+            value: self.reading(),
+        }
+    }
+
+    /// Takes over a reading and returns the value the device holds afterwards.
+    /// Readings out of range or of another kind leave the device as is.
+    fn update(&mut self, data: &SensorData) -> f32 {
+        if data.kind() == Self::KIND
+            && let Some(value) = data.value()
+        {
+            self.set_reading(value);
+        }
+        self.reading()
+    }
+
+    /// Parses a message of this device into [`SensorData`].
+    fn parse(s: &str) -> Result<SensorData, Box<dyn Error>> {
+        Ok(Self::from_str(s)?.data())
+    }
+
+    /// Formats the message the device sends.
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// A [`Device`] of any kind, as the house and the state keep them.
+pub trait AnyDevice: Debug + Send + Sync {
+    fn kind(&self) -> &'static str;
+
+    fn id(&self) -> &str;
+
+    fn data(&self) -> SensorData;
+
+    /// See [`Device::update`].
+    fn update(&mut self, data: &SensorData) -> f32;
+}
+
+impl<T: Device> AnyDevice for T {
+    fn kind(&self) -> &'static str {
+        T::KIND
+    }
+
+    fn id(&self) -> &str {
+        Device::id(self)
+    }
+
+    fn data(&self) -> SensorData {
+        Device::data(self)
+    }
+
+    fn update(&mut self, data: &SensorData) -> f32 {
+        Device::update(self, data)
+    }
+}
+
+/// What the server knows about a device kind without knowing its type.
+#[derive(Debug, Clone, Copy)]
+pub struct Kind {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub graduation: f32,
+    range: fn() -> RangeInclusive<f32>,
+    parse: fn(&str) -> Result<SensorData, Box<dyn Error>>,
+    format: fn(f32) -> String,
+    new: fn(&str) -> Box<dyn AnyDevice>,
+}
+
+impl Kind {
+    fn of<T: Device>() -> Self {
+        fn format<T: Device>(value: f32) -> String {
+            let mut device = T::default();
+            device.set_reading(value);
+            device.format()
+        }
+
+        fn new<T: Device>(id: &str) -> Box<dyn AnyDevice> {
+            let mut device = T::default();
+            device.set_id(id);
+            Box::new(device)
+        }
+
+        Self {
+            name: T::KIND,
+            unit: T::UNIT,
+            graduation: T::GRADUATION,
+            range: T::range,
+            parse: T::parse,
+            format: format::<T>,
+            new: new::<T>,
+        }
+    }
+
+    /// See [`Device::range`].
+    pub fn range(&self) -> RangeInclusive<f32> {
+        (self.range)()
+    }
+
+    pub fn parse(&self, s: &str) -> Result<SensorData, Box<dyn Error>> {
+        (self.parse)(s)
+    }
+
+    /// Message of a device of this kind reading `value`.
+    pub fn format(&self, value: f32) -> String {
+        (self.format)(value)
+    }
+
+    /// A device of this kind with default readings.
+    pub fn device(&self, id: &str) -> Box<dyn AnyDevice> {
+        (self.new)(id)
+    }
+}
+
+/// Known device kinds; dispatches incoming messages to the matching [`Device`].
+#[derive(Debug, Clone)]
+pub struct Kinds {
+    kinds: Vec<Kind>,
+}
+
+impl Default for Kinds {
+    /// Every device kind of this crate.
+    fn default() -> Self {
+        Self::empty()
+            .register::<Termometer>()
+            .register::<Socket>()
+            .register::<Hygrometer>()
+    }
+}
+
+impl Kinds {
+    pub fn empty() -> Self {
+        Self { kinds: vec![] }
+    }
+
+    /// Adds the kind, unless one of the same name is known already.
+    pub fn register<T: Device>(mut self) -> Self {
+        self.add::<T>();
+        self
+    }
+
+    fn add<T: Device>(&mut self) {
+        if self.get(T::KIND).is_none() {
+            self.kinds.push(Kind::of::<T>());
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.kinds.iter().map(|k| k.name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Kind> {
+        self.kinds.iter().find(|k| k.name == name)
+    }
+
+    /// Parses a message with the device kind it starts with.
+    pub fn parse(&self, s: &str) -> Result<SensorData, Box<dyn Error>> {
+        let word = s.split_whitespace().next().unwrap_or_default();
+
+        match self.get(word) {
+            Some(kind) => kind.parse(s),
+            None => Err(UnknownKind(word.to_string()).into()),
+        }
+    }
+}
+
+/// Kinds the server takes: those of [`Kinds::default`] and the [`register`]ed ones.
+static KINDS: LazyLock<RwLock<Kinds>> = LazyLock::new(|| RwLock::new(Kinds::default()));
+
+/// Makes the server take messages of another device kind, place such devices in
+/// the house and show them.
+pub fn register<T: Device>() {
+    KINDS.write().unwrap().add::<T>();
+}
+
+/// Registered kind of this name.
+pub fn kind(name: &str) -> Option<Kind> {
+    KINDS.read().unwrap().get(name).copied()
+}
+
+/// Names of the registered kinds, in the order of registration.
+pub fn kinds() -> Vec<&'static str> {
+    KINDS.read().unwrap().names().collect()
+}
+
+/// Parses a message of any registered kind, see [`Kinds::parse`].
+pub fn parse(s: &str) -> Result<SensorData, Box<dyn Error>> {
+    KINDS.read().unwrap().parse(s)
+}
+
+/// Message starting with a word no registered kind goes by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownKind(pub String);
+
+impl Display for UnknownKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown message")
+    }
+}
+
+impl Error for UnknownKind {}
+
+/// Reads the value from a `<kind> <value> ...` message, shared by the devices.
+pub fn parse_value(kind: &str, s: &str) -> Result<f32, Box<dyn Error>> {
+    let unlike = || format!("does not look like message from {}", kind.to_lowercase()).into();
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    let rest = s.strip_prefix(kind).ok_or_else(unlike)?;
+    let value = rest.trim_start();
+    if value.len() == rest.len() || digits(value) == 0 {
+        return Err(unlike());
+    }
+
+    let mut end = digits(value);
+    if let Some(fraction) = value[end..].strip_prefix('.')
+        && digits(fraction) > 0
+    {
+        end += 1 + digits(fraction);
+    }
+
+    value[..end]
+        .parse::<f32>()
+        .map_err(|_| "cannot parse float from string".into())
+}
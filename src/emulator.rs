@@ -0,0 +1,138 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+use crate::{
+    device::Device,
+    message::SocketMessage,
+    power::Power,
+    socket::{Socket, SocketCommand, SocketReading},
+};
+
+/// Smart socket that listens for commands itself, one per line:
+/// `on`, `off`, `power <watts>` and `report power`, or just `report` as the
+/// server polls by default.
+///
+/// Every command is answered with the state of the socket as a
+/// [`SocketMessage`], unknown ones and out of range power with a `Nack`.
+#[derive(Debug)]
+pub struct SocketEmulator {
+    socket: Socket,
+    /// Power restored when the socket is switched back on.
+    rated: f32,
+}
+
+impl SocketEmulator {
+    /// Switched on socket drawing `power` watts, which must be within the range
+    /// of a socket.
+    pub fn new(power: f32) -> Result<Self, Box<dyn Error>> {
+        rated(power)?;
+
+        Ok(Self {
+            socket: Socket::on(Power::new(power)),
+            rated: power,
+        })
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+
+    /// Answers a single command line.
+    pub fn answer(&mut self, command: &str) -> SocketMessage {
+        let command = command.trim();
+        if !matches!(command, "report" | "report power") {
+            let executed = command
+                .parse::<SocketCommand>()
+                .and_then(|command| self.execute(command));
+            if let Err(e) = executed {
+                return SocketMessage::Nack(e.to_string());
+            }
+        }
+
+        SocketMessage::Reading(SocketReading {
+            on: self.socket.is_on(),
+            power: self.socket.reading(),
+            telemetry: *self.socket.telemetry(),
+        })
+    }
+
+    /// A switched off socket only remembers the power to draw once switched on.
+    fn execute(&mut self, command: SocketCommand) -> Result<(), Box<dyn Error>> {
+        match command {
+            SocketCommand::On => {
+                self.socket.switch(true);
+                self.socket.set_reading(self.rated);
+            }
+            SocketCommand::Off => self.socket.switch(false),
+            SocketCommand::Power(p) => {
+                self.rated = rated(p)?;
+                if self.socket.is_on() {
+                    self.socket.set_reading(p);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Power a socket may be set to draw.
+fn rated(power: f32) -> Result<f32, Box<dyn Error>> {
+    if !Power::is_valid(power) {
+        return Err(format!(
+            "power {} W is out of {}..={} W",
+            power,
+            Power::MIN_POWER,
+            Power::MAX_POWER
+        )
+        .into());
+    }
+
+    Ok(power)
+}
+
+/// Serves controllers forever, every connection in its own task.
+pub async fn serve(
+    listener: TcpListener,
+    emulator: Arc<Mutex<SocketEmulator>>,
+) -> std::io::Result<()> {
+    loop {
+        let (tcp, _) = listener.accept().await?;
+
+        let emulator = Arc::clone(&emulator);
+        tokio::spawn(async move {
+            if let Err(e) = handle_controller(tcp, emulator).await {
+                tracing::warn!(error = ?e, "controller connection failed");
+            }
+        });
+    }
+}
+
+/// Answers the commands of one controller until it disconnects.
+pub async fn handle_controller<S>(
+    stream: S,
+    emulator: Arc<Mutex<SocketEmulator>>,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(stream).lines();
+
+    while let Some(command) = lines.next_line().await? {
+        if command.trim().is_empty() {
+            continue;
+        }
+
+        let answer = format!("{}\n", emulator.lock().unwrap().answer(&command));
+        lines.get_mut().write_all(answer.as_bytes()).await?;
+    }
+
+    Ok(())
+}
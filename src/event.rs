@@ -0,0 +1,22 @@
+use chrono::{DateTime, Utc};
+
+use crate::{presence::Presence, reading::Reading};
+
+/// What happened on the server, reported to the UI.
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+    /// Single reading, carrying the value that was stored.
+    Reading(Reading),
+    /// Batch of readings ingested together, oldest first.
+    Batch(Vec<Reading>),
+    /// Message that was answered with a nack.
+    Rejected { message: String, reason: String },
+    /// Command a client asked the server to carry out, e.g. `Report json`.
+    Command(String),
+    /// Device went online, stale or offline.
+    Presence {
+        device: String,
+        presence: Presence,
+        last_seen: Option<DateTime<Utc>>,
+    },
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::Deserialize;
+
+/// Misbehaviour of a simulated device.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum FaultKind {
+    /// Keeps reporting the last value sent before the fault.
+    Stuck,
+    /// Adds or subtracts `magnitude` to the value.
+    Spike { magnitude: f32 },
+    /// Reports a value above the maximum of the device, e.g. `Temperature::MAX_TEMPERATURE`.
+    OutOfRange,
+    /// Sends a frame the server cannot parse.
+    Malformed,
+    /// Writes half of the frame and drops the connection.
+    Disconnect,
+    /// Writes the frame `chunk` bytes at a time, waiting `delay_ms` after each chunk.
+    SlowWrite { chunk: usize, delay_ms: u64 },
+    /// The device clock runs `rate` seconds ahead per second of the simulation,
+    /// negative `rate` makes it fall behind.
+    ClockDrift { rate: f64 },
+}
+
+/// A fault and when it strikes:
+///
+/// ```toml
+/// faults = [
+///     { fault = "stuck", from_s = 60, until_s = 120 },
+///     { fault = "spike", magnitude = 15.0, probability = 0.05 },
+///     { fault = "slow_write", chunk = 4, delay_ms = 200, from_s = 300 },
+/// ]
+/// ```
+///
+/// Without a window the fault is scheduled for the whole run, without a
+/// probability it strikes every message inside of the window.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Fault {
+    #[serde(flatten)]
+    pub kind: FaultKind,
+    #[serde(default)]
+    pub from_s: f64,
+    pub until_s: Option<f64>,
+    #[serde(default = "always")]
+    pub probability: f64,
+}
+
+fn always() -> f64 {
+    1.0
+}
+
+impl Fault {
+    /// A fault striking every message of the run.
+    pub fn new(kind: FaultKind) -> Self {
+        Self {
+            kind,
+            from_s: 0.0,
+            until_s: None,
+            probability: always(),
+        }
+    }
+
+    /// Whether `t` since the start of the simulation falls into the window.
+    pub fn is_scheduled(&self, t: Duration) -> bool {
+        let t = t.as_secs_f64();
+
+        t >= self.from_s && self.until_s.is_none_or(|until| t < until)
+    }
+
+    /// Whether the fault strikes the message sent at `t`.
+    ///
+    /// Draws from `rng` only for scheduled faults with a probability below one,
+    /// so a run without such faults replays the same signal.
+    pub fn strikes(&self, t: Duration, rng: &mut impl Rng) -> bool {
+        if !self.is_scheduled(t) {
+            return false;
+        }
+
+        match self.probability {
+            p if p >= 1.0 => true,
+            p if p <= 0.0 => false,
+            p => rng.random_bool(p),
+        }
+    }
+}
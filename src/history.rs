@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+
+use crate::reading::Reading;
+
+/// In-memory list of the latest readings, oldest first.
+#[derive(Debug)]
+pub struct History {
+    readings: VecDeque<Reading>,
+    capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl History {
+    pub const DEFAULT_CAPACITY: usize = 10_000;
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            readings: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, reading: Reading) {
+        if self.readings.len() == self.capacity {
+            self.readings.pop_front();
+        }
+
+        self.readings.push_back(reading);
+    }
+
+    pub fn len(&self) -> usize {
+        self.readings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Reading> {
+        self.readings.iter()
+    }
+
+    /// Readings taken within `from..=to`, see [`Reading::time`].
+    pub fn range(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> impl Iterator<Item = &Reading> {
+        self.readings
+            .iter()
+            .filter(move |r| (from..=to).contains(&r.time()))
+    }
+}
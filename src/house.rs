@@ -0,0 +1,280 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    device::{self, AnyDevice},
+    sensor_data::SensorData,
+};
+
+/// House layout, read from the `[house]` table of the server config:
+///
+/// ```toml
+/// [house]
+/// name = "Дача"
+///
+/// [[house.rooms]]
+/// name = "kitchen"
+/// devices = [
+///     { id = "termometer", kind = "Termometer" },
+///     { id = "kettle", kind = "Socket" },
+/// ]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HouseConfig {
+    pub name: String,
+    pub rooms: Vec<RoomLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoomLayout {
+    pub name: String,
+    #[serde(default)]
+    pub devices: Vec<DeviceLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DeviceLayout {
+    pub id: String,
+    /// [`device::Device::KIND`] of the device.
+    pub kind: String,
+}
+
+/// What can go wrong when building or addressing a [`House`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum HouseError {
+    DuplicateRoom(String),
+    DuplicateDevice(DevicePath),
+    UnknownRoom(String),
+    UnknownDevice(DevicePath),
+    UnknownKind(String),
+    /// Names may not be empty, contain spaces or `/`.
+    BadName(String),
+    BadPath(String),
+}
+
+impl Display for HouseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HouseError::DuplicateRoom(room) => write!(f, "room {} already exists", room),
+            HouseError::DuplicateDevice(path) => write!(f, "device {} already exists", path),
+            HouseError::UnknownRoom(room) => write!(f, "unknown room {}", room),
+            HouseError::UnknownDevice(path) => write!(f, "unknown device {}", path),
+            HouseError::UnknownKind(kind) => write!(f, "unknown device kind {}", kind),
+            HouseError::BadName(name) => write!(f, "bad name {:?}", name),
+            HouseError::BadPath(path) => {
+                write!(f, "bad device path {:?}, expected <room>/<device>", path)
+            }
+        }
+    }
+}
+
+impl Error for HouseError {}
+
+fn check_name(name: &str) -> Result<(), HouseError> {
+    match name.is_empty() || name.contains('/') || name.contains(char::is_whitespace) {
+        true => Err(HouseError::BadName(name.to_string())),
+        false => Ok(()),
+    }
+}
+
+/// Address of a device in the house: `<room>/<device>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DevicePath {
+    pub room: String,
+    pub device: String,
+}
+
+impl DevicePath {
+    pub fn new(room: impl Into<String>, device: impl Into<String>) -> Self {
+        Self {
+            room: room.into(),
+            device: device.into(),
+        }
+    }
+}
+
+impl Display for DevicePath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.room, self.device)
+    }
+}
+
+impl FromStr for DevicePath {
+    type Err = HouseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || HouseError::BadPath(s.to_string());
+        let (room, device) = s.split_once('/').ok_or_else(bad)?;
+        check_name(room).map_err(|_| bad())?;
+        check_name(device).map_err(|_| bad())?;
+
+        Ok(Self::new(room, device))
+    }
+}
+
+/// A device living in a room, of any [`device::register`]ed kind.
+#[derive(Debug)]
+pub struct SmartDevice(Box<dyn AnyDevice>);
+
+impl SmartDevice {
+    /// A device of the given [`device::Device::KIND`] with default readings.
+    pub fn new(kind: &str, id: &str) -> Result<Self, HouseError> {
+        match device::kind(kind) {
+            Some(kind) => Ok(Self(kind.device(id))),
+            None => Err(HouseError::UnknownKind(kind.to_string())),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        self.0.id()
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.0.kind()
+    }
+
+    pub fn data(&self) -> SensorData {
+        self.0.data()
+    }
+
+    /// Whether the device reports this kind of data.
+    pub fn accepts(&self, data: &SensorData) -> bool {
+        data.kind() == self.kind()
+    }
+
+    /// See [`device::Device::update`].
+    pub fn update(&mut self, data: &SensorData) -> f32 {
+        self.0.update(data)
+    }
+}
+
+/// A named room with its devices, in the order they were added.
+#[derive(Debug)]
+pub struct HouseRoom {
+    name: String,
+    devices: Vec<SmartDevice>,
+}
+
+impl HouseRoom {
+    pub fn new(name: impl Into<String>) -> Result<Self, HouseError> {
+        let name = name.into();
+        check_name(&name)?;
+
+        Ok(Self {
+            name,
+            devices: vec![],
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &SmartDevice> {
+        self.devices.iter()
+    }
+
+    /// Adds a device; ids are unique within the room.
+    pub fn add_device(&mut self, device: SmartDevice) -> Result<(), HouseError> {
+        check_name(device.id())?;
+        if self.devices.iter().any(|d| d.id() == device.id()) {
+            return Err(HouseError::DuplicateDevice(DevicePath::new(
+                &self.name,
+                device.id(),
+            )));
+        }
+
+        self.devices.push(device);
+        Ok(())
+    }
+
+    pub fn device(&self, id: &str) -> Result<&SmartDevice, HouseError> {
+        self.devices
+            .iter()
+            .find(|d| d.id() == id)
+            .ok_or_else(|| HouseError::UnknownDevice(DevicePath::new(&self.name, id)))
+    }
+
+    pub fn device_mut(&mut self, id: &str) -> Result<&mut SmartDevice, HouseError> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.id() == id)
+            .ok_or_else(|| HouseError::UnknownDevice(DevicePath::new(&self.name, id)))
+    }
+}
+
+/// A house of named rooms, in the order they were added.
+#[derive(Debug, Default)]
+pub struct House {
+    name: String,
+    rooms: Vec<HouseRoom>,
+}
+
+impl House {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            rooms: vec![],
+        }
+    }
+
+    /// Builds the house from its layout, refusing duplicates and unknown kinds.
+    pub fn from_config(config: &HouseConfig) -> Result<Self, HouseError> {
+        let mut house = Self::new(&config.name);
+        for layout in &config.rooms {
+            let mut room = HouseRoom::new(&layout.name)?;
+            for device in &layout.devices {
+                room.add_device(SmartDevice::new(&device.kind, &device.id)?)?;
+            }
+            house.add_room(room)?;
+        }
+
+        Ok(house)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = &HouseRoom> {
+        self.rooms.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rooms.is_empty()
+    }
+
+    /// Adds a room; names are unique within the house.
+    pub fn add_room(&mut self, room: HouseRoom) -> Result<(), HouseError> {
+        if self.rooms.iter().any(|r| r.name == room.name) {
+            return Err(HouseError::DuplicateRoom(room.name));
+        }
+
+        self.rooms.push(room);
+        Ok(())
+    }
+
+    pub fn room(&self, name: &str) -> Result<&HouseRoom, HouseError> {
+        self.rooms
+            .iter()
+            .find(|r| r.name == name)
+            .ok_or_else(|| HouseError::UnknownRoom(name.to_string()))
+    }
+
+    pub fn room_mut(&mut self, name: &str) -> Result<&mut HouseRoom, HouseError> {
+        self.rooms
+            .iter_mut()
+            .find(|r| r.name == name)
+            .ok_or_else(|| HouseError::UnknownRoom(name.to_string()))
+    }
+
+    pub fn device(&self, path: &DevicePath) -> Result<&SmartDevice, HouseError> {
+        self.room(&path.room)?.device(&path.device)
+    }
+
+    pub fn device_mut(&mut self, path: &DevicePath) -> Result<&mut SmartDevice, HouseError> {
+        self.room_mut(&path.room)?.device_mut(&path.device)
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt::Display;
+
+/// Relative humidity, percent.
+#[derive(Debug, Default)]
+pub struct Humidity(f32);
+
+impl Humidity {
+    pub const MIN_HUMIDITY: f32 = 0.0;
+    pub const MAX_HUMIDITY: f32 = 100.0;
+    pub const GRADUATION: f32 = 1.0;
+
+    pub fn new(humidity: f32) -> Self {
+        Self(humidity)
+    }
+
+    pub fn get(&self) -> f32 {
+        self.0
+    }
+
+    pub fn set(&mut self, value: f32) {
+        if Self::is_valid(value) {
+            self.0 = value
+        }
+    }
+
+    pub fn is_valid(value: f32) -> bool {
+        (Self::MIN_HUMIDITY..=Self::MAX_HUMIDITY).contains(&value)
+    }
+
+    pub fn ratio(humidity: f32) -> f32 {
+        (humidity - Self::MIN_HUMIDITY) / (Self::MAX_HUMIDITY - Self::MIN_HUMIDITY)
+    }
+}
+
+impl Display for Humidity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}", self.get())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{error::Error, fmt::Display, ops::RangeInclusive, str::FromStr};
+
+use crate::{
+    device::{self, Device},
+    humidity::Humidity,
+    sensor_data::SensorData,
+};
+
+#[derive(Debug)]
+pub struct Hygrometer {
+    id: String,
+    humidity: Humidity,
+}
+
+impl Default for Hygrometer {
+    fn default() -> Self {
+        Self::new(Humidity::default())
+    }
+}
+
+impl Hygrometer {
+    pub fn new(humidity: Humidity) -> Self {
+        Self {
+            id: Self::KIND.to_lowercase(),
+            humidity,
+        }
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    pub fn humidity(&self) -> &Humidity {
+        &self.humidity
+    }
+
+    pub fn humidity_mut(&mut self) -> &mut Humidity {
+        &mut self.humidity
+    }
+}
+
+impl Device for Hygrometer {
+    const KIND: &'static str = "Hygrometer";
+    const UNIT: &'static str = "%";
+    const GRADUATION: f32 = Humidity::GRADUATION;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: &str) {
+        self.id = id.to_string();
+    }
+
+    fn reading(&self) -> f32 {
+        self.humidity.get()
+    }
+
+    fn set_reading(&mut self, value: f32) {
+        self.humidity.set(value)
+    }
+
+    fn range() -> RangeInclusive<f32> {
+        Humidity::MIN_HUMIDITY..=Humidity::MAX_HUMIDITY
+    }
+
+    fn data(&self) -> SensorData {
+        SensorData::Humidity(self.reading())
+    }
+}
+
+impl Display for Hygrometer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Hygrometer {} %", self.humidity())
+    }
+}
+
+impl FromStr for Hygrometer {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let h = device::parse_value(Self::KIND, s)?;
+
+        Ok(Self::new(Humidity::new(h)))
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{reading::Reading, sensor_data::SensorData};
+
+/// Append-only file with accepted readings, one per line:
+/// `<received>;<device time or empty>;<device id or empty>;<raw value or empty>;<message>`.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self { file })
+    }
+
+    /// Appends readings with a single write so they land in the file together.
+    pub fn append(&mut self, readings: &[Reading]) -> std::io::Result<()> {
+        let mut buf = String::new();
+        for r in readings {
+            let device_time = r.device_time.map(|t| t.to_rfc3339()).unwrap_or_default();
+            let raw = r.raw.map(|v| v.to_string()).unwrap_or_default();
+            buf.push_str(&format!(
+                "{};{};{};{};{}\n",
+                r.received.to_rfc3339(),
+                device_time,
+                r.device.as_deref().unwrap_or_default(),
+                raw,
+                r.data
+            ));
+        }
+
+        self.file.write_all(buf.as_bytes())?;
+        self.file.flush()
+    }
+
+    /// Reads back every reading stored in the file, skipping damaged lines.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Vec<Reading>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut readings = vec![];
+        for line in BufReader::new(file).lines() {
+            if let Some(reading) = Self::parse_line(&line?) {
+                readings.push(reading);
+            }
+        }
+
+        Ok(readings)
+    }
+
+    fn parse_line(line: &str) -> Option<Reading> {
+        let mut parts = line.splitn(5, ';');
+        let received = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+        let device_time = match parts.next()? {
+            "" => None,
+            t => Some(DateTime::parse_from_rfc3339(t).ok()?.with_timezone(&Utc)),
+        };
+        let device = match parts.next()? {
+            "" => None,
+            id => Some(id.to_string()),
+        };
+        let raw = match parts.next()? {
+            "" => None,
+            v => Some(v.parse().ok()?),
+        };
+        let data = SensorData::from_str(parts.next()?).ok()?;
+
+        Some(Reading {
+            device,
+            data,
+            raw,
+            device_time,
+            received: received.with_timezone(&Utc),
+        })
+    }
+}
@@ -1,5 +1,39 @@
+pub mod api;
+pub mod auth;
+pub mod calibration;
+pub mod client;
+pub mod config;
+pub mod device;
+pub mod emulator;
+pub mod event;
+pub mod fault;
+pub mod history;
+pub mod house;
+pub mod humidity;
+pub mod hygrometer;
+pub mod journal;
+pub mod limits;
+pub mod load;
+pub mod logging;
 pub mod message;
+pub mod metrics;
+pub mod outbox;
+pub mod poll;
 pub mod power;
+pub mod presence;
+pub mod protocol;
+pub mod reading;
+pub mod registry;
+pub mod reply;
+pub mod report;
+pub mod room;
+pub mod sensor_data;
+pub mod server;
+pub mod signal;
+pub mod simulator;
 pub mod socket;
+pub mod state;
+pub mod stream;
 pub mod temperature;
 pub mod termometer;
+pub mod tls;
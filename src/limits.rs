@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::reply::Reply;
+
+/// Protection against idle, slow and greedy clients, the `[limits]` table of
+/// the server config:
+///
+/// ```toml
+/// [limits]
+/// max_connections = 1024
+/// max_per_ip = 64
+/// idle_timeout_ms = 300000
+/// read_timeout_ms = 10000
+/// max_frame = 16384
+/// device_rate = { rate = 5.0, burst = 20 }
+/// connection_rate = { rate = 50.0, burst = 100 }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Connections open at once over TCP and TLS.
+    pub max_connections: usize,
+    /// Connections open at once from a single address. A load test run from
+    /// one host needs at least its [`crate::load::LoadConfig::tcp_connections`]
+    /// here, otherwise it measures the refusals.
+    pub max_per_ip: usize,
+    /// Time a connection may stay silent between frames.
+    pub idle_timeout_ms: u64,
+    /// Time to complete a frame once its first byte arrived.
+    pub read_timeout_ms: u64,
+    /// Longest frame in bytes, without the newline. The default takes a full
+    /// batch of a [`crate::client::Client`] flushing its outbox.
+    pub max_frame: usize,
+    /// Messages a device may send, a device may override it in the registry.
+    pub device_rate: Option<RateLimit>,
+    /// Messages a single TCP or TLS connection may send.
+    pub connection_rate: Option<RateLimit>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_per_ip: 64,
+            idle_timeout_ms: 300_000,
+            read_timeout_ms: 10_000,
+            max_frame: 16384,
+            device_rate: None,
+            connection_rate: None,
+        }
+    }
+}
+
+impl LimitsConfig {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout_ms)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+}
+
+/// Why a connection was refused or cut off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    TooManyConnections(usize),
+    TooManyFromIp(usize),
+    Idle(Duration),
+    SlowFrame(Duration),
+    FrameTooLarge(usize),
+}
+
+impl Refusal {
+    /// Short name of the reason, the same whatever the limit is.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Refusal::TooManyConnections(_) => "too_many_connections",
+            Refusal::TooManyFromIp(_) => "too_many_from_ip",
+            Refusal::Idle(_) => "idle",
+            Refusal::SlowFrame(_) => "slow_frame",
+            Refusal::FrameTooLarge(_) => "frame_too_large",
+        }
+    }
+
+    /// Reply telling the client why: a frame over the size limit is never
+    /// taken, anything else may get through on another try.
+    pub fn reply(&self) -> Reply {
+        match self {
+            Refusal::FrameTooLarge(_) => Reply::Nack(self.to_string()),
+            _ => Reply::Busy(self.to_string()),
+        }
+    }
+}
+
+impl Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::TooManyConnections(max) => write!(f, "server is full ({} connections)", max),
+            Refusal::TooManyFromIp(max) => {
+                write!(f, "too many connections from your address ({})", max)
+            }
+            Refusal::Idle(timeout) => write!(f, "idle for {} ms", timeout.as_millis()),
+            Refusal::SlowFrame(timeout) => {
+                write!(f, "frame not completed within {} ms", timeout.as_millis())
+            }
+            Refusal::FrameTooLarge(max) => write!(f, "frame longer than {} bytes", max),
+        }
+    }
+}
+
+/// Connections open at the moment, in total and by address.
+#[derive(Debug, Default)]
+pub struct Connections {
+    total: usize,
+    by_ip: HashMap<IpAddr, usize>,
+}
+
+impl Connections {
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn from_ip(&self, ip: IpAddr) -> usize {
+        self.by_ip.get(&ip).copied().unwrap_or(0)
+    }
+
+    /// Counts a new connection from `ip` unless it would exceed the limits.
+    pub fn open(&mut self, ip: IpAddr, limits: &LimitsConfig) -> Result<(), Refusal> {
+        if self.total >= limits.max_connections {
+            return Err(Refusal::TooManyConnections(limits.max_connections));
+        }
+        if self.from_ip(ip) >= limits.max_per_ip {
+            return Err(Refusal::TooManyFromIp(limits.max_per_ip));
+        }
+
+        self.total += 1;
+        *self.by_ip.entry(ip).or_default() += 1;
+        Ok(())
+    }
+
+    /// Forgets a connection counted by [`Connections::open`].
+    pub fn close(&mut self, ip: IpAddr) {
+        if let Some(count) = self.by_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                self.by_ip.remove(&ip);
+            }
+            self.total -= 1;
+        }
+    }
+}
+
+/// Sustained messages per second with room for a burst.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Token bucket: holds up to `burst` tokens, refilled at `rate` per second,
+/// every message takes one.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    /// Full bucket.
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last: now,
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Takes a token, `false` when the bucket is empty.
+    pub fn take(&mut self, now: Instant) -> bool {
+        self.take_many(now, 1)
+    }
+
+    /// Takes `count` tokens at once, none when fewer are left.
+    pub fn take_many(&mut self, now: Instant, count: usize) -> bool {
+        if !self.holds(now, count) {
+            return false;
+        }
+        self.tokens -= count as f64;
+        true
+    }
+
+    /// Whether `count` tokens are left, without taking them.
+    pub fn holds(&mut self, now: Instant, count: usize) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst as f64);
+        self.last = now;
+
+        self.tokens >= count as f64
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+use crate::{
+    client::Client, reading::Stamped, reply::Reply, temperature::Temperature,
+    termometer::Termometer,
+};
+
+/// Load test settings, read from a TOML file:
+///
+/// ```toml
+/// server = "localhost:8080"
+/// udp = "localhost:8081"
+/// tcp_connections = 2000 # needs max_per_ip = 2000 in [limits] of the server
+/// udp_connections = 500
+/// rate = 2.0          # messages per second per connection
+/// duration_s = 30.0
+/// timeout_ms = 1000
+/// summary = "load.json"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoadConfig {
+    /// TCP address of the server.
+    pub server: String,
+    /// UDP address of the server, needed for `udp_connections`.
+    pub udp: Option<String>,
+    /// TCP connections open at once, all from this host: the server refuses
+    /// those over its [`crate::limits::LimitsConfig::max_per_ip`], so the
+    /// default stays within the default limit.
+    pub tcp_connections: usize,
+    pub udp_connections: usize,
+    /// Messages per second each connection sends.
+    pub rate: f64,
+    pub duration_s: f64,
+    /// A message not answered within the timeout counts as dropped.
+    pub timeout_ms: u64,
+    pub seed: u64,
+    /// File to write the JSON [`Summary`] to, stdout if not set.
+    pub summary: Option<PathBuf>,
+}
+
+impl Default for LoadConfig {
+    fn default() -> Self {
+        Self {
+            server: "localhost:8080".into(),
+            udp: None,
+            tcp_connections: 64,
+            udp_connections: 0,
+            rate: 1.0,
+            duration_s: 10.0,
+            timeout_ms: 1000,
+            seed: 0,
+            summary: None,
+        }
+    }
+}
+
+impl LoadConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config {}", path.display()))?;
+
+        toml::from_str(&text).with_context(|| format!("bad config {}", path.display()))
+    }
+
+    fn period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate.max(1e-3))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// Result of a load test, stable enough to compare runs between versions.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub version: String,
+    pub started: DateTime<Utc>,
+    pub duration_s: f64,
+    pub rate: f64,
+    pub tcp: Stats,
+    pub udp: Stats,
+}
+
+/// Outcome of the messages sent over one transport.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub connections: usize,
+    pub sent: u64,
+    pub acked: u64,
+    pub nacked: u64,
+    /// Connection, write and read failures.
+    pub errors: u64,
+    /// Messages not answered within the timeout.
+    pub dropped: u64,
+    /// Answered messages per second.
+    pub throughput: f64,
+    /// From sending a message to receiving its reply.
+    pub latency_ms: Percentiles,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    /// Nearest-rank percentiles of the latencies.
+    pub fn new(latencies: &mut [Duration]) -> Self {
+        latencies.sort();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let rank = |p: f64| match latencies.len() {
+            0 => 0.0,
+            n => ms(latencies[((p * n as f64).ceil() as usize).clamp(1, n) - 1]),
+        };
+
+        Self {
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            max: rank(1.0),
+        }
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} connections, sent {}, acked {}, nacked {}, errors {}, dropped {}, {:.1} msg/s, \
+             latency p50 {:.2} ms p90 {:.2} ms p99 {:.2} ms max {:.2} ms",
+            self.connections,
+            self.sent,
+            self.acked,
+            self.nacked,
+            self.errors,
+            self.dropped,
+            self.throughput,
+            self.latency_ms.p50,
+            self.latency_ms.p90,
+            self.latency_ms.p99,
+            self.latency_ms.max
+        )
+    }
+}
+
+/// What a single connection counted.
+#[derive(Debug, Default)]
+struct Tally {
+    sent: u64,
+    acked: u64,
+    nacked: u64,
+    errors: u64,
+    dropped: u64,
+    latencies: Vec<Duration>,
+}
+
+impl Tally {
+    fn reply(&mut self, reply: &Reply, latency: Duration) {
+        match reply.is_ack() {
+            true => self.acked += 1,
+            false => self.nacked += 1,
+        }
+        self.latencies.push(latency);
+    }
+
+    fn merge(tallies: Vec<Tally>, elapsed: Duration) -> Stats {
+        let mut stats = Stats {
+            connections: tallies.len(),
+            ..Stats::default()
+        };
+        let mut latencies = vec![];
+
+        for tally in tallies {
+            stats.sent += tally.sent;
+            stats.acked += tally.acked;
+            stats.nacked += tally.nacked;
+            stats.errors += tally.errors;
+            stats.dropped += tally.dropped;
+            latencies.extend(tally.latencies);
+        }
+
+        stats.throughput = latencies.len() as f64 / elapsed.as_secs_f64().max(1e-9);
+        stats.latency_ms = Percentiles::new(&mut latencies);
+        stats
+    }
+}
+
+/// Opens all connections, sends for [`LoadConfig::duration_s`] and sums up.
+pub async fn run(config: &LoadConfig) -> anyhow::Result<Summary> {
+    let udp = match (config.udp_connections, &config.udp) {
+        (0, _) => None,
+        (_, Some(udp)) => Some(
+            tokio::net::lookup_host(udp)
+                .await?
+                .next()
+                .with_context(|| format!("cannot resolve {}", udp))?,
+        ),
+        (_, None) => anyhow::bail!("udp_connections need the udp address of the server"),
+    };
+
+    let started = Utc::now();
+    let start = Instant::now();
+    let end = start + Duration::from_secs_f64(config.duration_s.max(0.0));
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let tcp: Vec<_> = (0..config.tcp_connections)
+        .map(|_| {
+            let client = Client::new(&config.server);
+            let rng = StdRng::seed_from_u64(rng.random());
+            tokio::spawn(tcp_connection(client, config.clone(), end, rng))
+        })
+        .collect();
+    let udp: Vec<_> = (0..config.udp_connections)
+        .filter_map(|_| udp)
+        .map(|server| {
+            let rng = StdRng::seed_from_u64(rng.random());
+            tokio::spawn(udp_connection(server, config.clone(), end, rng))
+        })
+        .collect();
+
+    let mut tcp_tallies = vec![];
+    for task in tcp {
+        tcp_tallies.push(task.await?);
+    }
+    let mut udp_tallies = vec![];
+    for task in udp {
+        udp_tallies.push(task.await?);
+    }
+    let elapsed = start.elapsed();
+
+    Ok(Summary {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        started,
+        duration_s: elapsed.as_secs_f64(),
+        rate: config.rate,
+        tcp: Tally::merge(tcp_tallies, elapsed),
+        udp: Tally::merge(udp_tallies, elapsed),
+    })
+}
+
+/// Random termometer reading, stamped with the current time.
+fn message(rng: &mut StdRng) -> Stamped<Termometer> {
+    let value = rng.random_range(Temperature::MIN_TEMPERATURE..=Temperature::MAX_TEMPERATURE);
+
+    Stamped::now(Termometer::new(Temperature::new(value)))
+}
+
+/// Sends at the configured rate, starting at a random moment of the first
+/// period so that the connections do not fire all at once.
+async fn pace(config: &LoadConfig, rng: &mut StdRng) -> tokio::time::Interval {
+    let period = config.period();
+    tokio::time::sleep(period.mul_f64(rng.random())).await;
+
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+}
+
+async fn tcp_connection(
+    mut client: Client,
+    config: LoadConfig,
+    end: Instant,
+    mut rng: StdRng,
+) -> Tally {
+    let mut tally = Tally::default();
+    let mut interval = pace(&config, &mut rng).await;
+
+    while Instant::now() < end {
+        interval.tick().await;
+
+        let sent = Instant::now();
+        tally.sent += 1;
+        match tokio::time::timeout(config.timeout(), client.send(&message(&mut rng))).await {
+            Ok(Ok(reply)) => tally.reply(&reply, sent.elapsed()),
+            Ok(Err(_)) => tally.errors += 1,
+            Err(_) => {
+                tally.dropped += 1;
+                client.disconnect();
+            }
+        }
+    }
+
+    tally
+}
+
+async fn udp_connection(
+    server: std::net::SocketAddr,
+    config: LoadConfig,
+    end: Instant,
+    mut rng: StdRng,
+) -> Tally {
+    let mut tally = Tally::default();
+    let bind = match server {
+        std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+        std::net::SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = match UdpSocket::bind(bind).await {
+        Ok(socket) if socket.connect(server).await.is_ok() => socket,
+        _ => {
+            tally.errors += 1;
+            return tally;
+        }
+    };
+
+    let mut interval = pace(&config, &mut rng).await;
+    let mut buf = vec![0; 1024];
+    while Instant::now() < end {
+        interval.tick().await;
+
+        let sent = Instant::now();
+        tally.sent += 1;
+        if socket
+            .send(format!("{}\n", message(&mut rng)).as_bytes())
+            .await
+            .is_err()
+        {
+            tally.errors += 1;
+            continue;
+        }
+
+        // A late reply to a dropped datagram is taken for the reply to the next one,
+        // the latency stays within the timeout either way.
+        match tokio::time::timeout(config.timeout(), socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => match String::from_utf8_lossy(&buf[..len]).trim().parse::<Reply>() {
+                Ok(reply) => tally.reply(&reply, sent.elapsed()),
+                Err(_) => tally.errors += 1,
+            },
+            Ok(Err(_)) => tally.errors += 1,
+            Err(_) => tally.dropped += 1,
+        }
+    }
+
+    tally
+}
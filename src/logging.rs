@@ -0,0 +1,204 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Local;
+use serde::Deserialize;
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+    span,
+};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    EnvFilter, Layer, layer::Context, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+
+/// Where the server logs go, the `[log]` table of the server config:
+///
+/// ```toml
+/// [log]
+/// dir = "logs"
+/// level = "info"   # or a filter such as "otus_tokio_devices::server=debug"
+/// ```
+///
+/// `RUST_LOG` takes precedence over `level`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Directory of the daily rotated `server.log`, used while the TUI is on.
+    pub dir: PathBuf,
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            dir: "logs".into(),
+            level: "info".into(),
+        }
+    }
+}
+
+/// Sets up the global subscriber: stderr when headless, otherwise a daily
+/// rotated file, so that the TUI screen stays intact.
+///
+/// Warnings and errors also go to the returned [`LogPane`]. The guard flushes
+/// the file when dropped and must live until exit.
+pub fn init(config: &LogConfig, headless: bool) -> anyhow::Result<(LogPane, Option<WorkerGuard>)> {
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.level))?;
+    let pane = LogPane::default();
+
+    let (stderr, file, guard) = match headless {
+        true => (
+            Some(tracing_subscriber::fmt::layer().with_writer(std::io::stderr)),
+            None,
+            None,
+        ),
+        false => {
+            std::fs::create_dir_all(&config.dir)?;
+            let appender = tracing_appender::rolling::daily(&config.dir, "server.log");
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let file = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer);
+            (None, Some(file), Some(guard))
+        }
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr)
+        .with(file)
+        .with(pane.clone())
+        .try_init()?;
+
+    Ok((pane, guard))
+}
+
+/// Warnings and errors kept for the TUI, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct LogPane {
+    lines: Arc<Mutex<VecDeque<LogLine>>>,
+}
+
+/// Line of the [`LogPane`] with the level of its event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    pub level: Level,
+    pub text: String,
+}
+
+impl LogPane {
+    /// Lines kept, older ones are forgotten.
+    pub const CAPACITY: usize = 100;
+
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn push(&self, line: LogLine) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() >= Self::CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+/// Fields of a span, e.g. `peer=127.0.0.1:50000 device=kitchen`.
+struct SpanFields(Vec<(&'static str, String)>);
+
+impl<S> Layer<S> for LogPane
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.rest));
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+
+        // Поле записывается заново, а не добавляется ещё раз
+        if let Some(span) = ctx.span(id)
+            && let Some(SpanFields(known)) = span.extensions_mut().get_mut::<SpanFields>()
+        {
+            for (name, value) in fields.rest {
+                match known.iter_mut().find(|(known, _)| *known == name) {
+                    Some((_, known)) => *known = value,
+                    None => known.push((name, value)),
+                }
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level > Level::WARN {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let mut text = format!(
+            "{} {:>5} {}",
+            Local::now().format("%H:%M:%S"),
+            level,
+            fields.message
+        );
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(known)) = span.extensions().get::<SpanFields>() {
+                    write_fields(&mut text, known);
+                }
+            }
+        }
+        write_fields(&mut text, &fields.rest);
+
+        self.push(LogLine { level, text });
+    }
+}
+
+fn write_fields(text: &mut String, fields: &[(&'static str, String)]) {
+    for (name, value) in fields {
+        let _ = write!(text, " {}={}", name, value);
+    }
+}
+
+/// The message of an event and the other fields by name.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: Vec<(&'static str, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.push_str(value),
+            name => self.rest.push((name, value.to_string())),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            name => self.rest.push((name, format!("{:?}", value))),
+        }
+    }
+}
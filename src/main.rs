@@ -1,33 +1,47 @@
-use std::str::FromStr;
-use std::{result::Result::Ok, sync::Arc};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use futures::{FutureExt, StreamExt};
+use otus_tokio_devices::api::{self, Api};
+use otus_tokio_devices::config::{self, ServerConfig};
+use otus_tokio_devices::device;
+use otus_tokio_devices::event::DeviceEvent;
+use otus_tokio_devices::house::House;
+use otus_tokio_devices::humidity::Humidity;
+use otus_tokio_devices::journal::Journal;
+use otus_tokio_devices::logging::{self, LogPane};
+use otus_tokio_devices::metrics;
+use otus_tokio_devices::poll;
 use otus_tokio_devices::power::Power;
+use otus_tokio_devices::presence::{self, Presence};
+use otus_tokio_devices::reading::Reading;
+use otus_tokio_devices::registry::Registry;
+use otus_tokio_devices::report;
+use otus_tokio_devices::sensor_data::SensorData;
+use otus_tokio_devices::server;
 use otus_tokio_devices::socket::Socket;
+use otus_tokio_devices::state::State;
+use otus_tokio_devices::stream;
 use otus_tokio_devices::temperature::Temperature;
 use otus_tokio_devices::termometer::Termometer;
+use otus_tokio_devices::tls::TlsServer;
 
-use color_eyre::Result;
+use chrono::{Local, Utc};
+use color_eyre::{Result, eyre::eyre};
 
 use ratatui::{
     DefaultTerminal, Frame,
     layout::{Constraint, Direction, Layout},
+    style::{Color, Style},
     widgets::{Block, Borders, Gauge, List, ListItem},
 };
-use tokio::io::AsyncWriteExt;
 use tokio::{
-    io::AsyncReadExt,
-    net::{TcpListener, TcpStream},
-    sync::mpsc,
+    net::{TcpListener, UdpSocket},
+    sync::{broadcast, mpsc},
 };
-
-#[derive(Debug)]
-pub enum SensorData {
-    Temperature(f32),
-    Power(f32),
-    Unknown,
-}
+use tracing::Level;
 
 pub struct App {
     /// Is the application running?
@@ -35,86 +49,188 @@ pub struct App {
     // Event stream.
     event_stream: EventStream,
     messages: Vec<String>,
+    /// Warnings and errors of the server.
+    log: LogPane,
 
-    termometer: Termometer,
-    socket: Socket,
-    rx: tokio::sync::mpsc::Receiver<Arc<SensorData>>,
+    state: Arc<Mutex<State>>,
+    rx: tokio::sync::mpsc::Receiver<Arc<DeviceEvent>>,
 }
 
+/// How often the config file is checked for changes.
+const CONFIG_POLL_PERIOD: Duration = Duration::from_secs(2);
+/// How often device presence is checked.
+const PRESENCE_PERIOD: Duration = Duration::from_secs(1);
+/// Height of the log pane, borders included.
+const LOG_PANE_HEIGHT: u16 = 7;
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let (tx, rx) = mpsc::channel::<Arc<SensorData>>(32);
+    // Без интерфейса журнал пишется в stderr, с интерфейсом - в файл
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let headless = args.iter().any(|arg| arg == "--headless");
+    let config_path = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from);
+    let config = match &config_path {
+        Some(path) => ServerConfig::load(path).map_err(|e| eyre!(e))?,
+        None => ServerConfig::default(),
+    };
+    let (log, _guard) = logging::init(&config.log, headless).map_err(|e| eyre!(e))?;
+
+    // События устройств идут в интерфейс и подписчикам потока через API
+    let (tx, device_rx) = mpsc::channel::<Arc<DeviceEvent>>(32);
+    let (ui_tx, rx) = mpsc::channel::<Arc<DeviceEvent>>(32);
+    let (events, _) = broadcast::channel(config.stream.capacity.max(1));
+
+    let listener = TcpListener::bind(&config.listen).await?;
 
-    let listener = TcpListener::bind("localhost:8080").await?;
+    let termometer = Termometer::new(Temperature::new(0.0));
+    let socket = Socket::off();
+    let mut registry = Registry::new(config.devices.clone(), config.require_auth);
+    if let Some(path) = &config.counters
+        && registry.keyed()
+    {
+        registry = registry.with_counters(path)?;
+    }
+    let house = match &config.house {
+        Some(house) => House::from_config(house).map_err(|e| eyre!(e))?,
+        None => House::default(),
+    };
+    let mut state = State::new(termometer, socket)
+        .with_registry(registry)
+        .with_house(house)
+        .with_presence(config.presence)
+        .with_limits(config.limits)
+        .with_deadband(config.deadband)
+        .with_poll(config.poll.clone());
+    if let Some(path) = &config.journal {
+        for reading in Journal::load(path)? {
+            state.history_mut().push(reading);
+        }
+        state = state.with_journal(Journal::open(path)?);
+    }
+    let state = Arc::new(Mutex::new(state));
+    tokio::spawn(stream::fan_out(
+        device_rx,
+        ui_tx,
+        events.clone(),
+        Arc::clone(&state),
+    ));
+
+    // Ключи и калибровка устройств подхватываются без перезапуска
+    if let Some(path) = config_path {
+        let state = Arc::clone(&state);
+        tokio::spawn(config::watch(path, CONFIG_POLL_PERIOD, move |config| {
+            let mut state = state.lock().unwrap();
+            state
+                .registry_mut()
+                .update(config.devices, config.require_auth);
+            state.set_presence(config.presence);
+            state.set_limits(config.limits);
+            state.set_deadband(config.deadband);
+            // Опрос уже запущенных устройств не меняется, только куда слать команды
+            state.set_poll(config.poll);
+        }));
+    }
 
-    let t = Arc::new(tx);
+    if let Some(tls) = &config.tls {
+        let tls_server = TlsServer::new(tls).map_err(|e| eyre!(e))?;
+        let tls_listener = TcpListener::bind(&tls.listen).await?;
 
-    tokio::spawn(async move {
-        loop {
-            let (tcp, _) = listener.accept().await.unwrap();
-
-            let tx_clone = Arc::clone(&t);
-            tokio::spawn(async move {
-                match handle_connection(tcp).await {
-                    Ok(data) => {
-                        if let Err(send_err) = tx_clone.send(Arc::new(data)).await {
-                            eprintln!("Failed to send data through channel: {:?}", send_err);
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Error handling connection: {:?}", e);
-                    }
-                }
-            });
-        }
-    });
+        let server_state = Arc::clone(&state);
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server::serve_tls(tls_listener, tls_server, server_state, tx).await {
+                tracing::error!(error = ?e, "tls server stopped");
+            }
+        });
+    }
 
-    let termometer = Termometer::new(Temperature::new(0.0));
-    let socket = Socket::new(Power::new(0.0));
+    if let Some(udp) = &config.udp {
+        let udp_socket = UdpSocket::bind(udp).await?;
 
-    let terminal = ratatui::init();
+        let server_state = Arc::clone(&state);
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server::serve_udp(udp_socket, server_state, tx).await {
+                tracing::error!(error = ?e, "udp server stopped");
+            }
+        });
+    }
 
-    let mut app = App::new(termometer, socket, rx).await;
-    let _r = app.run(terminal).await;
+    if let Some(address) = &config.metrics {
+        let metrics_listener = TcpListener::bind(address).await?;
 
-    Ok(())
-}
+        let server_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(metrics_listener, server_state).await {
+                tracing::error!(error = ?e, "metrics endpoint stopped");
+            }
+        });
+    }
 
-async fn handle_connection(mut socket: TcpStream) -> anyhow::Result<SensorData> {
-    let mut buf = [0; 128];
+    if let Some(address) = &config.api {
+        let api_listener = TcpListener::bind(address).await?;
 
-    let n = socket.read(&mut buf).await?;
-    let recieved = String::from_utf8_lossy(&buf[..n]);
+        let api = Api::new(Arc::clone(&state), tx.clone())
+            .with_token(config.api_token.clone())
+            .with_stream(events, config.stream);
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(api_listener, api).await {
+                tracing::error!(error = ?e, "api stopped");
+            }
+        });
+    }
 
-    //println!("{:?}", recieved);
+    tokio::spawn(presence::watch(
+        Arc::clone(&state),
+        tx.clone(),
+        PRESENCE_PERIOD,
+    ));
 
-    if let Ok(t) = Termometer::from_str(&recieved) {
-        return Ok(SensorData::Temperature(t.temperature().get()));
+    for device in config.poll.iter().cloned() {
+        tokio::spawn(poll::poll(device, Arc::clone(&state), tx.clone()));
     }
 
-    if let Ok(s) = Socket::from_str(&recieved) {
-        return Ok(SensorData::Power(s.power().get()));
+    if let Some(report) = config.report.clone() {
+        tokio::spawn(report::write_every(Arc::clone(&state), report));
+    }
+
+    let server_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        if let Err(e) = server::serve(listener, server_state, tx).await {
+            tracing::error!(error = ?e, "server stopped");
+        }
+    });
+
+    tracing::info!(listen = %config.listen, "server started");
+
+    if headless {
+        run_headless(rx).await;
+        return Ok(());
     }
 
-    // Отправляем ответ клиенту
-    let response = format!("Ok: {}\n", recieved);
-    socket.write_all(response.as_bytes()).await?;
+    let terminal = ratatui::init();
+
+    let mut app = App::new(state, rx, log).await;
+    let _r = app.run(terminal).await;
 
-    Ok(SensorData::Unknown)
+    Ok(())
 }
 
 impl App {
     pub async fn new(
-        t: Termometer,
-        s: Socket,
-        rx: tokio::sync::mpsc::Receiver<Arc<SensorData>>,
+        state: Arc<Mutex<State>>,
+        rx: tokio::sync::mpsc::Receiver<Arc<DeviceEvent>>,
+        log: LogPane,
     ) -> Self {
         Self {
             running: true,
             event_stream: EventStream::default(),
             messages: vec![],
-            termometer: t,
-            socket: s,
+            log,
+            state,
             rx,
         }
     }
@@ -135,56 +251,109 @@ impl App {
     /// - <https://docs.rs/ratatui/latest/ratatui/widgets/index.html>
     /// - <https://github.com/ratatui/ratatui/tree/master/examples>
     fn draw(&mut self, f: &mut Frame) {
-        if let Ok(data) = &self.rx.try_recv() {
-            self.process_sensor_data(data);
+        while let Ok(data) = self.rx.try_recv() {
+            self.process_event(&data);
         }
 
+        let state = self.state.lock().unwrap();
+        let now = Utc::now();
+
+        // Без плана дома показываем отдельные устройства, иначе - комнаты
+        let mut gauges: Vec<(Option<&str>, Vec<Gauge>)> = vec![];
+        if state.house().is_empty() {
+            let devices = state
+                .devices()
+                .iter()
+                .filter_map(|kind| {
+                    let data = state.device_data(kind)?;
+                    Some(gauge(None, &data, state.presence(kind, now)))
+                })
+                .collect();
+            gauges.push((None, devices));
+        } else {
+            for room in state.house().rooms() {
+                let devices = room
+                    .devices()
+                    .map(|device| {
+                        let path = format!("{}/{}", room.name(), device.id());
+                        gauge(
+                            Some(device.id()),
+                            &device.data(),
+                            state.presence(&path, now),
+                        )
+                    })
+                    .collect();
+                gauges.push((Some(room.name()), devices));
+            }
+        }
+
+        let mut constraints: Vec<Constraint> = gauges
+            .iter()
+            .map(|(room, devices)| match room {
+                Some(_) => Constraint::Length(3 * devices.len() as u16 + 2),
+                None => Constraint::Length(3 * devices.len() as u16),
+            })
+            .collect();
+        constraints.push(Constraint::Min(5)); // Список сообщений
+        constraints.push(Constraint::Length(LOG_PANE_HEIGHT)); // Журнал
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Length(3), // шкала термометра
-                    Constraint::Length(3), // шкала розетки
-                    Constraint::Min(5),    // Список сообщений
-                ]
-                .as_ref(),
-            )
+            .constraints(constraints)
             .split(f.area());
 
-        // Отображение первой шкалы
-        let gauge1 = Gauge::default()
-            .block(Block::default().borders(Borders::ALL).title("Термометер"))
-            .label(format!(
-                "Температура: {:.2} C из {} С",
-                self.termometer.temperature().get(),
-                Temperature::MAX_TEMPERATURE
-            ))
-            .ratio(Temperature::ratio(self.termometer.temperature().get()).into());
-        f.render_widget(gauge1, chunks[0]);
-
-        // Отображение второй шкалы
-        let gauge2 = Gauge::default()
-            .block(Block::default().borders(Borders::ALL).title("Розетка"))
-            .label(format!(
-                "Мощность {:.1} W из {} W",
-                self.socket.power().get(),
-                Power::MAX_POWER
-            ))
-            .ratio(Power::ratio(self.socket.power().get()).into());
-        f.render_widget(gauge2, chunks[1]);
+        for (i, (room, devices)) in gauges.into_iter().enumerate() {
+            let mut area = chunks[i];
+            if let Some(room) = room {
+                let block = Block::default().borders(Borders::ALL).title(room);
+                area = block.inner(chunks[i]);
+                f.render_widget(block, chunks[i]);
+            }
+
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![Constraint::Length(3); devices.len()])
+                .split(area);
+            for (gauge, row) in devices.into_iter().zip(rows.iter()) {
+                f.render_widget(gauge, *row);
+            }
+        }
 
         // Отображение списка сообщений
+        let title = match state.metrics().dropped_total() {
+            0 => "Сообщения".to_string(),
+            dropped => format!("Сообщения (отброшено: {})", dropped),
+        };
         let messages: Vec<ListItem> = self
             .messages
             .iter()
             .map(|msg| ListItem::new(msg.as_str()))
             .collect();
         let messages_list = List::new(messages)
-            .block(Block::default().borders(Borders::ALL).title("Сообщения"))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .direction(ratatui::widgets::ListDirection::BottomToTop)
             .scroll_padding(2);
 
-        f.render_widget(messages_list, chunks[2]);
+        f.render_widget(messages_list, chunks[chunks.len() - 2]);
+
+        // Предупреждения и ошибки сервера, последние снизу
+        let log: Vec<ListItem> = self
+            .log
+            .lines()
+            .into_iter()
+            .rev()
+            .map(|line| {
+                let style = match line.level {
+                    Level::ERROR => Style::default().fg(Color::Red),
+                    _ => Style::default().fg(Color::Yellow),
+                };
+                ListItem::new(line.text).style(style)
+            })
+            .collect();
+        let log_list = List::new(log)
+            .block(Block::default().borders(Borders::ALL).title("Журнал"))
+            .direction(ratatui::widgets::ListDirection::BottomToTop);
+
+        f.render_widget(log_list, chunks[chunks.len() - 1]);
     }
 
     /// Reads the crossterm events and updates the state of [`App`].
@@ -228,22 +397,193 @@ impl App {
         self.running
     }
 
-    pub fn process_sensor_data(&mut self, data: &SensorData) {
-        match *data {
-            SensorData::Temperature(temp) => {
-                self.termometer.temperature_mut().set(temp); // Устанавливаем температуру в Termometer
-                self.messages
-                    .insert(0, format!("🌡️Temperature set to {} C", temp));
+    pub fn process_event(&mut self, event: &DeviceEvent) {
+        match event {
+            DeviceEvent::Reading(reading) => {
+                let message = Self::describe(reading);
+                self.messages.insert(0, message);
             }
-            SensorData::Power(power) => {
-                self.socket.power_mut().set(power); // Устанавливаем мощность в Socket
-                self.messages
-                    .insert(0, format!("⚡ Power set to {} W", power));
+            DeviceEvent::Batch(readings) => {
+                let time = Local::now().format("%H:%M:%S");
+                self.messages.insert(
+                    0,
+                    format!("{} 📦 Batch of {} readings received", time, readings.len()),
+                );
+                for reading in readings {
+                    let message = format!("    {}", Self::describe(reading));
+                    self.messages.insert(0, message);
+                }
             }
-            SensorData::Unknown => {
+            DeviceEvent::Rejected { message, reason } => {
+                let time = Local::now().format("%H:%M:%S");
                 self.messages
-                    .insert(0, "Unknown data received.".to_string());
+                    .insert(0, format!("{} ❌ Rejected {:?}: {}", time, message, reason));
+            }
+            DeviceEvent::Command(command) => {
+                let time = Local::now().format("%H:%M:%S");
+                self.messages.insert(0, format!("{} 📋 {}", time, command));
+            }
+            DeviceEvent::Presence {
+                device,
+                presence,
+                last_seen,
+            } => {
+                let time = Local::now().format("%H:%M:%S");
+                let icon = match presence {
+                    Presence::Online => "🟢",
+                    Presence::Stale => "🟡",
+                    Presence::Offline => "🔴",
+                };
+                let mut message = format!("{} {} [{}] {}", time, icon, device, presence);
+                if let Some(last_seen) = last_seen
+                    && *presence != Presence::Online
+                {
+                    let last_seen = last_seen.with_timezone(&Local).format("%H:%M:%S");
+                    message.push_str(&format!(", last seen {}", last_seen));
+                }
+                self.messages.insert(0, message);
             }
         }
     }
+
+    fn describe(reading: &Reading) -> String {
+        let time = reading.received.with_timezone(&Local).format("%H:%M:%S");
+        let time = match &reading.device {
+            Some(device) => format!("{} [{}]", time, device),
+            None => time.to_string(),
+        };
+        let mut message = match reading.data {
+            SensorData::Temperature(temp) => format!("{} 🌡️Temperature set to {} C", time, temp),
+            SensorData::Socket(socket) if !socket.on => format!("{} ⚡ Socket switched off", time),
+            SensorData::Socket(socket) => format!(
+                "{} ⚡ Power set to {} W{}",
+                time, socket.power, socket.telemetry
+            ),
+            SensorData::Humidity(humidity) => {
+                format!("{} 💧 Humidity set to {} %", time, humidity)
+            }
+            SensorData::Measurement { kind, value } => {
+                format!("{} {} set to {} {}", time, kind, value, reading.data.unit())
+            }
+            SensorData::Unknown => format!("{} Unknown data received.", time),
+        };
+
+        if let Some(device_time) = reading.device_time {
+            let device_time = device_time.with_timezone(&Local).format("%H:%M:%S");
+            message.push_str(&format!(" (device {})", device_time));
+        }
+
+        if let Some(raw) = reading.raw {
+            message.push_str(&format!(" (raw {})", raw));
+        }
+
+        if let Some(skew) = reading.clock_skew() {
+            message.push_str(&format!(" ⚠ clock skew {:+} s", skew.num_seconds()));
+        }
+
+        message
+    }
+}
+
+/// Logs the device events instead of showing them until Ctrl+C.
+async fn run_headless(mut rx: mpsc::Receiver<Arc<DeviceEvent>>) {
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(event) => log_event(&event),
+                None => return,
+            },
+            _ = tokio::signal::ctrl_c() => return,
+        }
+    }
+}
+
+fn log_event(event: &DeviceEvent) {
+    match event {
+        DeviceEvent::Reading(reading) => log_reading(reading),
+        DeviceEvent::Batch(readings) => {
+            tracing::info!(count = readings.len(), "batch received");
+            readings.iter().for_each(log_reading);
+        }
+        DeviceEvent::Rejected { message, reason } => {
+            tracing::info!(message, reason, "rejected")
+        }
+        DeviceEvent::Command(command) => tracing::info!(command, "command"),
+        DeviceEvent::Presence {
+            device, presence, ..
+        } => tracing::info!(device, %presence, "presence changed"),
+    }
+}
+
+fn log_reading(reading: &Reading) {
+    tracing::info!(device = reading.device.as_deref(), data = %reading.data, "reading");
+}
+
+/// Gauge of the live data of a device, titled with its name if given or its
+/// kind otherwise. Sockets also tell the relay state in the title.
+///
+/// Gauges of devices that stopped reporting are greyed out and tell why.
+fn gauge<'a>(name: Option<&str>, data: &SensorData, presence: Presence) -> Gauge<'a> {
+    let (title, label, ratio) = match *data {
+        SensorData::Temperature(temperature) => (
+            name.unwrap_or("Термометер").to_string(),
+            format!(
+                "Температура: {:.2} C из {} С",
+                temperature,
+                Temperature::MAX_TEMPERATURE
+            ),
+            Temperature::ratio(temperature),
+        ),
+        SensorData::Socket(socket) => {
+            let relay = match socket.on {
+                true => "Розетка: включена",
+                false => "Розетка: выключена",
+            };
+            let title = match name {
+                Some(name) => format!("{} - {}", name, relay),
+                None => relay.to_string(),
+            };
+            let label = format!(
+                "Мощность {:.1} W из {} W{}",
+                socket.power,
+                Power::MAX_POWER,
+                socket.telemetry
+            );
+            (title, label, Power::ratio(socket.power))
+        }
+        SensorData::Humidity(humidity) => (
+            name.unwrap_or("Гигрометр").to_string(),
+            format!(
+                "Влажность: {:.1} % из {} %",
+                humidity,
+                Humidity::MAX_HUMIDITY
+            ),
+            Humidity::ratio(humidity),
+        ),
+        ref data => {
+            let value = data.value().unwrap_or_default();
+            let ratio = device::kind(data.kind()).map_or(0.0, |kind| {
+                let range = kind.range();
+                (value - range.start()) / (range.end() - range.start())
+            });
+            (
+                name.unwrap_or(data.kind()).to_string(),
+                format!("{:.1} {}", value, data.unit()),
+                ratio.clamp(0.0, 1.0),
+            )
+        }
+    };
+
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let gauge = Gauge::default().label(label).ratio(ratio.into());
+    let status = match presence {
+        Presence::Online => return gauge.block(block),
+        Presence::Stale => "устарело",
+        Presence::Offline => "нет связи",
+    };
+
+    let grey = Style::default().fg(Color::DarkGray);
+    gauge
+        .gauge_style(grey)
+        .block(block.border_style(grey).title_bottom(status))
 }
@@ -1,3 +1,7 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use crate::{sensor_data::SensorData, socket::SocketReading};
+
 pub enum ThermometerMessage {
     Off,
     Value(f32),
@@ -23,27 +27,37 @@ impl From<ThermometerMessage> for String {
     }
 }
 
+/// Answer of a socket to a controller command, one line: its state as it
+/// reports it, e.g. `Socket 1500 W 230 V 6.5 A` or `Socket 0 W off`, or
+/// `Nack <reason>`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum SocketMessage {
-    Off,
-    Value(u8),
+    /// Relay state, power and telemetry.
+    Reading(SocketReading),
+    /// Command the socket did not take.
+    Nack(String),
 }
 
-impl From<String> for SocketMessage {
-    fn from(value: String) -> Self {
-        let v = value.trim().parse::<u8>().unwrap();
-
-        match v {
-            0 => SocketMessage::Off,
-            _ => SocketMessage::Value(v),
+impl Display for SocketMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketMessage::Reading(reading) => write!(f, "{}", SensorData::Socket(*reading)),
+            SocketMessage::Nack(reason) => write!(f, "Nack {}", reason),
         }
     }
 }
 
-impl From<SocketMessage> for String {
-    fn from(value: SocketMessage) -> Self {
-        match value {
-            SocketMessage::Off => "0".into(),
-            SocketMessage::Value(v) => format!("{}", v),
+impl FromStr for SocketMessage {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(reason) = s.trim().strip_prefix("Nack ") {
+            return Ok(SocketMessage::Nack(reason.to_string()));
+        }
+
+        match s.parse::<SensorData>()? {
+            SensorData::Socket(reading) => Ok(SocketMessage::Reading(reading)),
+            data => Err(format!("{} is not a socket reading", data).into()),
         }
     }
 }
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{Router, extract, http::header, response::IntoResponse, routing::get};
+use tokio::net::TcpListener;
+
+use crate::{limits::Refusal, sensor_data::SensorData, state::State};
+
+/// Counters of what the server has been through since the start.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Connections accepted over TCP and TLS.
+    pub connections: u64,
+    /// Connections refused or cut off, by [`Refusal::label`].
+    pub refused: HashMap<&'static str, u64>,
+    /// Messages dropped by the rate limits and the dead-band filter, by
+    /// [`Dropped::label`].
+    pub dropped: HashMap<&'static str, u64>,
+    /// Frames received over any transport.
+    pub messages: u64,
+    /// Frames that are not a well-formed message.
+    pub parse_errors: u64,
+    /// Messages of a device kind the server does not know.
+    pub unknown_messages: u64,
+    /// Events the TUI was too slow to take, see [`crate::stream::fan_out`].
+    pub ui_dropped: u64,
+    /// Time to process a frame, from parsing to the reply.
+    pub latency: Histogram,
+}
+
+/// Why a message was dropped without being stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dropped {
+    DeviceRate,
+    ConnectionRate,
+    /// Changed less than the dead-band of the device.
+    Deadband,
+}
+
+impl Dropped {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Dropped::DeviceRate => "device_rate",
+            Dropped::ConnectionRate => "connection_rate",
+            Dropped::Deadband => "deadband",
+        }
+    }
+}
+
+impl Metrics {
+    pub fn refuse(&mut self, refusal: Refusal) {
+        *self.refused.entry(refusal.label()).or_default() += 1;
+    }
+
+    pub fn drop(&mut self, dropped: Dropped) {
+        *self.dropped.entry(dropped.label()).or_default() += 1;
+    }
+
+    /// Messages dropped for any reason.
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped.values().sum()
+    }
+
+    /// Connections refused or cut off for any reason.
+    pub fn refused_total(&self) -> u64 {
+        self.refused.values().sum()
+    }
+}
+
+/// Observations counted into buckets by their upper bounds, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, the last one above every bound.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    /// From 50 µs to 1 s: processing a frame takes microseconds unless the
+    /// state lock is contended.
+    pub const LATENCY_BOUNDS: &'static [f64] = &[
+        0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.1, 1.0,
+    ];
+
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self.bounds.partition_point(|bound| *bound < seconds);
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Observations up to each bound, ending with `+Inf`.
+    pub fn cumulative(&self) -> impl Iterator<Item = (String, u64)> {
+        let bounds = self.bounds.iter().map(|b| b.to_string());
+        let counts = self.counts.iter().scan(0, |total, count| {
+            *total += count;
+            Some(*total)
+        });
+
+        bounds.chain(["+Inf".to_string()]).zip(counts)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(Self::LATENCY_BOUNDS)
+    }
+}
+
+/// Everything in the Prometheus text format: live values of the devices,
+/// counters and the processing latency.
+pub fn render(state: &State) -> String {
+    let mut out = String::new();
+    let metrics = state.metrics();
+
+    let (mut temperature, mut power, mut on, mut humidity) = (vec![], vec![], vec![], vec![]);
+    let mut other = vec![];
+    for device in state.devices() {
+        match state.device_data(&device) {
+            Some(SensorData::Temperature(t)) => temperature.push((device, t)),
+            Some(SensorData::Socket(s)) => {
+                power.push((device.clone(), s.power));
+                on.push((device, s.on as u8 as f32));
+            }
+            Some(SensorData::Humidity(h)) => humidity.push((device, h)),
+            Some(data @ SensorData::Measurement { value, .. }) => {
+                other.push((device, data.kind(), data.unit(), value))
+            }
+            Some(SensorData::Unknown) | None => {}
+        }
+    }
+
+    let gauges = [
+        ("temperature_celsius", "Current temperature.", temperature),
+        ("power_watts", "Current power drawn by a socket.", power),
+        ("socket_on", "Whether a socket is switched on.", on),
+        ("humidity_percent", "Current relative humidity.", humidity),
+    ];
+    for (name, help, values) in gauges {
+        describe(&mut out, name, help, "gauge");
+        for (device, value) in values {
+            let _ = writeln!(
+                out,
+                "devices_{}{{device=\"{}\"}} {}",
+                name,
+                escape(&device),
+                value
+            );
+        }
+    }
+
+    let name = "reading";
+    describe(
+        &mut out,
+        name,
+        "Current value of a device of another kind.",
+        "gauge",
+    );
+    for (device, kind, unit, value) in other {
+        let _ = writeln!(
+            out,
+            "devices_{}{{device=\"{}\",kind=\"{}\",unit=\"{}\"}} {}",
+            name,
+            escape(&device),
+            escape(kind),
+            escape(unit),
+            value
+        );
+    }
+
+    let counters = [
+        ("messages_total", "Frames received.", metrics.messages),
+        (
+            "parse_errors_total",
+            "Frames that are not a well-formed message.",
+            metrics.parse_errors,
+        ),
+        (
+            "unknown_messages_total",
+            "Messages of an unknown device kind.",
+            metrics.unknown_messages,
+        ),
+        (
+            "ui_dropped_total",
+            "Events the TUI was too slow to take.",
+            metrics.ui_dropped,
+        ),
+        (
+            "connections_total",
+            "Connections accepted.",
+            metrics.connections,
+        ),
+    ];
+    for (name, help, value) in counters {
+        describe(&mut out, name, help, "counter");
+        let _ = writeln!(out, "devices_{} {}", name, value);
+    }
+
+    let name = "connections_open";
+    describe(&mut out, name, "Connections open at the moment.", "gauge");
+    let _ = writeln!(out, "devices_{} {}", name, state.connections().total());
+
+    let labelled = [
+        (
+            "connections_refused_total",
+            "Connections refused or cut off.",
+            &metrics.refused,
+        ),
+        (
+            "dropped_total",
+            "Messages dropped by the rate limits and the dead-band filter.",
+            &metrics.dropped,
+        ),
+    ];
+    for (name, help, values) in labelled {
+        describe(&mut out, name, help, "counter");
+        let mut values: Vec<_> = values.iter().collect();
+        values.sort();
+        for (reason, value) in values {
+            let _ = writeln!(out, "devices_{}{{reason=\"{}\"}} {}", name, reason, value);
+        }
+    }
+
+    let name = "processing_seconds";
+    describe(&mut out, name, "Time to process a frame.", "histogram");
+    for (bound, count) in metrics.latency.cumulative() {
+        let _ = writeln!(out, "devices_{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+    }
+    let _ = writeln!(out, "devices_{}_sum {}", name, metrics.latency.sum());
+    let _ = writeln!(out, "devices_{}_count {}", name, metrics.latency.count());
+
+    out
+}
+
+fn describe(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP devices_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE devices_{} {}", name, kind);
+}
+
+/// Label value with `\`, `"` and newlines escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// Serves `GET /metrics` for Prometheus to scrape.
+pub async fn serve(listener: TcpListener, state: Arc<Mutex<State>>) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(scrape))
+        .with_state(state);
+
+    axum::serve(listener, app).await
+}
+
+async fn scrape(extract::State(state): extract::State<Arc<Mutex<State>>>) -> impl IntoResponse {
+    let text = render(&state.lock().unwrap());
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text)
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{reading::Stamped, sensor_data::SensorData};
+
+/// What to give up when the outbox is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DropPolicy {
+    /// Drop the oldest queued reading to make room for the new one.
+    #[default]
+    Oldest,
+    /// Keep the queue and drop the new reading.
+    Newest,
+}
+
+/// Readings waiting for the server to come back, oldest first.
+///
+/// Up to `memory` readings are kept in memory; with a spill file the rest goes
+/// to disk, one `<message> @<device time>` per line, and survives a restart.
+/// No more than `capacity` readings are kept in total.
+#[derive(Debug)]
+pub struct Outbox {
+    queue: VecDeque<Stamped<SensorData>>,
+    memory: usize,
+    capacity: usize,
+    policy: DropPolicy,
+    spill: Option<PathBuf>,
+    spilled: usize,
+    dropped: u64,
+}
+
+impl Outbox {
+    pub const DEFAULT_CAPACITY: usize = 1000;
+
+    /// In-memory outbox for up to `capacity` readings.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            memory: capacity,
+            capacity,
+            policy: DropPolicy::default(),
+            spill: None,
+            spilled: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: DropPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Keeps only `memory` readings in memory and spills the rest to `path`.
+    ///
+    /// Readings already in the file, left from a previous run, are queued first.
+    pub fn with_spill(mut self, path: impl AsRef<Path>, memory: usize) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        self.spilled = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().count(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        self.spill = Some(path);
+        self.memory = memory;
+
+        Ok(self)
+    }
+
+    /// Readings waiting, in memory and on disk.
+    pub fn len(&self) -> usize {
+        self.queue.len() + self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Readings lost because the outbox was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Queues a reading, dropping one according to the policy when full.
+    ///
+    /// Returns whether the reading was queued, it is not when it is the one
+    /// dropped.
+    pub fn push(&mut self, reading: Stamped<SensorData>) -> std::io::Result<bool> {
+        if self.len() >= self.capacity {
+            self.dropped += 1;
+            match self.policy {
+                DropPolicy::Oldest if self.capacity > 0 => {
+                    self.load()?;
+                    self.queue.pop_front();
+                }
+                _ => return Ok(false),
+            }
+        }
+
+        match &self.spill {
+            Some(path) if self.spilled > 0 || self.queue.len() >= self.memory => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", reading)?;
+                self.spilled += 1;
+            }
+            _ => self.queue.push_back(reading),
+        }
+
+        Ok(true)
+    }
+
+    /// Up to `limit` oldest readings, left in the outbox until [`Outbox::remove`].
+    pub fn peek(&mut self, limit: usize) -> std::io::Result<Vec<Stamped<SensorData>>> {
+        self.load()?;
+
+        Ok(self.queue.iter().take(limit).cloned().collect())
+    }
+
+    /// Forgets the `count` oldest readings once they are delivered.
+    pub fn remove(&mut self, count: usize) {
+        let count = count.min(self.queue.len());
+        self.queue.drain(..count);
+    }
+
+    /// Moves the oldest spilled readings into memory once the memory queue is
+    /// empty, so the order stays oldest first.
+    fn load(&mut self) -> std::io::Result<()> {
+        let Some(path) = &self.spill else {
+            return Ok(());
+        };
+        if !self.queue.is_empty() || self.spilled == 0 {
+            return Ok(());
+        }
+
+        let lines = BufReader::new(File::open(path)?)
+            .lines()
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let (head, rest) = lines.split_at(self.memory.max(1).min(lines.len()));
+
+        for line in head {
+            match Stamped::<SensorData>::from_str(line) {
+                Ok(reading) => self.queue.push_back(reading),
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), error = %e, "damaged outbox line")
+                }
+            }
+        }
+
+        let mut file = File::create(path)?;
+        for line in rest {
+            writeln!(file, "{}", line)?;
+        }
+        self.spilled = rest.len();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, anyhow};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::Sender,
+};
+
+use crate::{
+    event::DeviceEvent,
+    protocol,
+    reading::{Reading, Stamped},
+    reply::Reply,
+    sensor_data::SensorData,
+    socket::SocketCommand,
+    state::State,
+};
+
+/// Device the server asks for readings instead of waiting for it to push, an
+/// entry of the `[[poll]]` array of the server config:
+///
+/// ```toml
+/// [[poll]]
+/// device = "kitchen/termometer"
+/// address = "192.168.1.20:7000"
+/// query = "report"
+/// interval_ms = 5000
+/// timeout_ms = 1000
+/// retries = 2
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PollConfig {
+    /// Id the readings are attributed to, `<room>/<device>` for house devices.
+    pub device: String,
+    pub address: String,
+    /// Line the device answers with a reading, e.g. `report power` for a socket
+    /// answering `Socket 1500 W`.
+    #[serde(default = "report")]
+    pub query: String,
+    #[serde(default = "five_seconds")]
+    pub interval_ms: u64,
+    /// Time to connect, send the query and read the reply.
+    #[serde(default = "second")]
+    pub timeout_ms: u64,
+    /// Attempts after the first failed one, each over a new connection.
+    #[serde(default = "two")]
+    pub retries: u32,
+}
+
+fn report() -> String {
+    "report".into()
+}
+
+fn five_seconds() -> u64 {
+    5000
+}
+
+fn second() -> u64 {
+    1000
+}
+
+fn two() -> u32 {
+    2
+}
+
+impl PollConfig {
+    pub fn new(device: impl Into<String>, address: impl Into<String>) -> Self {
+        Self {
+            device: device.into(),
+            address: address.into(),
+            query: report(),
+            interval_ms: five_seconds(),
+            timeout_ms: second(),
+            retries: two(),
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.max(1))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// Connection to a polled device, kept open between queries.
+#[derive(Debug)]
+pub struct Poller {
+    config: PollConfig,
+    stream: Option<BufReader<TcpStream>>,
+}
+
+impl Poller {
+    pub fn new(config: PollConfig) -> Self {
+        Self {
+            config,
+            stream: None,
+        }
+    }
+
+    pub fn config(&self) -> &PollConfig {
+        &self.config
+    }
+
+    /// Sends the query and parses the reply, reconnecting and trying again
+    /// [`PollConfig::retries`] times when the device fails to answer in time.
+    pub async fn query(&mut self) -> anyhow::Result<Stamped<SensorData>> {
+        let query = self.config.query.clone();
+        self.request(&query).await
+    }
+
+    /// Switches a socket on or off or sets its power, acting as its controller.
+    /// Returns the state the socket reports afterwards.
+    pub async fn command(&mut self, command: SocketCommand) -> anyhow::Result<Stamped<SensorData>> {
+        self.request(&command.to_string()).await
+    }
+
+    async fn request(&mut self, request: &str) -> anyhow::Result<Stamped<SensorData>> {
+        let mut error = anyhow!("not sent");
+
+        for _ in 0..=self.config.retries {
+            match tokio::time::timeout(self.config.timeout(), self.exchange(request)).await {
+                Ok(Ok(reading)) => return Ok(reading),
+                Ok(Err(e)) => error = e,
+                Err(_) => error = anyhow!("no reply within {} ms", self.config.timeout_ms),
+            }
+            self.stream = None;
+        }
+
+        Err(error.context(format!(
+            "{} at {} did not answer {:?}",
+            self.config.device, self.config.address, request
+        )))
+    }
+
+    async fn exchange(&mut self, request: &str) -> anyhow::Result<Stamped<SensorData>> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let tcp = TcpStream::connect(&self.config.address)
+                    .await
+                    .with_context(|| format!("cannot connect to {}", self.config.address))?;
+                self.stream.insert(BufReader::new(tcp))
+            }
+        };
+
+        stream
+            .get_mut()
+            .write_all(format!("{}\n", request).as_bytes())
+            .await?;
+
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            anyhow::bail!("connection closed by {}", self.config.address);
+        }
+
+        let (_, reply) = protocol::address(&line);
+        reply
+            .parse()
+            .map_err(|e| anyhow!("bad reply {:?}: {}", line.trim(), e))
+    }
+}
+
+/// Queries the device every [`PollConfig::interval_ms`] and ingests the replies
+/// the same way as pushed readings. Failed polls are reported as rejected.
+#[tracing::instrument(name = "poll", skip_all, fields(device = %config.device, address = %config.address))]
+pub async fn poll(config: PollConfig, state: Arc<Mutex<State>>, tx: Sender<Arc<DeviceEvent>>) {
+    let mut interval = tokio::time::interval(config.interval());
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut poller = Poller::new(config);
+
+    loop {
+        interval.tick().await;
+
+        let event = match poller.query().await {
+            Ok(stamped) => {
+                let reading = Reading::new(stamped.message, stamped.time)
+                    .with_device(Some(poller.config().device.clone()));
+
+                let mut state = state.lock().unwrap();
+                if state.filter(&reading).is_some() {
+                    continue;
+                }
+
+                store(&mut state, reading)
+            }
+            Err(e) => {
+                tracing::warn!(error = format!("{:#}", e), "poll failed");
+                DeviceEvent::Rejected {
+                    message: poller.config().query.clone(),
+                    reason: format!("{:#}", e),
+                }
+            }
+        };
+
+        if tx.send(Arc::new(event)).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Stores a reading a polled device answered with and returns the event to
+/// report: the reading with the value that was kept, or the rejection.
+pub fn store(state: &mut State, mut reading: Reading) -> DeviceEvent {
+    match state.apply(&mut reading) {
+        Reply::Nack(reason) => DeviceEvent::Rejected {
+            message: format!(
+                "{} {}",
+                reading.device.as_deref().unwrap_or_default(),
+                reading.data
+            ),
+            reason,
+        },
+        Reply::Ack(v) => {
+            reading.data = reading.data.with_value(v);
+            DeviceEvent::Reading(reading)
+        }
+        _ => DeviceEvent::Reading(reading),
+    }
+}
@@ -17,11 +17,15 @@ impl Power {
     }
 
     pub fn set(&mut self, value: f32) {
-        if (Self::MIN_POWER..=Self::MAX_POWER).contains(&value) {
+        if Self::is_valid(value) {
             self.0 = value
         }
     }
 
+    pub fn is_valid(value: f32) -> bool {
+        (Self::MIN_POWER..=Self::MAX_POWER).contains(&value)
+    }
+
     pub fn ratio(power: f32) -> f32 {
         if power >= Self::MIN_POWER {
             return (power - Self::MIN_POWER) / (Self::MAX_POWER - Self::MIN_POWER);
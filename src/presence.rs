@@ -0,0 +1,92 @@
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use tokio::sync::mpsc::Sender;
+
+use crate::{event::DeviceEvent, state::State};
+
+/// Whether a device is still reporting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Presence {
+    Online,
+    /// Silent for longer than [`PresenceConfig::stale_s`], the value may be outdated.
+    Stale,
+    /// Silent for longer than [`PresenceConfig::offline_s`] or never heard of.
+    Offline,
+}
+
+impl Display for Presence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Presence::Online => write!(f, "online"),
+            Presence::Stale => write!(f, "stale"),
+            Presence::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// Staleness timeouts, the `[presence]` table of the server config or the
+/// `presence` of a single device:
+///
+/// ```toml
+/// [presence]
+/// stale_s = 60
+/// offline_s = 300
+///
+/// [devices."kitchen/termometer"]
+/// presence = { stale_s = 10, offline_s = 30 }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PresenceConfig {
+    pub stale_s: u64,
+    pub offline_s: u64,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            stale_s: 60,
+            offline_s: 300,
+        }
+    }
+}
+
+impl PresenceConfig {
+    /// Presence at `now` of a device last heard of at `last_seen`.
+    pub fn presence(&self, last_seen: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Presence {
+        let Some(last_seen) = last_seen else {
+            return Presence::Offline;
+        };
+        let silent = now - last_seen;
+
+        if silent > TimeDelta::seconds(self.offline_s as i64) {
+            Presence::Offline
+        } else if silent > TimeDelta::seconds(self.stale_s as i64) {
+            Presence::Stale
+        } else {
+            Presence::Online
+        }
+    }
+}
+
+/// Checks the presence of every device each `period` and reports the changes.
+pub async fn watch(state: Arc<Mutex<State>>, tx: Sender<Arc<DeviceEvent>>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    loop {
+        interval.tick().await;
+
+        let changes = state.lock().unwrap().presence_changes(Utc::now());
+        for event in changes {
+            if tx.send(Arc::new(event)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
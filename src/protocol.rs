@@ -0,0 +1,82 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use crate::{reading::Stamped, report::Format, sensor_data::SensorData};
+
+/// Frame sent by a device: one line of text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// Single reading, e.g. `Termometer 21.5 C @2025-04-01T10:00:00Z`.
+    Reading(Stamped<SensorData>),
+    /// Readings buffered by a device, separated by `;`, e.g.
+    /// `Batch Termometer 21 C @2025-04-01T10:00:00Z; Socket 1500 W @2025-04-01T10:00:05Z`.
+    Batch(Vec<Stamped<SensorData>>),
+    /// Request for the status report of the house, e.g. `Report text`, in the
+    /// default [`Format`] when none is given.
+    Report(Format),
+    /// Sign of life from a device with nothing new to report, e.g.
+    /// `kitchen/termometer Heartbeat`. Standalone devices name their kind:
+    /// `Heartbeat Termometer`.
+    Heartbeat(Option<String>),
+}
+
+/// Splits off the `<room>/<device>` address a frame may start with, e.g.
+/// `kitchen/termometer Termometer 21.5 C`.
+pub fn address(frame: &str) -> (Option<&str>, &str) {
+    let frame = frame.trim_start();
+
+    match frame.split_once(char::is_whitespace) {
+        Some((first, rest)) if first.contains('/') => (Some(first), rest.trim_start()),
+        _ => (None, frame),
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::Reading(reading) => write!(f, "{}", reading),
+            Message::Batch(readings) => {
+                let readings: Vec<String> = readings.iter().map(|r| r.to_string()).collect();
+                write!(f, "Batch {}", readings.join("; "))
+            }
+            Message::Report(format) => write!(f, "Report {}", format),
+            Message::Heartbeat(Some(kind)) => write!(f, "Heartbeat {}", kind),
+            Message::Heartbeat(None) => write!(f, "Heartbeat"),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(kind) = s.strip_prefix("Heartbeat") {
+            return match kind.trim() {
+                "" => Ok(Self::Heartbeat(None)),
+                kind => Ok(Self::Heartbeat(Some(kind.to_string()))),
+            };
+        }
+
+        if let Some(format) = s.strip_prefix("Report") {
+            return match format.trim() {
+                "" => Ok(Self::Report(Format::default())),
+                format => Ok(Self::Report(format.parse()?)),
+            };
+        }
+
+        match s.strip_prefix("Batch") {
+            Some(batch) => {
+                let mut readings = vec![];
+                for (i, reading) in batch.split(';').enumerate() {
+                    let reading = Stamped::from_str(reading)
+                        .map_err(|e| format!("reading {}: {}", i + 1, e))?;
+                    readings.push(reading);
+                }
+
+                Ok(Self::Batch(readings))
+            }
+            None => Ok(Self::Reading(Stamped::from_str(s)?)),
+        }
+    }
+}
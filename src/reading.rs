@@ -0,0 +1,101 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::sensor_data::SensorData;
+
+/// Device message optionally followed by the device clock, e.g.
+/// `Termometer 21.5 C @2025-04-01T10:00:00Z`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamped<T> {
+    pub message: T,
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl<T> Stamped<T> {
+    pub fn new(message: T, time: Option<DateTime<Utc>>) -> Self {
+        Self { message, time }
+    }
+
+    /// Stamps the message with the current local clock.
+    pub fn now(message: T) -> Self {
+        Self::new(message, Some(Utc::now()))
+    }
+}
+
+impl<T: Display> Display for Stamped<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.time {
+            Some(time) => write!(f, "{} @{}", self.message, time.to_rfc3339()),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl<T> FromStr for Stamped<T>
+where
+    T: FromStr<Err = Box<dyn Error>>,
+{
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('@') {
+            Some((message, time)) => {
+                let time = DateTime::parse_from_rfc3339(time.trim())
+                    .map_err(|e| format!("cannot parse timestamp: {}", e))?;
+
+                Ok(Self::new(T::from_str(message.trim())?, Some(time.into())))
+            }
+            None => Ok(Self::new(T::from_str(s.trim())?, None)),
+        }
+    }
+}
+
+/// Reading accepted by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// Device the reading came from, when the connection is authenticated.
+    pub device: Option<String>,
+    pub data: SensorData,
+    /// Value as sent by the device, when calibration changed it.
+    pub raw: Option<f32>,
+    /// Time reported by the device, if any.
+    pub device_time: Option<DateTime<Utc>>,
+    /// Time the server received the reading.
+    pub received: DateTime<Utc>,
+}
+
+impl Reading {
+    /// Difference between clocks above which the device is reported as skewed.
+    pub const MAX_SKEW: TimeDelta = TimeDelta::seconds(5);
+
+    pub fn new(data: SensorData, device_time: Option<DateTime<Utc>>) -> Self {
+        Self {
+            device: None,
+            data,
+            raw: None,
+            device_time,
+            received: Utc::now(),
+        }
+    }
+
+    pub fn with_device(mut self, device: Option<String>) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Time the reading was taken: the device clock when known, otherwise receive time.
+    pub fn time(&self) -> DateTime<Utc> {
+        self.device_time.unwrap_or(self.received)
+    }
+
+    /// How far the device clock is ahead of the server one (negative when behind).
+    pub fn skew(&self) -> Option<TimeDelta> {
+        self.device_time.map(|t| t - self.received)
+    }
+
+    /// Returns the skew only when it exceeds [`Reading::MAX_SKEW`].
+    pub fn clock_skew(&self) -> Option<TimeDelta> {
+        self.skew().filter(|s| s.abs() > Self::MAX_SKEW)
+    }
+}
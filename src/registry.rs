@@ -0,0 +1,149 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use serde::Deserialize;
+
+use crate::{auth::Signed, calibration::Calibration, limits::RateLimit, presence::PresenceConfig};
+
+/// Settings of a single device.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DeviceConfig {
+    /// Pre-shared key the device signs its messages with.
+    pub key: Option<String>,
+    /// Correction applied to the readings of a termometer.
+    pub calibration: Option<Calibration>,
+    /// Staleness timeouts of this device.
+    pub presence: Option<PresenceConfig>,
+    /// Messages this device may send.
+    pub rate: Option<RateLimit>,
+    /// Readings changing less than this since the stored value are dropped.
+    pub deadband: Option<f32>,
+}
+
+/// Devices known to the server, with their keys and the last accepted counters.
+#[derive(Debug, Default)]
+pub struct Registry {
+    devices: HashMap<String, DeviceConfig>,
+    counters: HashMap<String, u64>,
+    counter_file: Option<CounterFile>,
+    require_auth: bool,
+}
+
+impl Registry {
+    pub fn new(devices: HashMap<String, DeviceConfig>, require_auth: bool) -> Self {
+        Self {
+            devices,
+            counters: HashMap::new(),
+            counter_file: None,
+            require_auth,
+        }
+    }
+
+    /// Keeps the last accepted counters in a file, so that messages accepted
+    /// before a restart are refused after it too.
+    pub fn with_counters(mut self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let (file, counters) = CounterFile::open(path)?;
+        self.counters = counters;
+        self.counter_file = Some(file);
+        Ok(self)
+    }
+
+    /// Replaces device settings after the config changed, keeping the replay counters.
+    pub fn update(&mut self, devices: HashMap<String, DeviceConfig>, require_auth: bool) {
+        self.devices = devices;
+        self.require_auth = require_auth;
+    }
+
+    pub fn device(&self, id: &str) -> Option<&DeviceConfig> {
+        self.devices.get(id)
+    }
+
+    /// Whether any device holds a key.
+    pub fn keyed(&self) -> bool {
+        self.devices.values().any(|d| d.key.is_some())
+    }
+
+    /// Whether messages without a signature are refused.
+    pub fn require_auth(&self) -> bool {
+        self.require_auth
+    }
+
+    /// Verifies the signature and the counter of a message, remembering the counter.
+    pub fn authenticate(&mut self, signed: &Signed) -> Result<(), String> {
+        let key = self
+            .device(&signed.device)
+            .and_then(|d| d.key.as_deref())
+            .ok_or_else(|| format!("no key for device {}", signed.device))?;
+
+        if !signed.verify(key.as_bytes()) {
+            return Err(format!("bad signature from {}", signed.device));
+        }
+
+        let last = self.counters.entry(signed.device.clone()).or_default();
+        if signed.counter <= *last {
+            return Err(format!(
+                "replayed message from {}: counter {} is not above {}",
+                signed.device, signed.counter, last
+            ));
+        }
+        *last = signed.counter;
+
+        if let Some(file) = self.counter_file.as_mut()
+            && let Err(e) = file.append(&signed.device, signed.counter)
+        {
+            tracing::error!(error = ?e, "cannot write the replay counters");
+        }
+
+        Ok(())
+    }
+}
+
+/// Append-only file of accepted counters, one `<device> <counter>` per line.
+#[derive(Debug)]
+struct CounterFile {
+    file: File,
+}
+
+impl CounterFile {
+    /// Reads the last counter of every device and compacts the file down to them.
+    fn open(path: impl AsRef<Path>) -> std::io::Result<(Self, HashMap<String, u64>)> {
+        let path = path.as_ref();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut counters: HashMap<String, u64> = HashMap::new();
+        for line in text.lines() {
+            let Some((device, counter)) = line.split_once(' ') else {
+                continue;
+            };
+            if let Ok(counter) = counter.parse::<u64>() {
+                let last = counters.entry(device.to_string()).or_default();
+                *last = counter.max(*last);
+            }
+        }
+
+        // Сначала пишем сжатую копию, чтобы сбой не оставил файл пустым
+        let compacted = path.with_extension("tmp");
+        let mut text = String::new();
+        for (device, counter) in &counters {
+            text.push_str(&format!("{} {}\n", device, counter));
+        }
+        std::fs::write(&compacted, text)?;
+        std::fs::rename(&compacted, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok((Self { file }, counters))
+    }
+
+    fn append(&mut self, device: &str, counter: u64) -> std::io::Result<()> {
+        writeln!(self.file, "{} {}", device, counter)?;
+        self.file.flush()
+    }
+}
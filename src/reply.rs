@@ -0,0 +1,95 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use regex::Regex;
+
+/// Server reply sent back for every message received from a device.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    /// Message accepted. Carries the value the server actually stored, which
+    /// differs from the sent one when it did not pass the range check.
+    Ack(f32),
+    /// Batch accepted as a whole. Carries the number of stored readings.
+    AckBatch(usize),
+    /// Message rejected for good, sending it again will not help. Carries the reason.
+    Nack(String),
+    /// Message not taken for now, by a rate limit or a busy server: it may be
+    /// sent again later. Carries the reason.
+    Busy(String),
+    /// Answer to a report request: `Report <number of lines>` followed by the lines.
+    Report(String),
+}
+
+impl Reply {
+    /// Number of lines following the first one of a reply, see [`Reply::Report`].
+    pub fn continuation(first_line: &str) -> usize {
+        first_line
+            .trim()
+            .strip_prefix("Report ")
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0)
+    }
+
+    pub fn is_ack(&self) -> bool {
+        matches!(self, Reply::Ack(_) | Reply::AckBatch(_))
+    }
+}
+
+impl Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reply::Ack(value) => write!(f, "Ack {}", value),
+            Reply::AckBatch(count) => write!(f, "AckBatch {}", count),
+            Reply::Nack(reason) => write!(f, "Nack {}", reason),
+            Reply::Busy(reason) => write!(f, "Busy {}", reason),
+            Reply::Report(report) => {
+                let report = report.trim_end();
+                write!(f, "Report {}\n{}", report.lines().count(), report)
+            }
+        }
+    }
+}
+
+impl FromStr for Reply {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(report) = s.strip_prefix("Report ") {
+            let (count, report) = report.split_once('\n').unwrap_or((report, ""));
+            let count: usize = count.trim().parse()?;
+            let lines: Vec<&str> = report.lines().collect();
+            if lines.len() != count {
+                return Err(format!("expected {} report lines, got {}", count, lines.len()).into());
+            }
+
+            return Ok(Self::Report(lines.join("\n")));
+        }
+
+        let re = Regex::new(r"^(AckBatch|Ack|Nack|Busy)(\s)+(.*)$").unwrap();
+
+        match re.captures(s.trim()) {
+            Some(caps) => {
+                if &caps[1] == "Nack" {
+                    return Ok(Self::Nack(caps[3].to_string()));
+                }
+
+                if &caps[1] == "Busy" {
+                    return Ok(Self::Busy(caps[3].to_string()));
+                }
+
+                if &caps[1] == "AckBatch" {
+                    return match caps[3].trim().parse::<usize>() {
+                        Ok(count) => Ok(Self::AckBatch(count)),
+                        Err(_) => Err("cannot parse count from string".into()),
+                    };
+                }
+
+                if let Ok(v) = caps[3].trim().parse::<f32>() {
+                    return Ok(Self::Ack(v));
+                }
+
+                Err("cannot parse float from string".into())
+            }
+            None => Err("does not look like reply from server".into()),
+        }
+    }
+}
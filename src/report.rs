@@ -0,0 +1,351 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{reading::Reading, sensor_data::SensorData, state::State};
+
+/// Output format of a [`Report`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Text,
+    #[default]
+    Markdown,
+    Json,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Text => write!(f, "text"),
+            Format::Markdown => write!(f, "markdown"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "markdown" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown report format {:?}", s)),
+        }
+    }
+}
+
+/// Report written on a schedule, the `[report]` table of the server config:
+///
+/// ```toml
+/// [report]
+/// path = "status.md"
+/// format = "markdown"
+/// every_s = 3600
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ReportConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(default = "hour")]
+    pub every_s: u64,
+}
+
+fn hour() -> u64 {
+    3600
+}
+
+/// Status of every device: the rooms of the house or, without a house, the
+/// standalone devices.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub house: String,
+    pub generated: DateTime<Utc>,
+    pub rooms: Vec<RoomReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RoomReport {
+    pub name: String,
+    pub devices: Vec<DeviceReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceReport {
+    pub id: String,
+    pub kind: &'static str,
+    pub value: f32,
+    pub unit: &'static str,
+    /// Relay state of sockets.
+    pub on: Option<bool>,
+    /// When the server last heard from the device.
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Readings refused for being out of range.
+    pub violations: u64,
+    /// The last value out of range.
+    pub last_violation: Option<f32>,
+    /// Energy drawn by sockets over the kept history, watt-hours.
+    pub energy_wh: Option<f64>,
+}
+
+impl Report {
+    /// Walks every device of the state as of `now`.
+    pub fn new(state: &State, now: DateTime<Utc>) -> Self {
+        let mut rooms = vec![];
+
+        for room in state.house().rooms() {
+            let devices = room
+                .devices()
+                .map(|device| {
+                    let path = format!("{}/{}", room.name(), device.id());
+                    let history = state
+                        .history()
+                        .iter()
+                        .filter(|r| r.device.as_deref() == Some(path.as_str()))
+                        .collect();
+                    DeviceReport::new(state, &path, device.id(), device.data(), history, now)
+                })
+                .collect();
+
+            rooms.push(RoomReport {
+                name: room.name().to_string(),
+                devices,
+            });
+        }
+
+        if state.house().is_empty() {
+            let standalone = |r: &&Reading| r.device.as_deref().is_none_or(|d| !d.contains('/'));
+            let devices = state
+                .devices()
+                .iter()
+                .filter_map(|kind| state.standalone(kind))
+                .map(|device| {
+                    let history = state
+                        .history()
+                        .iter()
+                        .filter(standalone)
+                        .filter(|r| r.data.kind() == device.kind())
+                        .collect();
+                    DeviceReport::new(
+                        state,
+                        device.kind(),
+                        device.id(),
+                        device.data(),
+                        history,
+                        now,
+                    )
+                })
+                .collect();
+
+            rooms.push(RoomReport {
+                name: "standalone".into(),
+                devices,
+            });
+        }
+
+        Self {
+            house: state.house().name().to_string(),
+            generated: now,
+            rooms,
+        }
+    }
+
+    /// Energy drawn by every socket, watt-hours.
+    pub fn energy_wh(&self) -> f64 {
+        self.devices().filter_map(|d| d.energy_wh).sum()
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceReport> {
+        self.rooms.iter().flat_map(|r| r.devices.iter())
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.text(),
+            Format::Markdown => self.markdown(),
+            Format::Json => serde_json::to_string_pretty(self).unwrap_or_default(),
+        }
+    }
+
+    /// Writes the report to a file, replacing it as a whole.
+    pub fn write(&self, path: impl AsRef<Path>, format: Format) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, self.render(format))?;
+
+        std::fs::rename(tmp, path)
+    }
+
+    fn title(&self) -> String {
+        let generated = self
+            .generated
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S");
+        match self.house.is_empty() {
+            true => format!("Status at {}", generated),
+            false => format!("{} status at {}", self.house, generated),
+        }
+    }
+
+    fn text(&self) -> String {
+        let mut out = format!("{}\n", self.title());
+
+        for room in &self.rooms {
+            let _ = writeln!(out, "\n{}:", room.name);
+            for d in &room.devices {
+                let _ = writeln!(
+                    out,
+                    "  {} ({}): {}, last seen {}, {}{}",
+                    d.id,
+                    d.kind,
+                    d.value(),
+                    d.last_seen(),
+                    d.violations(),
+                    d.energy().map(|e| format!(", {}", e)).unwrap_or_default()
+                );
+            }
+        }
+
+        let _ = writeln!(out, "\nEnergy total: {:.1} Wh", self.energy_wh());
+        out
+    }
+
+    fn markdown(&self) -> String {
+        let mut out = format!("# {}\n", self.title());
+
+        for room in &self.rooms {
+            let _ = writeln!(out, "\n## {}\n", room.name);
+            let _ = writeln!(
+                out,
+                "| Device | Kind | Value | Last seen | Violations | Energy |"
+            );
+            let _ = writeln!(out, "|---|---|---|---|---|---|");
+            for d in &room.devices {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} | {} |",
+                    d.id,
+                    d.kind,
+                    d.value(),
+                    d.last_seen(),
+                    d.violations(),
+                    d.energy().unwrap_or_default()
+                );
+            }
+        }
+
+        let _ = writeln!(out, "\n**Energy total:** {:.1} Wh", self.energy_wh());
+        out
+    }
+}
+
+impl DeviceReport {
+    /// `history` holds the readings of this very device.
+    fn new(
+        state: &State,
+        key: &str,
+        id: &str,
+        data: SensorData,
+        history: Vec<&Reading>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let on = match data {
+            SensorData::Socket(socket) => Some(socket.on),
+            _ => None,
+        };
+        let energy_wh = matches!(data, SensorData::Socket(_)).then(|| energy_wh(&history, now));
+        let violations = state.violations(key);
+
+        Self {
+            id: id.to_string(),
+            kind: data.kind(),
+            value: data.value().unwrap_or_default(),
+            unit: data.unit(),
+            on,
+            last_seen: history
+                .iter()
+                .map(|r| r.received)
+                .max()
+                .max(state.last_seen(key)),
+            violations: violations.map_or(0, |v| v.count),
+            last_violation: violations.map(|v| v.last),
+            energy_wh,
+        }
+    }
+
+    fn value(&self) -> String {
+        match self.on {
+            Some(false) => "off".into(),
+            _ => format!("{} {}", self.value, self.unit),
+        }
+    }
+
+    fn last_seen(&self) -> String {
+        match self.last_seen {
+            Some(time) => time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            None => "never".into(),
+        }
+    }
+
+    fn violations(&self) -> String {
+        match self.last_violation {
+            Some(last) => format!("{} out of range (last {})", self.violations, last),
+            None => "in range".into(),
+        }
+    }
+
+    fn energy(&self) -> Option<String> {
+        self.energy_wh.map(|e| format!("{:.1} Wh", e))
+    }
+}
+
+/// Power held from every socket reading to the next one, and from the last one
+/// to `now`, in watt-hours.
+fn energy_wh(history: &[&Reading], now: DateTime<Utc>) -> f64 {
+    let mut readings: Vec<(DateTime<Utc>, f32)> = history
+        .iter()
+        .filter_map(|r| match r.data {
+            SensorData::Socket(socket) if socket.on => Some((r.time(), socket.power)),
+            SensorData::Socket(_) => Some((r.time(), 0.0)),
+            _ => None,
+        })
+        .collect();
+    readings.sort_by_key(|(time, _)| *time);
+
+    let ends = readings.iter().skip(1).map(|(time, _)| *time).chain([now]);
+    readings
+        .iter()
+        .zip(ends)
+        .map(|((start, power), end)| {
+            let hours = (end - *start).num_milliseconds().max(0) as f64 / 3_600_000.0;
+            *power as f64 * hours
+        })
+        .sum()
+}
+
+/// Writes the report every [`ReportConfig::every_s`] until the task is dropped.
+pub async fn write_every(state: Arc<Mutex<State>>, config: ReportConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.every_s.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        let report = Report::new(&state.lock().unwrap(), Utc::now());
+        if let Err(e) = report.write(&config.path, config.format) {
+            tracing::error!(path = %config.path.display(), error = ?e, "cannot write the report");
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, de::Error};
+
+use crate::{
+    power::Power,
+    socket::{Socket, SocketCommand},
+    temperature::Temperature,
+    termometer::Termometer,
+};
+
+/// A room heated by a socket heater, read from the simulator config:
+///
+/// ```toml
+/// [[rooms]]
+/// termometer = "living-room"
+/// heater = "living-heater"
+/// interval_ms = 1000
+/// speed = 60.0          # simulated seconds per real second
+/// thermal_mass = 2.0e5  # J/°C
+/// loss = 40.0           # W/°C through the walls
+/// outdoor = 5.0         # °C
+/// initial = 18.0        # °C
+/// heater_power = 1500.0 # W
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoomConfig {
+    /// Id of the termometer measuring the room.
+    pub termometer: String,
+    /// Id of the socket feeding the heater.
+    pub heater: String,
+    #[serde(default = "second")]
+    pub interval_ms: u64,
+    #[serde(default = "real_time")]
+    pub speed: f64,
+    /// Heat capacity of the room, above zero.
+    #[serde(deserialize_with = "positive")]
+    pub thermal_mass: f64,
+    /// Heat lost through the walls per degree of difference, zero or above.
+    #[serde(deserialize_with = "non_negative")]
+    pub loss: f64,
+    pub outdoor: f64,
+    pub initial: f64,
+    /// Rated power of the heater, within the range of a socket.
+    #[serde(deserialize_with = "rated")]
+    pub heater_power: f32,
+    /// Pre-shared key of both devices.
+    pub key: Option<String>,
+}
+
+fn second() -> u64 {
+    1000
+}
+
+fn real_time() -> f64 {
+    1.0
+}
+
+fn rated<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let power = f32::deserialize(deserializer)?;
+    if !Power::is_valid(power) {
+        return Err(D::Error::custom(format!(
+            "heater power {} W is out of {}..={} W",
+            power,
+            Power::MIN_POWER,
+            Power::MAX_POWER
+        )));
+    }
+
+    Ok(power)
+}
+
+fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if !(value.is_finite() && value > 0.0) {
+        return Err(D::Error::custom(format!("{} must be above zero", value)));
+    }
+
+    Ok(value)
+}
+
+fn non_negative<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if !(value.is_finite() && value >= 0.0) {
+        return Err(D::Error::custom(format!(
+            "{} must not be below zero",
+            value
+        )));
+    }
+
+    Ok(value)
+}
+
+/// Thermal model of a room: one lumped heat capacity warmed by the heater and
+/// losing heat to the outside in proportion to the temperature difference.
+#[derive(Debug)]
+pub struct Room {
+    config: RoomConfig,
+    temperature: f64,
+    heater: Socket,
+}
+
+impl Room {
+    /// The room at its initial temperature with the heater switched off.
+    pub fn new(config: RoomConfig) -> Self {
+        let heater = Socket::off().with_id(&config.heater);
+
+        Self {
+            temperature: config.initial,
+            heater,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &RoomConfig {
+        &self.config
+    }
+
+    /// Air temperature, °C.
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn heater(&self) -> &Socket {
+        &self.heater
+    }
+
+    /// Termometer reporting the current temperature of the room.
+    pub fn termometer(&self) -> Termometer {
+        Termometer::new(Temperature::new(self.temperature as f32)).with_id(&self.config.termometer)
+    }
+
+    /// Passes a command to the heater socket.
+    ///
+    /// Switching on restores the rated power of the heater unless another
+    /// power was set.
+    pub fn command(&mut self, command: SocketCommand) {
+        self.heater.execute(command);
+        if command == SocketCommand::On && self.heater.power().get() == 0.0 {
+            self.heater.power_mut().set(self.config.heater_power);
+        }
+    }
+
+    /// Advances the simulation by `dt` of simulated time.
+    ///
+    /// Uses the exact solution for a constant heater power, so any step is stable:
+    /// the temperature approaches `outdoor + power / loss` with the time constant
+    /// `thermal_mass / loss`.
+    pub fn step(&mut self, dt: Duration) {
+        let power = match self.heater.is_on() {
+            true => self.heater.power().get() as f64,
+            false => 0.0,
+        };
+
+        if self.config.loss <= 0.0 {
+            self.temperature += power * dt.as_secs_f64() / self.config.thermal_mass;
+            return;
+        }
+
+        let steady = self.config.outdoor + power / self.config.loss;
+        let decay = (-self.config.loss * dt.as_secs_f64() / self.config.thermal_mass).exp();
+        self.temperature = steady + (self.temperature - steady) * decay;
+    }
+}
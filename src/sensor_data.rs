@@ -0,0 +1,109 @@
+use std::{error::Error, fmt::Display, str::FromStr};
+
+use crate::{
+    device::{self, Device},
+    humidity::Humidity,
+    hygrometer::Hygrometer,
+    power::Power,
+    socket::{Socket, SocketReading},
+    temperature::Temperature,
+    termometer::Termometer,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SensorData {
+    Temperature(f32),
+    Socket(SocketReading),
+    Humidity(f32),
+    /// Reading of a [`device::register`]ed kind without a variant of its own.
+    Measurement {
+        kind: &'static str,
+        value: f32,
+    },
+    Unknown,
+}
+
+impl SensorData {
+    /// Whether the value fits the range of the device.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            SensorData::Socket(s) => s.is_valid(),
+            data => match (device::kind(data.kind()), data.value()) {
+                (Some(kind), Some(value)) => kind.range().contains(&value),
+                _ => false,
+            },
+        }
+    }
+
+    /// [`Device::KIND`] of the device sending this kind of reading.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SensorData::Temperature(_) => Termometer::KIND,
+            SensorData::Socket(_) => Socket::KIND,
+            SensorData::Humidity(_) => Hygrometer::KIND,
+            SensorData::Measurement { kind, .. } => kind,
+            SensorData::Unknown => "Unknown",
+        }
+    }
+
+    /// [`Device::UNIT`] of the value.
+    pub fn unit(&self) -> &'static str {
+        device::kind(self.kind()).map_or("", |kind| kind.unit)
+    }
+
+    /// Smallest change of the value the device can tell.
+    pub fn graduation(&self) -> f32 {
+        device::kind(self.kind()).map_or(0.0, |kind| kind.graduation)
+    }
+
+    /// Value carried by the reading.
+    pub fn value(&self) -> Option<f32> {
+        match *self {
+            SensorData::Temperature(v) | SensorData::Humidity(v) => Some(v),
+            SensorData::Socket(s) => Some(s.power),
+            SensorData::Measurement { value, .. } => Some(value),
+            SensorData::Unknown => None,
+        }
+    }
+
+    /// Same kind of reading with another value.
+    pub fn with_value(&self, value: f32) -> Self {
+        match *self {
+            SensorData::Temperature(_) => SensorData::Temperature(value),
+            SensorData::Socket(s) => SensorData::Socket(SocketReading { power: value, ..s }),
+            SensorData::Humidity(_) => SensorData::Humidity(value),
+            SensorData::Measurement { kind, .. } => SensorData::Measurement { kind, value },
+            SensorData::Unknown => SensorData::Unknown,
+        }
+    }
+}
+
+impl Display for SensorData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            SensorData::Temperature(t) => write!(f, "{}", Termometer::new(Temperature::new(t))),
+            SensorData::Socket(s) => {
+                let socket = match s.on {
+                    true => Socket::on(Power::new(s.power)),
+                    false => Socket::off(),
+                };
+                write!(f, "{}", socket.with_telemetry(s.telemetry))
+            }
+            SensorData::Humidity(h) => write!(f, "{}", Hygrometer::new(Humidity::new(h))),
+            SensorData::Measurement { kind, value } => match device::kind(kind) {
+                Some(kind) => write!(f, "{}", kind.format(value)),
+                None => write!(f, "{} {}", kind, value),
+            },
+            SensorData::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+impl FromStr for SensorData {
+    type Err = Box<dyn Error>;
+
+    /// Parses a message of any kind [`device::register`]ed with the server.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        device::parse(s)
+    }
+}
@@ -0,0 +1,513 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
+    },
+    net::{TcpListener, UdpSocket},
+    sync::mpsc::Sender,
+};
+use tracing::Instrument;
+
+use crate::{
+    auth::Signed,
+    device::UnknownKind,
+    event::DeviceEvent,
+    limits::{LimitsConfig, Refusal, TokenBucket},
+    metrics::Dropped,
+    protocol::{self, Message},
+    reading::Reading,
+    reply::Reply,
+    report::Report,
+    state::State,
+    tls::TlsServer,
+};
+
+/// Time to tell a refused client why before giving up on it.
+const REFUSAL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Accepts device connections forever, serving each one in its own task.
+///
+/// Connections over the [`LimitsConfig`] of the state are answered with a
+/// `Busy` telling why and closed.
+pub async fn serve(
+    listener: TcpListener,
+    state: Arc<Mutex<State>>,
+    tx: Sender<Arc<DeviceEvent>>,
+) -> anyhow::Result<()> {
+    loop {
+        let (tcp, peer) = listener.accept().await?;
+
+        let admitted = match Admitted::new(&state, peer) {
+            Ok(admitted) => admitted,
+            Err(refusal) => {
+                tokio::spawn(refuse(tcp, refusal));
+                continue;
+            }
+        };
+
+        let state = Arc::clone(&state);
+        let tx = tx.clone();
+        let span = connection_span(peer);
+        tokio::spawn(
+            async move {
+                tracing::debug!("connected");
+                if let Err(e) = handle_connection(tcp, None, state, tx).await {
+                    tracing::warn!(error = ?e, "connection failed");
+                }
+                tracing::debug!("disconnected");
+                drop(admitted);
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Same as [`serve`] but over TLS. Readings from a client with a certificate
+/// are attributed to the device the certificate names.
+pub async fn serve_tls(
+    listener: TcpListener,
+    tls: TlsServer,
+    state: Arc<Mutex<State>>,
+    tx: Sender<Arc<DeviceEvent>>,
+) -> anyhow::Result<()> {
+    loop {
+        let (tcp, peer) = listener.accept().await?;
+
+        let admitted = Admitted::new(&state, peer);
+        let timeout = state.lock().unwrap().limits().read_timeout();
+        let tls = tls.clone();
+        let state = Arc::clone(&state);
+        let tx = tx.clone();
+        let span = connection_span(peer);
+        tokio::spawn(
+            async move {
+                let stream = match tokio::time::timeout(timeout, tls.acceptor().accept(tcp)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        tracing::warn!(error = ?e, "TLS handshake failed");
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::warn!("TLS handshake timed out");
+                        return;
+                    }
+                };
+
+                let admitted = match admitted {
+                    Ok(admitted) => admitted,
+                    Err(refusal) => return refuse(stream, refusal).await,
+                };
+
+                let device = tls.device_id(stream.get_ref().1.peer_certificates());
+                if let Some(device) = &device {
+                    tracing::Span::current().record("device", device.as_str());
+                }
+                tracing::debug!("connected");
+                if let Err(e) = handle_connection(stream, device, state, tx).await {
+                    tracing::warn!(error = ?e, "connection failed");
+                }
+                tracing::debug!("disconnected");
+                drop(admitted);
+            }
+            .instrument(span),
+        );
+    }
+}
+
+/// Span of a TCP or TLS connection; the device id is recorded once known.
+fn connection_span(peer: SocketAddr) -> tracing::Span {
+    tracing::info_span!("connection", %peer, device = tracing::field::Empty)
+}
+
+/// Connection counted against the limits until dropped.
+struct Admitted {
+    ip: IpAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl Admitted {
+    fn new(state: &Arc<Mutex<State>>, peer: SocketAddr) -> Result<Self, Refusal> {
+        if let Err(refusal) = state.lock().unwrap().connect(peer.ip()) {
+            tracing::warn!(%peer, %refusal, "connection refused");
+            return Err(refusal);
+        }
+
+        Ok(Self {
+            ip: peer.ip(),
+            state: Arc::clone(state),
+        })
+    }
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.disconnect(self.ip);
+        }
+    }
+}
+
+/// Tells the client why it is refused and closes the connection.
+async fn refuse<S: AsyncWrite + Unpin>(mut stream: S, refusal: Refusal) {
+    let reply = format!("{}\n", refusal.reply());
+
+    let _ = tokio::time::timeout(REFUSAL_TIMEOUT, async {
+        stream.write_all(reply.as_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await;
+}
+
+/// Serves devices sending datagrams: every datagram holds one or more newline
+/// separated messages and is answered with a datagram of their replies.
+pub async fn serve_udp(
+    socket: UdpSocket,
+    state: Arc<Mutex<State>>,
+    tx: Sender<Arc<DeviceEvent>>,
+) -> anyhow::Result<()> {
+    let mut buf = vec![0; 65_536];
+
+    loop {
+        // Ошибка одной датаграммы не должна останавливать приём остальных
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!(error = ?e, "cannot receive over UDP");
+                continue;
+            }
+        };
+        let datagram = String::from_utf8_lossy(&buf[..len]);
+
+        let max_frame = state.lock().unwrap().limits().max_frame;
+        let mut response = String::new();
+        for recieved in datagram.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if recieved.len() > max_frame {
+                let refusal = Refusal::FrameTooLarge(max_frame);
+                state.lock().unwrap().metrics_mut().refuse(refusal);
+                response.push_str(&format!("{}\n", refusal.reply()));
+                continue;
+            }
+
+            let (reply, event) = process(&state, None, recieved);
+
+            if let Some(event) = event
+                && let Err(send_err) = tx.send(Arc::new(event)).await
+            {
+                tracing::error!(error = ?send_err, "cannot pass the event to the UI");
+            }
+            response.push_str(&format!("{}\n", reply));
+        }
+
+        if !response.is_empty()
+            && let Err(e) = socket.send_to(response.as_bytes(), peer).await
+        {
+            tracing::warn!(%peer, error = ?e, "cannot reply over UDP");
+        }
+    }
+}
+
+/// Reads newline separated messages and answers every one of them with a [`Reply`].
+///
+/// Connections silent for too long, too slow to complete a frame or sending
+/// frames over the size limit are told why and closed.
+pub async fn handle_connection<S>(
+    socket: S,
+    device: Option<String>,
+    state: Arc<Mutex<State>>,
+    tx: Sender<Arc<DeviceEvent>>,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let limits = *state.lock().unwrap().limits();
+    let mut bucket = limits
+        .connection_rate
+        .map(|rate| TokenBucket::new(rate, Instant::now()));
+    let mut reader = BufReader::new(socket);
+    // Устройство соединения пишется в его span один раз
+    let mut recorded = device.is_some();
+
+    loop {
+        let recieved = match read_frame(&mut reader, &limits).await? {
+            Ok(Some(recieved)) => recieved,
+            Ok(None) => break,
+            Err(refusal) => {
+                tracing::warn!(%refusal, "connection cut off");
+                state.lock().unwrap().metrics_mut().refuse(refusal);
+                refuse(reader.get_mut(), refusal).await;
+                break;
+            }
+        };
+        let recieved = recieved.trim();
+        if recieved.is_empty() {
+            continue;
+        }
+
+        let throttled = bucket.as_mut().is_some_and(|b| !b.take(Instant::now()));
+        let (reply, event) = match throttled {
+            true => {
+                state
+                    .lock()
+                    .unwrap()
+                    .metrics_mut()
+                    .drop(Dropped::ConnectionRate);
+                (Reply::Busy("connection rate limit exceeded".into()), None)
+            }
+            false => {
+                let (reply, event, sender) = process_from(&state, device.as_deref(), recieved);
+                if !recorded && let Some(sender) = &sender {
+                    tracing::Span::current().record("device", sender.as_str());
+                    recorded = true;
+                }
+                (reply, event)
+            }
+        };
+
+        if let Some(event) = event
+            && let Err(send_err) = tx.send(Arc::new(event)).await
+        {
+            tracing::error!(error = ?send_err, "cannot pass the event to the UI");
+        }
+
+        let response = format!("{}\n", reply);
+        reader.get_mut().write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Reads one line within the limits, `None` once the client closed the connection.
+async fn read_frame<R>(
+    reader: &mut R,
+    limits: &LimitsConfig,
+) -> std::io::Result<Result<Option<String>, Refusal>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    match tokio::time::timeout(limits.idle_timeout(), reader.fill_buf()).await {
+        Ok(buf) => {
+            if buf?.is_empty() {
+                return Ok(Ok(None));
+            }
+        }
+        Err(_) => return Ok(Err(Refusal::Idle(limits.idle_timeout()))),
+    }
+
+    // Room for the frame and `\r\n`, anything longer is cut short and refused
+    let mut frame = vec![];
+    let mut limited = (&mut *reader).take(limits.max_frame as u64 + 2);
+    let read = limited.read_until(b'\n', &mut frame);
+    match tokio::time::timeout(limits.read_timeout(), read).await {
+        Ok(read) => read?,
+        Err(_) => return Ok(Err(Refusal::SlowFrame(limits.read_timeout()))),
+    };
+
+    let frame = String::from_utf8_lossy(&frame);
+    let frame = frame.trim_end_matches(['\r', '\n']);
+    if frame.len() > limits.max_frame {
+        return Ok(Err(Refusal::FrameTooLarge(limits.max_frame)));
+    }
+
+    Ok(Ok(Some(frame.to_string())))
+}
+
+/// Parses one frame, applies it to the state and returns the reply with the event
+/// to report, if the frame is worth one: heartbeats are not.
+///
+/// `device` is the id of the sender authenticated by the transport, if known.
+pub fn process(
+    state: &Mutex<State>,
+    device: Option<&str>,
+    recieved: &str,
+) -> (Reply, Option<DeviceEvent>) {
+    let (reply, event, _) = process_from(state, device, recieved);
+    (reply, event)
+}
+
+/// Same as [`process`], also returning the device the frame came from, if known.
+fn process_from(
+    state: &Mutex<State>,
+    device: Option<&str>,
+    recieved: &str,
+) -> (Reply, Option<DeviceEvent>, Option<String>) {
+    let start = Instant::now();
+    let (device, (reply, event)) = match sender(state, device, recieved) {
+        Ok((device, message)) => {
+            let processed = dispatch(state, device.clone(), &message, recieved);
+            (device, processed)
+        }
+        Err(reason) => (None, rejected(recieved, reason)),
+    };
+
+    let mut state = state.lock().unwrap();
+    let metrics = state.metrics_mut();
+    metrics.messages += 1;
+    metrics.latency.observe(start.elapsed());
+
+    (reply, event, device)
+}
+
+fn rejected(recieved: &str, reason: String) -> (Reply, Option<DeviceEvent>) {
+    let event = DeviceEvent::Rejected {
+        message: recieved.to_string(),
+        reason: reason.clone(),
+    };
+    (Reply::Nack(reason), Some(event))
+}
+
+/// Authenticates the frame and returns the device that sent it, if known, with
+/// the message stripped of the signature and the address.
+fn sender(
+    state: &Mutex<State>,
+    device: Option<&str>,
+    recieved: &str,
+) -> Result<(Option<String>, String), String> {
+    let (device, recieved) = authenticate(state, device, recieved).inspect_err(|reason| {
+        tracing::warn!(message = recieved, %reason, "authentication failed");
+    })?;
+
+    let (path, message) = protocol::address(&recieved);
+    match (device, path) {
+        (Some(device), Some(path)) if device != path => {
+            Err(format!("{} cannot report for {}", device, path))
+        }
+        // Устройство с ключом пишет только само, даже если подпись не обязательна
+        (None, Some(path)) if keyed(state, Some(path)) => {
+            Err(format!("{} must sign its messages", path))
+        }
+        // Без адреса показание ложится на общее устройство своего вида, от имени
+        // которого может писать и устройство с ключом
+        (None, None) if keyed(state, None) => {
+            Err("unsigned message, devices here sign theirs".into())
+        }
+        (device, path) => Ok((path.map(str::to_string).or(device), message.to_string())),
+    }
+}
+
+/// Whether the registry holds a key for the device or, for an unaddressed
+/// frame, for any device at all.
+fn keyed(state: &Mutex<State>, device: Option<&str>) -> bool {
+    let state = state.lock().unwrap();
+    let registry = state.registry();
+    match device {
+        Some(device) => registry.device(device).is_some_and(|d| d.key.is_some()),
+        None => registry.keyed(),
+    }
+}
+
+fn dispatch(
+    state: &Mutex<State>,
+    device: Option<String>,
+    message: &str,
+    recieved: &str,
+) -> (Reply, Option<DeviceEvent>) {
+    let rejected = |reason: String| rejected(recieved, reason);
+
+    match Message::from_str(message) {
+        Ok(Message::Reading(stamped)) => {
+            let mut reading =
+                Reading::new(stamped.message, stamped.time).with_device(device.clone());
+            let mut state = state.lock().unwrap();
+            if let Some(reply) = state.filter(&reading) {
+                return (reply, None);
+            }
+            let reply = state.apply(&mut reading);
+
+            match &reply {
+                Reply::Ack(v) => reading.data = reading.data.with_value(*v),
+                Reply::Nack(reason) => return rejected(reason.clone()),
+                Reply::AckBatch(_) | Reply::Busy(_) | Reply::Report(_) => {}
+            }
+
+            (reply, Some(DeviceEvent::Reading(reading)))
+        }
+        Ok(Message::Batch(batch)) => {
+            let mut readings: Vec<Reading> = batch
+                .into_iter()
+                .map(|stamped| {
+                    Reading::new(stamped.message, stamped.time).with_device(device.clone())
+                })
+                .collect();
+
+            let mut state = state.lock().unwrap();
+            if let Some(reply) = state.filter_batch(&mut readings) {
+                return (reply, None);
+            }
+
+            match state.apply_batch(&mut readings) {
+                Reply::Nack(reason) => rejected(reason),
+                reply if readings.is_empty() => (reply, None),
+                reply => (reply, Some(DeviceEvent::Batch(readings))),
+            }
+        }
+        Ok(Message::Heartbeat(kind)) => {
+            let reply = state
+                .lock()
+                .unwrap()
+                .heartbeat(device.as_deref(), kind.as_deref());
+
+            match reply {
+                Reply::Nack(reason) => rejected(reason),
+                reply => (reply, None),
+            }
+        }
+        Ok(Message::Report(format)) => {
+            let report = Report::new(&state.lock().unwrap(), Utc::now());
+            let command = Message::Report(format).to_string();
+
+            (
+                Reply::Report(report.render(format)),
+                Some(DeviceEvent::Command(command)),
+            )
+        }
+        Err(e) => {
+            let mut state = state.lock().unwrap();
+            let metrics = state.metrics_mut();
+            match e.is::<UnknownKind>() {
+                true => metrics.unknown_messages += 1,
+                false => metrics.parse_errors += 1,
+            }
+
+            rejected(e.to_string())
+        }
+    }
+}
+
+/// Unwraps a [`Signed`] frame, returning the device that signed it and the inner message.
+///
+/// Unsigned frames pass through unless the registry requires authentication and
+/// the transport did not authenticate the sender already.
+fn authenticate(
+    state: &Mutex<State>,
+    device: Option<&str>,
+    recieved: &str,
+) -> Result<(Option<String>, String), String> {
+    let mut state = state.lock().unwrap();
+    let registry = state.registry_mut();
+
+    if !recieved.starts_with("Auth") {
+        if device.is_none() && registry.require_auth() {
+            return Err("unauthenticated message".into());
+        }
+
+        return Ok((device.map(str::to_string), recieved.to_string()));
+    }
+
+    let signed = Signed::from_str(recieved).map_err(|e| e.to_string())?;
+    if let Some(device) = device
+        && device != signed.device
+    {
+        return Err(format!("{} cannot sign for {}", device, signed.device));
+    }
+    registry.authenticate(&signed)?;
+
+    Ok((Some(signed.device), signed.message))
+}
@@ -0,0 +1,100 @@
+use std::{f64::consts::TAU, time::Duration};
+
+use rand::Rng;
+use serde::Deserialize;
+
+/// Seconds in a day, the default period of [`SignalModel::Sine`].
+const DAY: f64 = 86_400.0;
+
+fn day() -> f64 {
+    DAY
+}
+
+/// How a simulated reading changes over time.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum SignalModel {
+    /// Starts at `start` and moves by up to `step` each sample, reflecting off the bounds.
+    RandomWalk {
+        start: f32,
+        step: f32,
+        min: Option<f32>,
+        max: Option<f32>,
+    },
+    /// `mean + amplitude * sin(2π (t + phase) / period)`, a daily cycle by default.
+    Sine {
+        mean: f32,
+        amplitude: f32,
+        #[serde(default = "day")]
+        period_s: f64,
+        #[serde(default)]
+        phase_s: f64,
+    },
+    /// Holds each of `levels` for `every_s` seconds, then moves to the next one.
+    Step { levels: Vec<f32>, every_s: f64 },
+    /// Uniform noise of `amplitude` around `mean`.
+    Noise { mean: f32, amplitude: f32 },
+}
+
+/// A running signal: the model plus the state it carries between samples.
+#[derive(Debug, Clone)]
+pub struct Signal {
+    model: SignalModel,
+    value: Option<f32>,
+}
+
+impl Signal {
+    pub fn new(model: SignalModel) -> Self {
+        Self { model, value: None }
+    }
+
+    pub fn model(&self) -> &SignalModel {
+        &self.model
+    }
+
+    /// Value at `t` since the start of the simulation.
+    pub fn sample(&mut self, t: Duration, rng: &mut impl Rng) -> f32 {
+        let t = t.as_secs_f64();
+
+        let value = match &self.model {
+            SignalModel::RandomWalk {
+                start,
+                step,
+                min,
+                max,
+            } => match self.value {
+                None => *start,
+                Some(value) => {
+                    let mut next = value + rng.random_range(-1.0..=1.0) * step;
+                    if let Some(min) = *min
+                        && next < min
+                    {
+                        next = 2.0 * min - next;
+                    }
+                    if let Some(max) = *max
+                        && next > max
+                    {
+                        next = 2.0 * max - next;
+                    }
+                    next
+                }
+            },
+            SignalModel::Sine {
+                mean,
+                amplitude,
+                period_s,
+                phase_s,
+            } => mean + amplitude * (TAU * (t + phase_s) / period_s).sin() as f32,
+            SignalModel::Step { levels, every_s } => match levels.len() {
+                0 => 0.0,
+                n => levels[(t / every_s) as usize % n],
+            },
+            SignalModel::Noise { mean, amplitude } => {
+                mean + rng.random_range(-1.0..=1.0) * amplitude
+            }
+        };
+
+        self.value = Some(value);
+        value
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    ops::RangeInclusive,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use chrono::{TimeDelta, Utc};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::Deserialize;
+
+use crate::{
+    client::Client,
+    device::Device,
+    fault::{Fault, FaultKind},
+    humidity::Humidity,
+    hygrometer::Hygrometer,
+    power::Power,
+    reading::Stamped,
+    room::{Room, RoomConfig},
+    signal::{Signal, SignalModel},
+    socket::Socket,
+    temperature::Temperature,
+    termometer::Termometer,
+};
+
+/// Simulator settings, read from a TOML file:
+///
+/// ```toml
+/// server = "localhost:8080"
+/// seed = 42
+///
+/// [[devices]]
+/// kind = "Termometer"
+/// id = "room"
+/// count = 10
+/// interval_ms = 1000
+/// signal = { model = "sine", mean = 21.0, amplitude = 3.0 }
+/// faults = [{ fault = "spike", magnitude = 10.0, probability = 0.01 }]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SimulatorConfig {
+    pub server: String,
+    /// Seed of every random generator, the same seed replays the same run.
+    pub seed: u64,
+    pub devices: Vec<VirtualDeviceConfig>,
+    /// Heated rooms, see [`RoomConfig`].
+    pub rooms: Vec<RoomConfig>,
+}
+
+impl Default for SimulatorConfig {
+    /// A termometer following the daily cycle, a socket stepping between loads
+    /// and a noisy hygrometer.
+    fn default() -> Self {
+        let device = |kind, id: &str, interval_ms, signal| VirtualDeviceConfig {
+            kind,
+            id: id.to_string(),
+            count: 1,
+            interval_ms,
+            key: None,
+            signal,
+            faults: vec![],
+        };
+
+        Self {
+            server: "localhost:8080".into(),
+            seed: 0,
+            devices: vec![
+                device(
+                    Kind::Termometer,
+                    "termometer",
+                    1000,
+                    SignalModel::Sine {
+                        mean: 21.0,
+                        amplitude: 3.0,
+                        period_s: 86_400.0,
+                        phase_s: 0.0,
+                    },
+                ),
+                device(
+                    Kind::Socket,
+                    "socket",
+                    2000,
+                    SignalModel::Step {
+                        levels: vec![500.0, 1500.0, 2000.0],
+                        every_s: 30.0,
+                    },
+                ),
+                device(
+                    Kind::Hygrometer,
+                    "hygrometer",
+                    3000,
+                    SignalModel::Noise {
+                        mean: 45.0,
+                        amplitude: 5.0,
+                    },
+                ),
+            ],
+            rooms: vec![],
+        }
+    }
+}
+
+impl SimulatorConfig {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config {}", path.display()))?;
+
+        toml::from_str(&text).with_context(|| format!("bad config {}", path.display()))
+    }
+
+    /// Expands every entry into `count` devices, each with its own generator.
+    ///
+    /// Devices of an entry with `count` above one get the number appended to the id.
+    pub fn devices(&self) -> Vec<VirtualDevice> {
+        let mut devices = vec![];
+
+        for entry in &self.devices {
+            for n in 1..=entry.count {
+                let id = match entry.count {
+                    1 => entry.id.clone(),
+                    _ => format!("{}-{}", entry.id, n),
+                };
+                let seed = self.seed.wrapping_add(devices.len() as u64);
+
+                devices.push(VirtualDevice {
+                    id,
+                    kind: entry.kind,
+                    interval: Duration::from_millis(entry.interval_ms.max(1)),
+                    key: entry.key.clone(),
+                    signal: Signal::new(entry.signal.clone()),
+                    faults: entry.faults.clone(),
+                    last: None,
+                    rng: StdRng::seed_from_u64(seed),
+                });
+            }
+        }
+
+        devices
+    }
+
+    pub fn rooms(&self) -> Vec<VirtualRoom> {
+        self.rooms.iter().cloned().map(VirtualRoom::new).collect()
+    }
+}
+
+/// Kind of a simulated device.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Kind {
+    Termometer,
+    Socket,
+    Hygrometer,
+}
+
+impl Kind {
+    /// Message the device sends for a value.
+    pub fn message(&self, value: f32) -> String {
+        match self {
+            Kind::Termometer => Termometer::new(Temperature::new(value)).to_string(),
+            Kind::Socket => Socket::on(Power::new(value)).to_string(),
+            Kind::Hygrometer => Hygrometer::new(Humidity::new(value)).to_string(),
+        }
+    }
+
+    /// Values the device may report.
+    pub fn range(&self) -> RangeInclusive<f32> {
+        match self {
+            Kind::Termometer => Termometer::range(),
+            Kind::Socket => Socket::range(),
+            Kind::Hygrometer => Hygrometer::range(),
+        }
+    }
+}
+
+/// One entry of [`SimulatorConfig::devices`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct VirtualDeviceConfig {
+    pub kind: Kind,
+    pub id: String,
+    #[serde(default = "one")]
+    pub count: usize,
+    pub interval_ms: u64,
+    /// Pre-shared key to sign messages with.
+    pub key: Option<String>,
+    pub signal: SignalModel,
+    #[serde(default)]
+    pub faults: Vec<Fault>,
+}
+
+fn one() -> usize {
+    1
+}
+
+/// Simulated device reporting its signal to the server.
+#[derive(Debug)]
+pub struct VirtualDevice {
+    id: String,
+    kind: Kind,
+    interval: Duration,
+    key: Option<String>,
+    signal: Signal,
+    faults: Vec<Fault>,
+    /// Value of the last message, repeated by [`FaultKind::Stuck`].
+    last: Option<f32>,
+    rng: StdRng,
+}
+
+/// What a device does on a tick, see [`VirtualDevice::next`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Send(Stamped<String>),
+    SendSlowly {
+        message: Stamped<String>,
+        chunk: usize,
+        delay: Duration,
+    },
+    /// Starts sending the message and drops the connection half way.
+    Disconnect(Stamped<String>),
+}
+
+impl Action {
+    pub fn message(&self) -> &Stamped<String> {
+        match self {
+            Action::Send(message)
+            | Action::SendSlowly { message, .. }
+            | Action::Disconnect(message) => message,
+        }
+    }
+}
+
+impl VirtualDevice {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Message for the moment `t` since the start of the simulation.
+    pub fn message(&mut self, t: Duration) -> String {
+        let value = self.signal.sample(t, &mut self.rng);
+
+        self.kind.message(value)
+    }
+
+    /// Faults to inject into the messages of the device.
+    pub fn with_faults(mut self, faults: Vec<Fault>) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Message for the moment `t` with the faults striking at `t` applied.
+    ///
+    /// Faults apply in the configured order, so a spike after a stuck value
+    /// spikes the stuck one.
+    pub fn next(&mut self, t: Duration) -> Action {
+        let mut value = self.signal.sample(t, &mut self.rng);
+        let mut time = Utc::now();
+        let mut malformed = false;
+        let mut disconnect = false;
+        let mut slowly = None;
+
+        for fault in &self.faults {
+            if !fault.strikes(t, &mut self.rng) {
+                continue;
+            }
+
+            match fault.kind {
+                FaultKind::Stuck => value = self.last.unwrap_or(value),
+                FaultKind::Spike { magnitude } => match self.rng.random_bool(0.5) {
+                    true => value += magnitude,
+                    false => value -= magnitude,
+                },
+                FaultKind::OutOfRange => {
+                    let range = self.kind.range();
+                    value = range.end() + (range.end() - range.start()) / 2.0;
+                }
+                FaultKind::Malformed => malformed = true,
+                FaultKind::Disconnect => disconnect = true,
+                FaultKind::SlowWrite { chunk, delay_ms } => {
+                    slowly = Some((chunk, Duration::from_millis(delay_ms)))
+                }
+                FaultKind::ClockDrift { rate } => {
+                    time += TimeDelta::milliseconds((t.as_secs_f64() * rate * 1000.0) as i64)
+                }
+            }
+        }
+
+        self.last = Some(value);
+        let mut message = self.kind.message(value);
+        if malformed {
+            message = self.garble(&message);
+        }
+        let message = Stamped::new(message, Some(time));
+
+        match (disconnect, slowly) {
+            (true, _) => Action::Disconnect(message),
+            (false, Some((chunk, delay))) => Action::SendSlowly {
+                message,
+                chunk,
+                delay,
+            },
+            (false, None) => Action::Send(message),
+        }
+    }
+
+    /// Spoils a message one of the ways a flaky device does.
+    fn garble(&mut self, message: &str) -> String {
+        let kind = message.split_whitespace().next().unwrap_or_default();
+
+        match self.rng.random_range(0..3) {
+            0 => kind.to_string(),
+            1 => format!("{} ##.# ?", kind),
+            _ => message.chars().rev().collect(),
+        }
+    }
+
+    /// Client talking to `server` on behalf of this device.
+    pub fn client(&self, server: &str) -> Client {
+        let client = Client::new(server);
+
+        match &self.key {
+            Some(key) => client.with_key(self.id.clone(), key.clone()),
+            None => client,
+        }
+    }
+
+    /// Reports to the server every [`VirtualDevice::interval`] until the task is
+    /// dropped, logging every message with the reply.
+    pub async fn run(mut self, mut client: Client) {
+        let start = Instant::now();
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+
+            let action = self.next(start.elapsed());
+            let message = &action.message().message;
+            let result = match &action {
+                Action::Send(stamped) => client.send(stamped).await,
+                Action::SendSlowly {
+                    message: stamped,
+                    chunk,
+                    delay,
+                } => client.send_slowly(stamped, *chunk, *delay).await,
+                Action::Disconnect(stamped) => {
+                    match client.abort(stamped).await {
+                        Ok(()) => tracing::info!(device = self.id, %message, "cut off"),
+                        Err(e) => tracing::warn!(device = self.id, %message, error = %e, "failed"),
+                    }
+                    continue;
+                }
+            };
+
+            match result {
+                Ok(reply) => tracing::info!(device = self.id, %message, %reply, "sent"),
+                Err(e) => tracing::warn!(device = self.id, %message, error = %e, "failed"),
+            }
+        }
+    }
+}
+
+/// A [`Room`] whose termometer and heater socket report to the server.
+///
+/// Every tick advances the room by the interval times [`RoomConfig::speed`], so
+/// hours of heating pass in seconds.
+#[derive(Debug)]
+pub struct VirtualRoom {
+    room: Arc<Mutex<Room>>,
+    interval: Duration,
+    step: Duration,
+}
+
+impl VirtualRoom {
+    pub fn new(config: RoomConfig) -> Self {
+        let interval = Duration::from_millis(config.interval_ms.max(1));
+        let step = interval.mul_f64(config.speed.max(0.0));
+
+        Self {
+            room: Arc::new(Mutex::new(Room::new(config))),
+            interval,
+            step,
+        }
+    }
+
+    /// The simulated room, shared with the running task to send heater commands.
+    pub fn room(&self) -> Arc<Mutex<Room>> {
+        Arc::clone(&self.room)
+    }
+
+    /// Clients of the termometer and the heater socket.
+    pub fn clients(&self, server: &str) -> (Client, Client) {
+        let room = self.room.lock().unwrap();
+        let config = room.config();
+        let client = |id: &str| {
+            let client = Client::new(server);
+            match &config.key {
+                Some(key) => client.with_key(id, key.clone()),
+                None => client,
+            }
+        };
+
+        (client(&config.termometer), client(&config.heater))
+    }
+
+    /// Advances the room and reports both devices every tick until the task is
+    /// dropped, logging every message with the reply.
+    pub async fn run(self, mut termometer: Client, mut heater: Client) {
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+
+            let (temperature, socket) = {
+                let mut room = self.room.lock().unwrap();
+                room.step(self.step);
+                (room.termometer().to_string(), room.heater().to_string())
+            };
+
+            for (client, message) in [(&mut termometer, temperature), (&mut heater, socket)] {
+                match client.send(&Stamped::now(&message)).await {
+                    Ok(reply) => tracing::info!(message, %reply, "sent"),
+                    Err(e) => tracing::warn!(message, error = %e, "failed"),
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,172 @@
-use std::{error::Error, fmt::Display, str::FromStr};
+use std::{error::Error, fmt::Display, ops::RangeInclusive, str::FromStr};
 
-use regex::Regex;
+use crate::{
+    device::{self, Device},
+    power::Power,
+    sensor_data::SensorData,
+};
 
-use crate::power::Power;
+/// Optional electrical measurements a socket may report along with the power.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Telemetry {
+    /// Volts.
+    pub voltage: Option<f32>,
+    /// Amperes.
+    pub current: Option<f32>,
+    /// From 0 to 1.
+    pub power_factor: Option<f32>,
+    /// Hertz.
+    pub frequency: Option<f32>,
+}
+
+impl Telemetry {
+    pub fn is_valid(&self) -> bool {
+        let non_negative = |v: Option<f32>| v.is_none_or(|v| v >= 0.0);
+
+        non_negative(self.voltage)
+            && non_negative(self.current)
+            && non_negative(self.frequency)
+            && self.power_factor.is_none_or(|pf| (0.0..=1.0).contains(&pf))
+    }
+}
+
+impl Display for Telemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(v) = self.voltage {
+            write!(f, " {} V", v)?;
+        }
+        if let Some(a) = self.current {
+            write!(f, " {} A", a)?;
+        }
+        if let Some(pf) = self.power_factor {
+            write!(f, " pf {}", pf)?;
+        }
+        if let Some(hz) = self.frequency {
+            write!(f, " {} Hz", hz)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Command to a socket: `on`, `off` or `power <watts>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SocketCommand {
+    On,
+    Off,
+    /// Sets the power the load draws when switched on.
+    Power(f32),
+}
+
+impl Display for SocketCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketCommand::On => write!(f, "on"),
+            SocketCommand::Off => write!(f, "off"),
+            SocketCommand::Power(p) => write!(f, "power {}", p),
+        }
+    }
+}
+
+impl FromStr for SocketCommand {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace();
+        let command = match (tokens.next(), tokens.next()) {
+            (Some("on"), None) => SocketCommand::On,
+            (Some("off"), None) => SocketCommand::Off,
+            (Some("power"), Some(p)) => SocketCommand::Power(p.parse()?),
+            _ => return Err(format!("unknown socket command {:?}", s.trim()).into()),
+        };
+
+        match tokens.next() {
+            None => Ok(command),
+            Some(_) => Err(format!("unknown socket command {:?}", s.trim()).into()),
+        }
+    }
+}
+
+/// Everything a socket reports: relay state, power and telemetry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SocketReading {
+    pub on: bool,
+    pub power: f32,
+    pub telemetry: Telemetry,
+}
+
+impl SocketReading {
+    /// Switched on socket drawing `power` watts, without telemetry.
+    pub fn on(power: f32) -> Self {
+        Self {
+            on: true,
+            power,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    /// Switched off socket: no power drawn.
+    pub fn off() -> Self {
+        Self {
+            on: false,
+            power: 0.0,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    /// A switched on socket must report power within range, a switched off one none at all.
+    pub fn is_valid(&self) -> bool {
+        let power = match self.on {
+            true => Socket::range().contains(&self.power),
+            false => self.power == 0.0,
+        };
 
-#[derive(Debug, Default)]
+        power && self.telemetry.is_valid()
+    }
+}
+
+#[derive(Debug)]
 pub struct Socket {
+    id: String,
+    on: bool,
     power: Power,
+    telemetry: Telemetry,
+}
+
+impl Default for Socket {
+    fn default() -> Self {
+        Self::off()
+    }
 }
 
 impl Socket {
-    pub fn new(power: Power) -> Self {
-        Self { power }
+    /// Switched on socket drawing `power`.
+    pub fn on(power: Power) -> Self {
+        Self {
+            id: Self::KIND.to_lowercase(),
+            on: true,
+            power,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    /// Switched off socket: no power drawn.
+    pub fn off() -> Self {
+        Self {
+            on: false,
+            power: Power::new(0.0),
+            ..Self::on(Power::default())
+        }
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry: Telemetry) -> Self {
+        self.telemetry = telemetry;
+        self
     }
 
     pub fn power(&self) -> &Power {
@@ -21,11 +176,93 @@ impl Socket {
     pub fn power_mut(&mut self) -> &mut Power {
         &mut self.power
     }
+
+    /// Whether the relay is closed.
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Switching the relay off drops the power to zero.
+    pub fn switch(&mut self, on: bool) {
+        self.on = on;
+        if !on {
+            self.power = Power::new(0.0);
+        }
+    }
+
+    /// Carries out a command. Power outside of range is ignored.
+    pub fn execute(&mut self, command: SocketCommand) {
+        match command {
+            SocketCommand::On => self.switch(true),
+            SocketCommand::Off => self.switch(false),
+            SocketCommand::Power(p) => self.power.set(p),
+        }
+    }
+
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// Takes over everything the socket reported. Power outside of range is ignored.
+    pub fn update(&mut self, reading: &SocketReading) {
+        self.switch(reading.on);
+        if reading.on {
+            self.power.set(reading.power);
+        }
+        self.telemetry = reading.telemetry;
+    }
 }
 
+impl Device for Socket {
+    const KIND: &'static str = "Socket";
+    const UNIT: &'static str = "W";
+    const GRADUATION: f32 = Power::GRADUATION;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: &str) {
+        self.id = id.to_string();
+    }
+
+    fn reading(&self) -> f32 {
+        self.power.get()
+    }
+
+    fn set_reading(&mut self, value: f32) {
+        self.power.set(value)
+    }
+
+    fn range() -> RangeInclusive<f32> {
+        Power::MIN_POWER..=Power::MAX_POWER
+    }
+
+    fn data(&self) -> SensorData {
+        SensorData::Socket(SocketReading {
+            on: self.on,
+            power: self.reading(),
+            telemetry: self.telemetry,
+        })
+    }
+
+    fn update(&mut self, data: &SensorData) -> f32 {
+        if let SensorData::Socket(reading) = data {
+            Socket::update(self, reading);
+        }
+        self.reading()
+    }
+}
+
+/// `Socket <power> W [off] [<volts> V] [<amperes> A] [pf <factor>] [<hertz> Hz]`
 impl Display for Socket {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Socket {} W", self.power)
+        write!(f, "Socket {} W", self.power)?;
+        if !self.on {
+            write!(f, " off")?;
+        }
+
+        write!(f, "{}", self.telemetry)
     }
 }
 
@@ -33,17 +270,33 @@ impl FromStr for Socket {
     type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let re = Regex::new(r"^Socket(\s)+(\d+(\.\d+)?)").unwrap();
+        let p = device::parse_value(Self::KIND, s)?;
 
-        match re.captures(s) {
-            Some(caps) => {
-                if let Ok(t) = caps[2].parse::<f32>() {
-                    return Ok(Self::new(Power::new(t)));
+        let mut socket = Self::on(Power::new(p));
+        let mut tokens = s.split_whitespace().skip(2);
+        while let Some(token) = tokens.next() {
+            match token {
+                "W" => {}
+                "on" => socket.on = true,
+                "off" => socket.on = false,
+                "pf" => {
+                    let pf = tokens.next().ok_or("missing power factor")?;
+                    socket.telemetry.power_factor = Some(pf.parse()?);
+                }
+                value => {
+                    let value = value
+                        .parse::<f32>()
+                        .map_err(|_| format!("unexpected {:?} in socket message", value))?;
+                    match tokens.next() {
+                        Some("V") => socket.telemetry.voltage = Some(value),
+                        Some("A") => socket.telemetry.current = Some(value),
+                        Some("Hz") => socket.telemetry.frequency = Some(value),
+                        unit => return Err(format!("unknown unit {:?}", unit).into()),
+                    }
                 }
-
-                Err("cannot parse float from string".into())
             }
-            None => Err("does not look like message from socket".into()),
         }
+
+        Ok(socket)
     }
 }
@@ -0,0 +1,695 @@
+use std::{collections::HashMap, net::IpAddr, time::Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    device::{self, AnyDevice, Device},
+    event::DeviceEvent,
+    history::History,
+    house::{DevicePath, House, HouseError},
+    hygrometer::Hygrometer,
+    journal::Journal,
+    limits::{Connections, LimitsConfig, Refusal, TokenBucket},
+    metrics::{Dropped, Metrics},
+    poll::PollConfig,
+    presence::{Presence, PresenceConfig},
+    reading::Reading,
+    registry::{DeviceConfig, Registry},
+    reply::Reply,
+    sensor_data::SensorData,
+    socket::Socket,
+    termometer::Termometer,
+};
+
+/// Current state of the devices known to the server.
+#[derive(Debug, Default)]
+pub struct State {
+    termometer: Termometer,
+    socket: Socket,
+    hygrometer: Hygrometer,
+    /// Standalone devices of the other [`device::register`]ed kinds, once heard
+    /// from.
+    others: HashMap<&'static str, Box<dyn AnyDevice>>,
+    history: History,
+    journal: Option<Journal>,
+    registry: Registry,
+    house: House,
+    violations: HashMap<String, Violations>,
+    seen: HashMap<String, DateTime<Utc>>,
+    presence: HashMap<String, Presence>,
+    presence_config: PresenceConfig,
+    limits: LimitsConfig,
+    connections: Connections,
+    metrics: Metrics,
+    buckets: HashMap<String, TokenBucket>,
+    deadband: bool,
+    poll: Vec<PollConfig>,
+}
+
+/// Readings of a device refused for being out of range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Violations {
+    pub count: u64,
+    /// The last value out of range and when it came.
+    pub last: f32,
+    pub time: DateTime<Utc>,
+}
+
+impl State {
+    pub fn new(termometer: Termometer, socket: Socket) -> Self {
+        Self {
+            termometer,
+            socket,
+            ..Default::default()
+        }
+    }
+
+    /// Persists every accepted reading to the journal.
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Devices addressed by `<room>/<device>` paths. Readings from other devices
+    /// update the standalone termometer, socket and hygrometer.
+    pub fn with_house(mut self, house: House) -> Self {
+        self.house = house;
+        self
+    }
+
+    pub fn house(&self) -> &House {
+        &self.house
+    }
+
+    pub fn termometer(&self) -> &Termometer {
+        &self.termometer
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+
+    pub fn hygrometer(&self) -> &Hygrometer {
+        &self.hygrometer
+    }
+
+    pub fn hygrometer_mut(&mut self) -> &mut Hygrometer {
+        &mut self.hygrometer
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// Range violations of a house device by its `<room>/<device>` path, or of a
+    /// standalone device by its kind, e.g. `Termometer`.
+    pub fn violations(&self, device: &str) -> Option<&Violations> {
+        self.violations.get(device)
+    }
+
+    /// Default staleness timeouts, a device may override them in the registry.
+    pub fn with_presence(mut self, config: PresenceConfig) -> Self {
+        self.presence_config = config;
+        self
+    }
+
+    pub fn set_presence(&mut self, config: PresenceConfig) {
+        self.presence_config = config;
+    }
+
+    /// When the server last heard from a device, by the same key as [`State::violations`].
+    pub fn last_seen(&self, device: &str) -> Option<DateTime<Utc>> {
+        self.seen.get(device).copied()
+    }
+
+    /// Presence of a device at `now`, by the same key as [`State::violations`].
+    pub fn presence(&self, device: &str, now: DateTime<Utc>) -> Presence {
+        let config = self
+            .registry
+            .device(device)
+            .and_then(|d| d.presence)
+            .unwrap_or(self.presence_config);
+
+        config.presence(self.last_seen(device), now)
+    }
+
+    /// Devices whose presence is tracked: every house device or, without a
+    /// house, the standalone ones.
+    pub fn devices(&self) -> Vec<String> {
+        let mut devices: Vec<String> = self
+            .house
+            .rooms()
+            .flat_map(|room| {
+                room.devices()
+                    .map(|d| format!("{}/{}", room.name(), d.id()))
+            })
+            .collect();
+
+        for kind in device::kinds() {
+            if self.standalone(kind).is_some() && self.house.is_empty() {
+                devices.push(kind.to_string());
+            }
+        }
+
+        devices
+    }
+
+    /// Live data of a device by the same key as [`State::violations`].
+    pub fn device_data(&self, device: &str) -> Option<SensorData> {
+        if device.contains('/') {
+            let path: DevicePath = device.parse().ok()?;
+            return self.house.device(&path).ok().map(|d| d.data());
+        }
+
+        self.standalone(device).map(|d| d.data())
+    }
+
+    /// Standalone device of this kind.
+    pub fn standalone(&self, kind: &str) -> Option<&dyn AnyDevice> {
+        match kind {
+            Termometer::KIND => Some(&self.termometer),
+            Socket::KIND => Some(&self.socket),
+            Hygrometer::KIND => Some(&self.hygrometer),
+            _ => self.others.get(kind).map(|d| d.as_ref()),
+        }
+    }
+
+    /// Standalone device of this kind, set up on first use for registered kinds.
+    fn standalone_mut(&mut self, kind: &str) -> Option<&mut dyn AnyDevice> {
+        match kind {
+            Termometer::KIND => Some(&mut self.termometer),
+            Socket::KIND => Some(&mut self.socket),
+            Hygrometer::KIND => Some(&mut self.hygrometer),
+            _ => {
+                let kind = device::kind(kind)?;
+                let device = self
+                    .others
+                    .entry(kind.name)
+                    .or_insert_with(|| kind.device(&kind.name.to_lowercase()));
+                Some(device.as_mut())
+            }
+        }
+    }
+
+    /// Presence events of the devices whose presence changed since the last call.
+    /// Devices start offline.
+    pub fn presence_changes(&mut self, now: DateTime<Utc>) -> Vec<DeviceEvent> {
+        let mut events = vec![];
+
+        for device in self.devices() {
+            let presence = self.presence(&device, now);
+            let previous = self
+                .presence
+                .insert(device.clone(), presence)
+                .unwrap_or(Presence::Offline);
+
+            if previous != presence {
+                events.push(DeviceEvent::Presence {
+                    last_seen: self.last_seen(&device),
+                    device,
+                    presence,
+                });
+            }
+        }
+
+        events
+    }
+
+    /// Notes that a device is alive and acknowledges with its current value.
+    ///
+    /// `kind` tells which standalone device sent the heartbeat, devices addressed
+    /// by `<room>/<device>` need not send it.
+    pub fn heartbeat(&mut self, device: Option<&str>, kind: Option<&str>) -> Reply {
+        let now = Utc::now();
+
+        if let Some(path) = device.filter(|d| d.contains('/')) {
+            let value = path
+                .parse::<DevicePath>()
+                .and_then(|path| self.house.device(&path).map(|d| d.data()));
+            return match value {
+                Ok(data) => {
+                    self.seen.insert(path.to_string(), now);
+                    Reply::Ack(data.value().unwrap_or_default())
+                }
+                Err(e) => Reply::Nack(e.to_string()),
+            };
+        }
+
+        let Some(kind) = kind else {
+            return Reply::Nack("heartbeat of unknown device".into());
+        };
+        let Some(data) = self.standalone_mut(kind).map(|d| d.data()) else {
+            return Reply::Nack(format!("unknown device kind {}", kind));
+        };
+        self.seen.insert(data.kind().to_string(), now);
+
+        Reply::Ack(data.value().unwrap_or_default())
+    }
+
+    /// Connection limits, applied to connections accepted from now on.
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn set_limits(&mut self, limits: LimitsConfig) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &LimitsConfig {
+        &self.limits
+    }
+
+    /// Drops readings changing less than the graduation of the device.
+    pub fn with_deadband(mut self, deadband: bool) -> Self {
+        self.deadband = deadband;
+        self
+    }
+
+    pub fn set_deadband(&mut self, deadband: bool) {
+        self.deadband = deadband;
+    }
+
+    /// Devices the server can reach, the only ones taking commands.
+    pub fn with_poll(mut self, poll: Vec<PollConfig>) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    pub fn set_poll(&mut self, poll: Vec<PollConfig>) {
+        self.poll = poll;
+    }
+
+    /// How to reach a device, `None` for one the server cannot.
+    pub fn poll(&self, device: &str) -> Option<&PollConfig> {
+        self.poll.iter().find(|p| p.device == device)
+    }
+
+    pub fn connections(&self) -> &Connections {
+        &self.connections
+    }
+
+    /// Counts a new connection from `ip`, or the refusal when over the limits.
+    pub fn connect(&mut self, ip: IpAddr) -> Result<(), Refusal> {
+        match self.connections.open(ip, &self.limits) {
+            Ok(()) => {
+                self.metrics.connections += 1;
+                Ok(())
+            }
+            Err(refusal) => {
+                self.metrics.refuse(refusal);
+                Err(refusal)
+            }
+        }
+    }
+
+    pub fn disconnect(&mut self, ip: IpAddr) {
+        self.connections.close(ip);
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn metrics_mut(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    /// Stores the reading and acknowledges it with the value that was actually kept.
+    ///
+    /// Termometer readings are calibrated first. Only readings that passed the
+    /// range check end up in history.
+    pub fn apply(&mut self, reading: &mut Reading) -> Reply {
+        self.calibrate(reading);
+
+        let Some(sent) = reading.data.value() else {
+            return Reply::Nack("unknown message".into());
+        };
+        if let Err(reason) = self.check_address(reading) {
+            return Reply::Nack(reason);
+        }
+        self.touch(reading);
+        if !reading.data.is_valid() {
+            self.violation(reading);
+        }
+        let stored = self.set_live(reading);
+
+        if sent == stored {
+            self.record(std::slice::from_ref(reading));
+        }
+
+        Reply::Ack(stored)
+    }
+
+    /// Drops a reading over the rate limit of its device or within its dead-band,
+    /// returning the reply to send instead of storing it.
+    ///
+    /// The dead-band of a device in the registry takes precedence over the
+    /// dead-band switch.
+    pub fn filter(&mut self, reading: &Reading) -> Option<Reply> {
+        if let Some(reply) = self.throttle(std::slice::from_ref(reading)) {
+            return Some(reply);
+        }
+
+        let deadband = self.deadband(reading)?;
+        let mut reading = reading.clone();
+        self.calibrate(&mut reading);
+        if !reading.data.is_valid() || self.check_address(&reading).is_err() {
+            return None;
+        }
+        self.last_seen(&Self::key(&reading))?;
+
+        let stored = self.live(&reading)?;
+        if changed(&stored, &reading.data, deadband) {
+            return None;
+        }
+
+        self.touch(&reading);
+        self.metrics.drop(Dropped::Deadband);
+        Some(Reply::Ack(stored.value().unwrap_or_default()))
+    }
+
+    /// Same as [`State::filter`] for a batch: the whole batch is refused when its
+    /// device has fewer tokens left than readings, otherwise readings within the
+    /// dead-band of the value kept before them are dropped from it.
+    ///
+    /// A batch that [`State::apply_batch`] is going to refuse is left as is.
+    pub fn filter_batch(&mut self, readings: &mut Vec<Reading>) -> Option<Reply> {
+        if let Some(reply) = self.throttle(readings) {
+            return Some(reply);
+        }
+
+        readings.sort_by_key(|r| r.time());
+        let mut calibrated = readings.clone();
+        for reading in calibrated.iter_mut() {
+            self.calibrate(reading);
+        }
+        if calibrated
+            .iter()
+            .any(|r| !r.data.is_valid() || self.check_address(r).is_err())
+        {
+            return None;
+        }
+
+        let mut kept: HashMap<String, SensorData> = HashMap::new();
+        let mut filtered = vec![];
+        for (reading, calibrated) in readings.drain(..).zip(calibrated) {
+            let key = Self::key(&calibrated);
+            let stored = match kept.get(&key) {
+                Some(data) => Some(data.clone()),
+                None => self.last_seen(&key).and(self.live(&calibrated)),
+            };
+
+            if let (Some(deadband), Some(stored)) = (self.deadband(&calibrated), stored)
+                && !changed(&stored, &calibrated.data, deadband)
+            {
+                self.touch(&calibrated);
+                self.metrics.drop(Dropped::Deadband);
+                continue;
+            }
+
+            kept.insert(key, calibrated.data);
+            filtered.push(reading);
+        }
+        *readings = filtered;
+
+        None
+    }
+
+    /// Takes a token per reading out of the bucket of the device sending them,
+    /// returning a [`Reply::Busy`] to send instead when fewer are left.
+    ///
+    /// A batch is charged only when every device in it has enough tokens, so a
+    /// refused batch leaves all the buckets as they were.
+    ///
+    /// Readings for devices missing from the house are not counted: they are
+    /// refused anyway and must not leave buckets behind. The rate limit of a
+    /// device in the registry takes precedence over [`LimitsConfig::device_rate`].
+    pub fn throttle(&mut self, readings: &[Reading]) -> Option<Reply> {
+        if readings.iter().any(|r| self.check_address(r).is_err()) {
+            return None;
+        }
+
+        let mut counts: Vec<(String, usize)> = vec![];
+        for reading in readings {
+            let key = match &reading.device {
+                Some(device) => device.clone(),
+                None => reading.data.kind().to_string(),
+            };
+            match counts.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((key, 1)),
+            }
+        }
+
+        let now = Instant::now();
+        let mut charged = vec![];
+        for (key, count) in counts {
+            let Some(rate) = self
+                .registry
+                .device(&key)
+                .and_then(|d| d.rate)
+                .or(self.limits.device_rate)
+            else {
+                continue;
+            };
+
+            let bucket = self
+                .buckets
+                .entry(key.clone())
+                .or_insert_with(|| TokenBucket::new(rate, now));
+            if bucket.limit() != rate {
+                *bucket = TokenBucket::new(rate, now);
+            }
+            if !bucket.holds(now, count) {
+                self.metrics.drop(Dropped::DeviceRate);
+                return Some(Reply::Busy("rate limit exceeded".into()));
+            }
+            charged.push((key, count));
+        }
+
+        for (key, count) in charged {
+            if let Some(bucket) = self.buckets.get_mut(&key) {
+                bucket.take_many(now, count);
+            }
+        }
+
+        None
+    }
+
+    /// Dead-band of the device sending `reading`, if it has one, see
+    /// [`State::filter`].
+    fn deadband(&self, reading: &Reading) -> Option<f32> {
+        let configured = self.settings(reading).and_then(|d| d.deadband);
+
+        configured.or(self.deadband.then(|| reading.data.graduation()))
+    }
+
+    /// Stores a batch of readings as a whole: either every reading is in range and
+    /// all of them go to history and journal, or none does.
+    ///
+    /// Only the newest reading of each kind updates the live devices.
+    pub fn apply_batch(&mut self, readings: &mut [Reading]) -> Reply {
+        for reading in readings.iter_mut() {
+            self.calibrate(reading);
+        }
+
+        for (i, reading) in readings.iter().enumerate() {
+            if let Err(reason) = self.check_address(reading) {
+                return Reply::Nack(format!("reading {}: {}", i + 1, reason));
+            }
+        }
+        for reading in readings.iter() {
+            self.touch(reading);
+        }
+
+        if let Some(i) = readings.iter().position(|r| !r.data.is_valid()) {
+            self.violation(&readings[i]);
+            return Reply::Nack(format!(
+                "reading {}: {} is out of range",
+                i + 1,
+                readings[i].data
+            ));
+        }
+
+        readings.sort_by_key(|r| r.time());
+
+        for kind in device::kinds() {
+            let same_kind = |r: &&Reading| r.data.kind() == kind;
+            let Some(newest) = readings.iter().rev().find(same_kind) else {
+                continue;
+            };
+
+            // Живое значение не откатываем на более старое из буфера устройства
+            if self
+                .history
+                .iter()
+                .filter(same_kind)
+                .filter(|r| Self::path(&r.device) == Self::path(&newest.device))
+                .all(|r| r.time() <= newest.time())
+            {
+                self.set_live(newest);
+            }
+        }
+
+        self.record(readings);
+
+        Reply::AckBatch(readings.len())
+    }
+
+    /// Registry settings of the device a reading comes from, by its id or, for
+    /// an unaddressed reading, by its kind, the same key as [`State::throttle`].
+    fn settings(&self, reading: &Reading) -> Option<&DeviceConfig> {
+        let key = match &reading.device {
+            Some(device) => device.as_str(),
+            None => reading.data.kind(),
+        };
+
+        self.registry.device(key)
+    }
+
+    /// Applies the calibration of the sending device, keeping the raw value.
+    fn calibrate(&self, reading: &mut Reading) {
+        let SensorData::Temperature(raw) = reading.data else {
+            return;
+        };
+        let calibration = self.settings(reading).and_then(|d| d.calibration.as_ref());
+
+        if let Some(calibration) = calibration {
+            reading.raw = Some(raw);
+            reading.data = SensorData::Temperature(calibration.apply(raw));
+        }
+    }
+
+    /// House device the reading is addressed to by its `<room>/<device>` id.
+    fn path(device: &Option<String>) -> Option<DevicePath> {
+        device.as_deref()?.parse().ok()
+    }
+
+    /// Refuses readings for devices missing from the house or of another kind.
+    /// With a house plan every reading must name its device, standalone ones
+    /// are not shown.
+    fn check_address(&self, reading: &Reading) -> Result<(), String> {
+        let id = match reading.device.as_deref() {
+            Some(id) if id.contains('/') => id,
+            _ if self.house.is_empty() => return Ok(()),
+            _ => {
+                return Err(format!(
+                    "{} is not addressed, house devices are <room>/<device>",
+                    reading.data
+                ));
+            }
+        };
+
+        let path: DevicePath = id.parse().map_err(|e: HouseError| e.to_string())?;
+        let device = self.house.device(&path).map_err(|e| e.to_string())?;
+        if !device.accepts(&reading.data) {
+            return Err(format!(
+                "{} is a {}, not {}",
+                path,
+                device.kind(),
+                reading.data
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Key of the live device a reading updates: its path or, for a standalone
+    /// device, its kind.
+    fn key(reading: &Reading) -> String {
+        match Self::path(&reading.device) {
+            Some(path) => path.to_string(),
+            None => reading.data.kind().to_string(),
+        }
+    }
+
+    fn touch(&mut self, reading: &Reading) {
+        let seen = self
+            .seen
+            .entry(Self::key(reading))
+            .or_insert(reading.received);
+        *seen = (*seen).max(reading.received);
+    }
+
+    fn violation(&mut self, reading: &Reading) {
+        let device = Self::key(reading);
+        let last = reading.data.value().unwrap_or_default();
+        let time = reading.received;
+
+        self.violations
+            .entry(device)
+            .and_modify(|v| {
+                v.count += 1;
+                v.last = last;
+                v.time = time;
+            })
+            .or_insert(Violations {
+                count: 1,
+                last,
+                time,
+            });
+    }
+
+    /// Data of the live device a reading updates.
+    fn live(&self, reading: &Reading) -> Option<SensorData> {
+        if let Some(path) = Self::path(&reading.device) {
+            return self.house.device(&path).ok().map(|d| d.data());
+        }
+
+        self.standalone(reading.data.kind()).map(|d| d.data())
+    }
+
+    /// Updates the live device and returns the value it holds afterwards.
+    fn set_live(&mut self, reading: &Reading) -> f32 {
+        if let Some(path) = Self::path(&reading.device)
+            && let Ok(device) = self.house.device_mut(&path)
+        {
+            return device.update(&reading.data);
+        }
+
+        self.standalone_mut(reading.data.kind())
+            .map_or(0.0, |d| d.update(&reading.data))
+    }
+
+    fn record(&mut self, readings: &[Reading]) {
+        if let Some(journal) = self.journal.as_mut()
+            && let Err(e) = journal.append(readings)
+        {
+            tracing::error!(error = ?e, "cannot write the journal");
+        }
+
+        for r in readings {
+            self.history.push(r.clone());
+        }
+    }
+}
+
+/// Whether `sent` differs from the `stored` data by the dead-band or more.
+/// Switching a socket is always a change.
+fn changed(stored: &SensorData, sent: &SensorData, deadband: f32) -> bool {
+    match (stored, sent) {
+        (SensorData::Socket(stored), SensorData::Socket(sent)) if stored.on != sent.on => true,
+        (stored, sent) => match (stored.value(), sent.value()) {
+            (Some(stored), Some(sent)) => (stored - sent).abs() >= deadband,
+            _ => true,
+        },
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::extract::ws::{Message, WebSocket};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{Receiver, Sender, error::TrySendError},
+    },
+    time::Instant,
+};
+
+use crate::{
+    api::{DeviceStatus, Sample},
+    event::DeviceEvent,
+    state::State,
+};
+
+/// Live stream of readings over WebSocket, the `[stream]` table of the server
+/// config:
+///
+/// ```toml
+/// [stream]
+/// capacity = 256
+/// heartbeat_ms = 15000
+/// send_timeout_ms = 5000
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// Events kept for a client that has not taken them yet. A client falling
+    /// further behind skips the oldest ones and is told how many.
+    pub capacity: usize,
+    /// Time between pings. A client that did not answer the previous ping is
+    /// disconnected.
+    pub heartbeat_ms: u64,
+    /// Time a client may take to accept a message before it is disconnected.
+    pub send_timeout_ms: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            heartbeat_ms: 15_000,
+            send_timeout_ms: 5000,
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_ms.max(1))
+    }
+
+    pub fn send_timeout(&self) -> Duration {
+        Duration::from_millis(self.send_timeout_ms)
+    }
+}
+
+/// Devices a client subscribed to, from the query of `/stream`, e.g.
+/// `/stream?kind=Socket&room=kitchen,hall`.
+///
+/// Every parameter takes a comma-separated list, a missing one matches any
+/// device. Standalone devices are in no room.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Filter {
+    pub kind: Option<String>,
+    /// `<room>/<device>` path of house devices, the kind of standalone ones.
+    pub device: Option<String>,
+    pub room: Option<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, device: &str, kind: &str) -> bool {
+        let room = device.split_once('/').map(|(room, _)| room);
+
+        listed(&self.kind, Some(kind))
+            && listed(&self.device, Some(device))
+            && listed(&self.room, room)
+    }
+}
+
+fn listed(list: &Option<String>, value: Option<&str>) -> bool {
+    match list {
+        Some(list) => value.is_some_and(|v| list.split(',').any(|item| item.trim() == v)),
+        None => true,
+    }
+}
+
+/// Message sent to a client, tagged by `type`:
+///
+/// - `{"type": "snapshot", "devices": [...]}` once on connect,
+/// - `{"type": "reading", "device": "kitchen/kettle", "kind": "Socket", ...}`
+///   for every stored reading,
+/// - `{"type": "lagged", "skipped": 12}` when the client fell behind.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Update {
+    Snapshot { devices: Vec<DeviceStatus> },
+    Reading(Sample),
+    Lagged { skipped: u64 },
+}
+
+/// Readings carried by an event, one by one.
+pub fn samples(event: &DeviceEvent) -> Vec<Sample> {
+    match event {
+        DeviceEvent::Reading(reading) => vec![Sample::from(reading)],
+        DeviceEvent::Batch(readings) => readings.iter().map(Sample::from).collect(),
+        _ => vec![],
+    }
+}
+
+/// Passes every event on to `tx` and publishes it to the stream clients.
+///
+/// Events `tx` has no room for are dropped and counted in
+/// [`Metrics::ui_dropped`](crate::metrics::Metrics::ui_dropped), so that a slow
+/// reader does not hold up the devices.
+pub async fn fan_out(
+    mut rx: Receiver<Arc<DeviceEvent>>,
+    tx: Sender<Arc<DeviceEvent>>,
+    events: broadcast::Sender<Arc<DeviceEvent>>,
+    state: Arc<Mutex<State>>,
+) {
+    while let Some(event) = rx.recv().await {
+        // Без подписчиков событие просто никому не нужно
+        let _ = events.send(Arc::clone(&event));
+
+        match tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => state.lock().unwrap().metrics_mut().ui_dropped += 1,
+            Err(TrySendError::Closed(_)) => return,
+        }
+    }
+}
+
+/// Sends the snapshot of the subscribed devices and then their readings until
+/// the client leaves, stops answering pings or cannot keep up.
+///
+/// `events` must be subscribed before the snapshot is taken, so that no reading
+/// falls in between.
+pub async fn run(
+    mut socket: WebSocket,
+    state: Arc<Mutex<State>>,
+    mut events: broadcast::Receiver<Arc<DeviceEvent>>,
+    filter: Filter,
+    config: StreamConfig,
+) {
+    let devices = {
+        let state = state.lock().unwrap();
+        let now = Utc::now();
+        state
+            .devices()
+            .iter()
+            .filter_map(|device| DeviceStatus::new(&state, device, now))
+            .filter(|status| filter.matches(&status.device, status.kind))
+            .collect()
+    };
+    if send(&mut socket, &Update::Snapshot { devices }, config)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let mut heartbeat =
+        tokio::time::interval_at(Instant::now() + config.heartbeat(), config.heartbeat());
+    let mut answered = true;
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let updates = match event {
+                    Ok(event) => samples(&event)
+                        .into_iter()
+                        .filter(|sample| filter.matches(&sample.device, sample.kind))
+                        .map(Update::Reading)
+                        .collect(),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!(skipped, "stream client lagged");
+                        vec![Update::Lagged { skipped }]
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                for update in updates {
+                    if send(&mut socket, &update, config).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = heartbeat.tick() => {
+                if !answered {
+                    tracing::debug!("stream client stopped answering pings");
+                    return;
+                }
+                answered = false;
+                let ping = Message::Ping(Default::default());
+                if deliver(&mut socket, ping, config).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Любое сообщение клиента, не только pong, значит, что он жив
+                Some(Ok(_)) => answered = true,
+            },
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, update: &Update, config: StreamConfig) -> Result<(), ()> {
+    let text = serde_json::to_string(update).map_err(|_| ())?;
+    deliver(socket, Message::Text(text.into()), config).await
+}
+
+/// Gives up on clients that do not take the message in time.
+async fn deliver(socket: &mut WebSocket, message: Message, config: StreamConfig) -> Result<(), ()> {
+    match tokio::time::timeout(config.send_timeout(), socket.send(message)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(_)) => Err(()),
+        Err(_) => {
+            tracing::warn!(
+                timeout_ms = config.send_timeout_ms,
+                "stream client is too slow, disconnected"
+            );
+            Err(())
+        }
+    }
+}
@@ -17,11 +17,15 @@ impl Temperature {
     }
 
     pub fn set(&mut self, value: f32) {
-        if (Self::MIN_TEMPERATURE..=Self::MAX_TEMPERATURE).contains(&value) {
+        if Self::is_valid(value) {
             self.0 = value
         }
     }
 
+    pub fn is_valid(value: f32) -> bool {
+        (Self::MIN_TEMPERATURE..=Self::MAX_TEMPERATURE).contains(&value)
+    }
+
     pub fn ratio(temperature: f32) -> f32 {
         (temperature - Self::MIN_TEMPERATURE) / (Self::MAX_TEMPERATURE - Self::MIN_TEMPERATURE)
     }
@@ -1,17 +1,34 @@
-use std::{error::Error, fmt::Display, str::FromStr};
+use std::{error::Error, fmt::Display, ops::RangeInclusive, str::FromStr};
 
-use regex::Regex;
+use crate::{
+    device::{self, Device},
+    sensor_data::SensorData,
+    temperature::Temperature,
+};
 
-use crate::temperature::Temperature;
-
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Termometer {
+    id: String,
     temperature: Temperature,
 }
 
+impl Default for Termometer {
+    fn default() -> Self {
+        Self::new(Temperature::default())
+    }
+}
+
 impl Termometer {
     pub fn new(temperature: Temperature) -> Self {
-        Self { temperature }
+        Self {
+            id: Self::KIND.to_lowercase(),
+            temperature,
+        }
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
     }
 
     pub fn temperature(&self) -> &Temperature {
@@ -23,6 +40,36 @@ impl Termometer {
     }
 }
 
+impl Device for Termometer {
+    const KIND: &'static str = "Termometer";
+    const UNIT: &'static str = "C";
+    const GRADUATION: f32 = Temperature::GRADUATION;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn set_id(&mut self, id: &str) {
+        self.id = id.to_string();
+    }
+
+    fn reading(&self) -> f32 {
+        self.temperature.get()
+    }
+
+    fn set_reading(&mut self, value: f32) {
+        self.temperature.set(value)
+    }
+
+    fn range() -> RangeInclusive<f32> {
+        Temperature::MIN_TEMPERATURE..=Temperature::MAX_TEMPERATURE
+    }
+
+    fn data(&self) -> SensorData {
+        SensorData::Temperature(self.reading())
+    }
+}
+
 impl Display for Termometer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Termometer {}", self.temperature())
@@ -33,17 +80,8 @@ impl FromStr for Termometer {
     type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let re = Regex::new(r"^Termometer(\s)+(\d+(\.\d+)?)").unwrap();
+        let t = device::parse_value(Self::KIND, s)?;
 
-        match re.captures(s) {
-            Some(caps) => {
-                if let Ok(t) = caps[2].parse::<f32>() {
-                    return Ok(Self::new(Temperature::new(t)));
-                }
-
-                Err("cannot parse float from string".into())
-            }
-            None => Err("does not look like message from termometer".into()),
-        }
+        Ok(Self::new(Temperature::new(t)))
     }
 }
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use anyhow::Context;
+use tokio_rustls::{
+    TlsAcceptor, TlsConnector,
+    rustls::{
+        ClientConfig, RootCertStore, ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::config::TlsConfig;
+
+/// Reads every certificate from a PEM file.
+pub fn load_certs(path: impl AsRef<Path>) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let path = path.as_ref();
+    CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("cannot read certificates from {}", path.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("bad certificate in {}", path.display()))
+}
+
+/// Reads the first private key from a PEM file.
+pub fn load_key(path: impl AsRef<Path>) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let path = path.as_ref();
+    PrivateKeyDer::from_pem_file(path)
+        .with_context(|| format!("cannot read private key from {}", path.display()))
+}
+
+fn root_store(ca: impl AsRef<Path>) -> anyhow::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca)? {
+        roots.add(cert)?;
+    }
+
+    Ok(roots)
+}
+
+/// Server side of TLS: the acceptor plus the mapping of client certificates to devices.
+#[derive(Clone)]
+pub struct TlsServer {
+    acceptor: TlsAcceptor,
+    devices: Arc<HashMap<String, String>>,
+}
+
+impl TlsServer {
+    /// Builds the acceptor; client certificates are required when `client_ca` is set.
+    pub fn new(config: &TlsConfig) -> anyhow::Result<Self> {
+        let builder = ServerConfig::builder();
+        let builder = match &config.client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(root_store(ca)?)).build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server = builder.with_single_cert(load_certs(&config.cert)?, load_key(&config.key)?)?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            devices: Arc::new(config.devices.clone()),
+        })
+    }
+
+    pub fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+
+    /// Device id for the peer certificate: the configured mapping of its subject
+    /// common name, or the common name itself.
+    pub fn device_id(&self, peer: Option<&[CertificateDer<'_>]>) -> Option<String> {
+        let subject = subject_common_name(peer?.first()?)?;
+
+        Some(self.devices.get(&subject).cloned().unwrap_or(subject))
+    }
+}
+
+/// Common name from the subject of a DER certificate.
+pub fn subject_common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = X509Certificate::from_der(cert.as_ref()).ok()?;
+    let cn = cert.subject().iter_common_name().next()?;
+
+    cn.as_str().ok().map(str::to_string)
+}
+
+/// Client side of TLS: trusted server CA and an optional client identity.
+pub fn connector(
+    ca: impl AsRef<Path>,
+    identity: Option<(&Path, &Path)>,
+) -> anyhow::Result<TlsConnector> {
+    let builder = ClientConfig::builder().with_root_certificates(root_store(ca)?);
+    let client = match identity {
+        Some((cert, key)) => builder.with_client_auth_cert(load_certs(cert)?, load_key(key)?)?,
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(TlsConnector::from(Arc::new(client)))
+}
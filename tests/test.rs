@@ -1,3 +1,85 @@
+/// Fixtures shared by the test modules.
+mod support {
+    use std::sync::{Arc, Mutex};
+
+    use otus_tokio_devices::{
+        emulator::{self, SocketEmulator},
+        event::DeviceEvent,
+        house::{House, HouseConfig},
+        server,
+        state::State,
+    };
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    /// `[[rooms]]` table of a kitchen with a termometer and a kettle.
+    pub const KITCHEN: &str = r#"
+        [[rooms]]
+        name = "kitchen"
+        devices = [
+            { id = "termometer", kind = "Termometer" },
+            { id = "kettle", kind = "Socket" },
+        ]
+        "#;
+
+    /// House read from the `[house]` table of the server config.
+    pub fn house(config: &str) -> House {
+        let config: HouseConfig = toml::from_str(config).unwrap();
+        House::from_config(&config).unwrap()
+    }
+
+    /// Listener on a free local port, with its address.
+    pub async fn listener() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        (listener, addr)
+    }
+
+    /// Local address nothing listens on, for a server or a device that is down.
+    pub async fn vacant() -> String {
+        listener().await.1
+    }
+
+    /// Sender of events nobody looks at.
+    pub fn drain() -> mpsc::Sender<Arc<DeviceEvent>> {
+        let (tx, mut rx) = mpsc::channel(32);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+
+        tx
+    }
+
+    /// Serves the state over TCP at `addr`, passing the events to `tx`, and
+    /// returns the address it listens on.
+    pub async fn serve(
+        addr: &str,
+        state: &Arc<Mutex<State>>,
+        tx: mpsc::Sender<Arc<DeviceEvent>>,
+    ) -> String {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(server::serve(listener, Arc::clone(state), tx));
+
+        addr
+    }
+
+    /// Serves the state over TCP on a free port, dropping the events.
+    pub async fn start(state: State) -> (String, Arc<Mutex<State>>) {
+        let state = Arc::new(Mutex::new(state));
+        let addr = serve("127.0.0.1:0", &state, drain()).await;
+
+        (addr, state)
+    }
+
+    /// Socket emulator drawing `power` watts on a free port.
+    pub async fn emulator(power: f32) -> (String, Arc<Mutex<SocketEmulator>>) {
+        let (listener, addr) = listener().await;
+        let emulator = Arc::new(Mutex::new(SocketEmulator::new(power).unwrap()));
+        tokio::spawn(emulator::serve(listener, Arc::clone(&emulator)));
+
+        (addr, emulator)
+    }
+}
+
 #[cfg(test)]
 mod socket_tests {
     use otus_tokio_devices::socket::Socket;
@@ -29,7 +111,7 @@ mod socket_tests {
 
         let result = Socket::from_str(message);
 
-        assert!(!result.is_ok(), "Got an error");
+        assert!(result.is_err(), "Got an error");
     }
 }
 
@@ -70,6 +152,3847 @@ mod termometer_test {
 
         let termometer = Termometer::from_str(message);
 
-        assert!(!termometer.is_ok(), "Got an error");
+        assert!(termometer.is_err(), "Got an error");
+    }
+}
+
+#[cfg(test)]
+mod hygrometer_test {
+    use otus_tokio_devices::hygrometer::Hygrometer;
+    use otus_tokio_devices::sensor_data::SensorData;
+    use std::str::FromStr;
+
+    #[test]
+    fn positive_f32_in_string() {
+        let message = "Hygrometer 45.5 %";
+
+        let hygrometer = Hygrometer::from_str(message);
+
+        assert!(hygrometer.is_ok(), "Looks like string has been parsed well");
+        assert!(
+            hygrometer.unwrap().humidity().get() == 45.5,
+            "Humidity is correct"
+        );
+    }
+
+    #[test]
+    fn positive_roundtrip_through_sensor_data() {
+        let data = SensorData::from_str("Hygrometer 60 %").unwrap();
+
+        assert_eq!(data, SensorData::Humidity(60.0));
+        assert_eq!(SensorData::from_str(&data.to_string()).unwrap(), data);
+    }
+
+    #[test]
+    fn negative_missing_humidity() {
+        let message = "Hygrometer wet %";
+
+        let hygrometer = Hygrometer::from_str(message);
+
+        assert!(hygrometer.is_err(), "Got an error");
+    }
+}
+
+#[cfg(test)]
+mod reply_test {
+    use otus_tokio_devices::reply::Reply;
+    use std::str::FromStr;
+
+    #[test]
+    fn positive_ack_roundtrip() {
+        let reply = Reply::from_str(&Reply::Ack(21.5).to_string());
+
+        assert!(reply.is_ok(), "Looks like string has been parsed well");
+        assert_eq!(reply.unwrap(), Reply::Ack(21.5), "Value is correct");
+    }
+
+    #[test]
+    fn positive_nack_keeps_reason() {
+        let reply = Reply::from_str("Nack cannot parse float from string\n");
+
+        assert_eq!(
+            reply.unwrap(),
+            Reply::Nack("cannot parse float from string".into()),
+            "Reason is correct"
+        );
+    }
+
+    #[test]
+    fn positive_busy_roundtrip() {
+        let busy = Reply::Busy("rate limit exceeded".into());
+        let reply = Reply::from_str(&busy.to_string()).unwrap();
+
+        assert_eq!(reply, busy, "Busy is not confused with Nack");
+        assert!(!reply.is_ack());
+    }
+
+    #[test]
+    fn negative_garbage() {
+        let reply = Reply::from_str("Ok: Termometer 21 C");
+
+        assert!(reply.is_err(), "Got an error");
+    }
+}
+
+#[cfg(test)]
+mod server_test {
+    use std::sync::{Arc, Mutex};
+
+    use otus_tokio_devices::{
+        client::Client, power::Power, reading::Stamped, reply::Reply, socket::Socket, state::State,
+        temperature::Temperature, termometer::Termometer,
+    };
+
+    use crate::support;
+
+    async fn start() -> (Client, Arc<Mutex<State>>) {
+        let (addr, state) = support::start(State::default()).await;
+        (Client::new(addr), state)
+    }
+
+    #[tokio::test]
+    async fn positive_ack_for_every_reading() {
+        let (mut client, state) = start().await;
+
+        let termometer = Termometer::new(Temperature::new(21.5));
+        let socket = Socket::on(Power::new(1500.0));
+
+        assert_eq!(client.send(&termometer).await.unwrap(), Reply::Ack(21.5));
+        assert_eq!(client.send(&socket).await.unwrap(), Reply::Ack(1500.0));
+        assert_eq!(state.lock().unwrap().termometer().temperature().get(), 21.5);
+    }
+
+    #[tokio::test]
+    async fn positive_humidity_reading() {
+        let (mut client, state) = start().await;
+
+        let reply = client.send(&"Hygrometer 55 %").await.unwrap();
+
+        assert_eq!(reply, Reply::Ack(55.0));
+        assert_eq!(state.lock().unwrap().hygrometer().humidity().get(), 55.0);
+    }
+
+    #[tokio::test]
+    async fn positive_ack_carries_stored_value() {
+        let (mut client, _) = start().await;
+
+        let termometer = Termometer::new(Temperature::new(42.0));
+        client.send(&termometer).await.unwrap();
+
+        let out_of_range = Termometer::new(Temperature::new(500.0));
+        let reply = client.send(&out_of_range).await.unwrap();
+
+        assert_eq!(reply, Reply::Ack(42.0), "Out of range value is rejected");
+    }
+
+    #[tokio::test]
+    async fn positive_device_time_kept_in_history() {
+        let (mut client, state) = start().await;
+
+        let time = "2025-04-01T10:00:00Z".parse().unwrap();
+        let termometer = Stamped::new(Termometer::new(Temperature::new(20.0)), Some(time));
+        client.send(&termometer).await.unwrap();
+
+        let state = state.lock().unwrap();
+        let reading = state.history().iter().last().unwrap();
+        assert_eq!(reading.device_time, Some(time), "Device time is kept");
+        assert!(reading.clock_skew().is_some(), "Old timestamp is a skew");
+    }
+
+    #[tokio::test]
+    async fn positive_batch_updates_live_state_with_newest() {
+        let (mut client, state) = start().await;
+
+        let batch = "Batch Termometer 25 C @2025-04-01T10:05:00Z; \
+            Termometer 21 C @2025-04-01T10:00:00Z; Socket 1500 W @2025-04-01T10:01:00Z";
+        let reply = client.send(&batch).await.unwrap();
+
+        assert_eq!(reply, Reply::AckBatch(3));
+        let state = state.lock().unwrap();
+        assert_eq!(state.termometer().temperature().get(), 25.0, "Newest wins");
+        assert_eq!(state.socket().power().get(), 1500.0);
+        assert_eq!(state.history().len(), 3, "Every reading is kept");
+    }
+
+    #[tokio::test]
+    async fn positive_old_batch_does_not_roll_back_live_state() {
+        let (mut client, state) = start().await;
+
+        client
+            .send(&Termometer::new(Temperature::new(30.0)))
+            .await
+            .unwrap();
+        let batch = "Batch Termometer 21 C @2025-04-01T10:00:00Z";
+        client.send(&batch).await.unwrap();
+
+        let state = state.lock().unwrap();
+        assert_eq!(state.termometer().temperature().get(), 30.0);
+        assert_eq!(state.history().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn negative_batch_is_atomic() {
+        let (mut client, state) = start().await;
+
+        let batch = "Batch Termometer 21 C @2025-04-01T10:00:00Z; Termometer 500 C";
+        let reply = client.send(&batch).await.unwrap();
+
+        assert!(!reply.is_ack(), "Got a nack");
+        let state = state.lock().unwrap();
+        assert!(state.history().is_empty(), "Nothing is stored");
+        assert_eq!(state.termometer().temperature().get(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn negative_nack_with_reason() {
+        let (mut client, _) = start().await;
+
+        let reply = client.send(&"Termometer x C").await.unwrap();
+
+        assert!(!reply.is_ack(), "Got a nack");
+        assert_eq!(
+            reply,
+            Reply::Nack("does not look like message from termometer".into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod reading_test {
+    use chrono::{TimeDelta, Utc};
+    use otus_tokio_devices::{
+        reading::{Reading, Stamped},
+        sensor_data::SensorData,
+        termometer::Termometer,
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn positive_stamped_roundtrip() {
+        let message = "Termometer 21.5 C @2025-04-01T10:00:00+00:00";
+
+        let stamped = Stamped::<SensorData>::from_str(message).unwrap();
+
+        assert_eq!(stamped.message, SensorData::Temperature(21.5));
+        assert_eq!(stamped.time, Some("2025-04-01T10:00:00Z".parse().unwrap()));
+        assert_eq!(
+            Stamped::<SensorData>::from_str(&stamped.to_string()).unwrap(),
+            stamped
+        );
+    }
+
+    #[test]
+    fn positive_without_timestamp() {
+        let stamped = Stamped::<Termometer>::from_str("Termometer 21 C").unwrap();
+
+        assert!(stamped.time.is_none(), "No device time");
+    }
+
+    #[test]
+    fn negative_bad_timestamp() {
+        let stamped = Stamped::<SensorData>::from_str("Termometer 21 C @yesterday");
+
+        assert!(stamped.is_err(), "Got an error");
+    }
+
+    #[test]
+    fn positive_small_skew_is_ignored() {
+        let reading = Reading::new(
+            SensorData::Temperature(21.0),
+            Some(Utc::now() - TimeDelta::seconds(1)),
+        );
+
+        assert!(reading.skew().is_some());
+        assert!(reading.clock_skew().is_none(), "Within tolerance");
+    }
+}
+
+#[cfg(test)]
+mod journal_test {
+    use chrono::{TimeDelta, Utc};
+    use otus_tokio_devices::{
+        history::History, journal::Journal, reading::Reading, sensor_data::SensorData,
+        socket::SocketReading,
+    };
+
+    #[test]
+    fn positive_journal_roundtrip() {
+        let path = std::env::temp_dir().join(format!("journal-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let readings = vec![
+            Reading::new(SensorData::Temperature(21.5), Some(Utc::now())),
+            Reading::new(SensorData::Socket(SocketReading::on(1500.0)), None),
+        ];
+        Journal::open(&path).unwrap().append(&readings).unwrap();
+
+        let loaded = Journal::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].data, SensorData::Temperature(21.5));
+        assert_eq!(loaded[1].device_time, None);
+    }
+
+    #[test]
+    fn positive_history_range_uses_reading_time() {
+        let now = Utc::now();
+        let mut history = History::new(2);
+        for minutes in [30, 20, 10] {
+            let time = now - TimeDelta::minutes(minutes);
+            history.push(Reading::new(SensorData::Temperature(20.0), Some(time)));
+        }
+
+        assert_eq!(history.len(), 2, "Oldest reading is dropped");
+        let recent = history.range(now - TimeDelta::minutes(15), now);
+        assert_eq!(recent.count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod protocol_test {
+    use otus_tokio_devices::{protocol::Message, sensor_data::SensorData, socket::SocketReading};
+    use std::str::FromStr;
+
+    #[test]
+    fn positive_batch_roundtrip() {
+        let message = "Batch Termometer 21 C @2025-04-01T10:00:00+00:00; Socket 1500 W";
+
+        let batch = Message::from_str(message).unwrap();
+
+        match &batch {
+            Message::Batch(readings) => {
+                assert_eq!(readings.len(), 2);
+                assert_eq!(
+                    readings[1].message,
+                    SensorData::Socket(SocketReading::on(1500.0))
+                );
+            }
+            _ => panic!("Expected a batch"),
+        }
+        assert_eq!(Message::from_str(&batch.to_string()).unwrap(), batch);
+    }
+
+    #[test]
+    fn negative_batch_names_bad_reading() {
+        let message = "Batch Termometer 21 C; Socket x W";
+
+        let err = Message::from_str(message).unwrap_err();
+
+        assert!(err.to_string().starts_with("reading 2:"), "Got {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tls_test {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use otus_tokio_devices::{
+        client::Client,
+        config::{ServerConfig, TlsConfig},
+        reply::Reply,
+        server,
+        state::State,
+        temperature::Temperature,
+        termometer::Termometer,
+        tls::{self, TlsServer},
+    };
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair};
+    use tokio::net::TcpListener;
+
+    use crate::support;
+
+    /// Self-signed CA with a server certificate for `localhost` and a client one for `kitchen`.
+    fn generate(dir: &Path) {
+        let mut ca = CertificateParams::new(vec![]).unwrap();
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca.distinguished_name.push(DnType::CommonName, "test ca");
+        let ca = CertifiedIssuer::self_signed(ca, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+
+        for (name, san) in [("server", "localhost"), ("client", "kitchen")] {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![san.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, san);
+            let cert = params.signed_by(&key, &ca).unwrap();
+
+            std::fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tls-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        generate(&dir);
+        dir
+    }
+
+    async fn start(dir: &Path, mutual: bool) -> (String, Arc<Mutex<State>>) {
+        let config = TlsConfig {
+            listen: "127.0.0.1:0".into(),
+            cert: dir.join("server.pem"),
+            key: dir.join("server.key"),
+            client_ca: mutual.then(|| dir.join("ca.pem")),
+            devices: HashMap::from([("kitchen".into(), "kitchen-termometer".into())]),
+        };
+
+        let listener = TcpListener::bind(&config.listen).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let tls = TlsServer::new(&config).unwrap();
+        tokio::spawn(server::serve_tls(
+            listener,
+            tls,
+            Arc::clone(&state),
+            support::drain(),
+        ));
+
+        (addr.to_string(), state)
+    }
+
+    #[tokio::test]
+    async fn positive_client_certificate_names_device() {
+        let dir = temp_dir("mutual");
+        let (addr, state) = start(&dir, true).await;
+
+        let identity = (dir.join("client.pem"), dir.join("client.key"));
+        let connector =
+            tls::connector(dir.join("ca.pem"), Some((&identity.0, &identity.1))).unwrap();
+        let mut client = Client::new(addr).with_tls(connector, "localhost").unwrap();
+
+        let reply = client.send(&Termometer::new(Temperature::new(21.0))).await;
+
+        assert_eq!(reply.unwrap(), Reply::Ack(21.0));
+        let state = state.lock().unwrap();
+        let reading = state.history().iter().last().unwrap();
+        assert_eq!(reading.device.as_deref(), Some("kitchen-termometer"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn positive_server_only_tls() {
+        let dir = temp_dir("server-only");
+        let (addr, _) = start(&dir, false).await;
+
+        let connector = tls::connector(dir.join("ca.pem"), None).unwrap();
+        let mut client = Client::new(addr).with_tls(connector, "localhost").unwrap();
+
+        let reply = client.send(&Termometer::new(Temperature::new(21.0))).await;
+
+        assert_eq!(reply.unwrap(), Reply::Ack(21.0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn negative_client_without_certificate() {
+        let dir = temp_dir("no-cert");
+        let (addr, state) = start(&dir, true).await;
+
+        let connector = tls::connector(dir.join("ca.pem"), None).unwrap();
+        let mut client = Client::new(addr).with_tls(connector, "localhost").unwrap();
+
+        let reply = client.send(&Termometer::new(Temperature::new(21.0))).await;
+
+        assert!(reply.is_err(), "Handshake is refused");
+        assert!(state.lock().unwrap().history().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn positive_config_with_tls_section() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            listen = "0.0.0.0:8080"
+
+            [tls]
+            listen = "0.0.0.0:8443"
+            cert = "server.pem"
+            key = "server.key"
+            client_ca = "ca.pem"
+
+            [tls.devices]
+            kitchen = "kitchen-termometer"
+            "#,
+        )
+        .unwrap();
+
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.devices["kitchen"], "kitchen-termometer");
+        assert!(config.journal.is_none(), "Defaults are kept");
+    }
+}
+
+#[cfg(test)]
+mod auth_test {
+    use std::{
+        collections::HashMap,
+        str::FromStr,
+        sync::{Arc, Mutex},
+    };
+
+    use otus_tokio_devices::{
+        auth::Signed,
+        client::Client,
+        registry::{DeviceConfig, Registry},
+        reply::Reply,
+        state::State,
+        temperature::Temperature,
+        termometer::Termometer,
+    };
+
+    use crate::support;
+
+    async fn start() -> (String, Arc<Mutex<State>>) {
+        let devices = HashMap::from([(
+            "kitchen".to_string(),
+            DeviceConfig {
+                key: Some("secret".into()),
+                ..Default::default()
+            },
+        )]);
+
+        support::start(State::default().with_registry(Registry::new(devices, true))).await
+    }
+
+    #[test]
+    fn positive_signed_roundtrip() {
+        let signed = Signed::new("kitchen", b"secret", 7, "Termometer 21 C");
+
+        let parsed = Signed::from_str(&signed.to_string()).unwrap();
+
+        assert_eq!(parsed, signed);
+        assert!(parsed.verify(b"secret"), "Signature matches");
+        assert!(!parsed.verify(b"other"), "Wrong key is detected");
+    }
+
+    #[tokio::test]
+    async fn positive_client_signs_messages() {
+        let (addr, state) = start().await;
+        let mut client = Client::new(addr).with_key("kitchen", "secret");
+
+        let termometer = Termometer::new(Temperature::new(21.0));
+        assert_eq!(client.send(&termometer).await.unwrap(), Reply::Ack(21.0));
+        assert_eq!(client.send(&termometer).await.unwrap(), Reply::Ack(21.0));
+
+        let state = state.lock().unwrap();
+        let reading = state.history().iter().last().unwrap();
+        assert_eq!(reading.device.as_deref(), Some("kitchen"));
+    }
+
+    #[tokio::test]
+    async fn negative_unsigned_message() {
+        let (addr, _) = start().await;
+        let mut client = Client::new(addr);
+
+        let reply = client.send(&Termometer::new(Temperature::new(21.0))).await;
+
+        assert_eq!(
+            reply.unwrap(),
+            Reply::Nack("unauthenticated message".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_unsigned_report() {
+        let (addr, _) = start().await;
+
+        let reply = Client::new(&addr).send(&"Report").await.unwrap();
+        assert_eq!(reply, Reply::Nack("unauthenticated message".into()));
+        let reply = Client::new(&addr).send(&"kitchen/kettle Report").await;
+        assert_eq!(
+            reply.unwrap(),
+            Reply::Nack("unauthenticated message".into()),
+            "An address does not stand for a signature"
+        );
+
+        let mut signed = Client::new(&addr).with_key("kitchen", "secret");
+        let reply = signed.send(&"Report json").await.unwrap();
+        assert!(matches!(reply, Reply::Report(_)), "{}", reply);
+    }
+
+    #[tokio::test]
+    async fn negative_unsigned_standalone_reading_with_keys() {
+        let devices = HashMap::from([(
+            "kitchen".to_string(),
+            DeviceConfig {
+                key: Some("secret".into()),
+                ..Default::default()
+            },
+        )]);
+        let state = State::default().with_registry(Registry::new(devices, false));
+        let (addr, state) = support::start(state).await;
+        let mut signed = Client::new(&addr).with_key("kitchen", "secret");
+        assert!(signed.send(&"Termometer 21 C").await.unwrap().is_ack());
+
+        let reply = Client::new(&addr).send(&"Termometer 99 C").await.unwrap();
+
+        assert_eq!(
+            reply,
+            Reply::Nack("unsigned message, devices here sign theirs".into())
+        );
+        assert_eq!(
+            state.lock().unwrap().history().len(),
+            1,
+            "The keyed device's reading is not overwritten"
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_replayed_message() {
+        let (addr, _) = start().await;
+        let mut client = Client::new(addr);
+
+        let signed = Signed::new("kitchen", b"secret", 1, "Termometer 21 C");
+        assert!(client.send(&signed).await.unwrap().is_ack());
+
+        let reply = client.send(&signed).await.unwrap();
+        assert!(!reply.is_ack(), "Replay is refused");
+    }
+
+    #[test]
+    fn negative_replay_after_restart() {
+        let path = std::env::temp_dir().join(format!("counters-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let devices = HashMap::from([(
+            "kitchen".to_string(),
+            DeviceConfig {
+                key: Some("secret".into()),
+                ..Default::default()
+            },
+        )]);
+        let signed = |counter| Signed::new("kitchen", b"secret", counter, "Termometer 21 C");
+
+        let mut registry = Registry::new(devices.clone(), true)
+            .with_counters(&path)
+            .unwrap();
+        assert!(registry.authenticate(&signed(1)).is_ok());
+        assert!(registry.authenticate(&signed(5)).is_ok());
+        drop(registry);
+
+        let mut registry = Registry::new(devices, true).with_counters(&path).unwrap();
+        let replayed = registry.authenticate(&signed(5));
+        let next = registry.authenticate(&signed(6));
+        let _ = std::fs::remove_file(&path);
+
+        assert!(replayed.is_err(), "Counter survives the restart");
+        assert!(next.is_ok());
+    }
+
+    #[tokio::test]
+    async fn negative_wrong_key() {
+        let (addr, state) = start().await;
+        let mut client = Client::new(addr).with_key("kitchen", "guess");
+
+        let reply = client.send(&Termometer::new(Temperature::new(21.0))).await;
+
+        assert_eq!(
+            reply.unwrap(),
+            Reply::Nack("bad signature from kitchen".into())
+        );
+        assert!(state.lock().unwrap().history().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod device_test {
+    use std::{error::Error, fmt::Display, ops::RangeInclusive, str::FromStr};
+
+    use otus_tokio_devices::{
+        device::{self, Device, Kinds, UnknownKind},
+        house::{House, HouseConfig},
+        metrics,
+        reading::Reading,
+        reply::Reply,
+        sensor_data::SensorData,
+        socket::{Socket, SocketReading},
+        state::State,
+        termometer::Termometer,
+    };
+
+    /// Device kind defined outside of the crate.
+    #[derive(Debug, Default)]
+    struct Barometer(f32);
+
+    impl Display for Barometer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Barometer {} hPa", self.0)
+        }
+    }
+
+    impl FromStr for Barometer {
+        type Err = Box<dyn Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Ok(Self(device::parse_value(Self::KIND, s)?))
+        }
+    }
+
+    impl Device for Barometer {
+        const KIND: &'static str = "Barometer";
+        const UNIT: &'static str = "hPa";
+        const GRADUATION: f32 = 1.0;
+
+        fn id(&self) -> &str {
+            "barometer"
+        }
+
+        fn set_id(&mut self, _id: &str) {}
+
+        fn reading(&self) -> f32 {
+            self.0
+        }
+
+        fn set_reading(&mut self, value: f32) {
+            if Self::range().contains(&value) {
+                self.0 = value
+            }
+        }
+
+        fn range() -> RangeInclusive<f32> {
+            800.0..=1100.0
+        }
+    }
+
+    #[test]
+    fn positive_dispatch_by_kind() {
+        let kinds = Kinds::default();
+
+        assert_eq!(
+            kinds.parse("Termometer 21 C").unwrap(),
+            SensorData::Temperature(21.0)
+        );
+        assert_eq!(
+            kinds.parse("Socket 1500 W").unwrap(),
+            SensorData::Socket(SocketReading::on(1500.0))
+        );
+    }
+
+    #[test]
+    fn positive_register_new_kind() {
+        let kinds = Kinds::default().register::<Barometer>();
+
+        assert!(kinds.names().any(|name| name == "Barometer"));
+        assert!(kinds.parse("Barometer 1013 hPa").is_ok());
+    }
+
+    #[test]
+    fn positive_registered_kind_flows_through_server() {
+        device::register::<Barometer>();
+        let config: HouseConfig = toml::from_str(
+            r#"
+            name = "Дача"
+
+            [[rooms]]
+            name = "hall"
+            devices = [{ id = "barometer", kind = "Barometer" }]
+            "#,
+        )
+        .unwrap();
+        let mut state = State::default().with_house(House::from_config(&config).unwrap());
+
+        let data: SensorData = "Barometer 1013 hPa".parse().unwrap();
+        let mut reading =
+            Reading::new(data.clone(), None).with_device(Some("hall/barometer".into()));
+        let reply = state.apply(&mut reading);
+
+        assert_eq!(
+            data,
+            SensorData::Measurement {
+                kind: "Barometer",
+                value: 1013.0
+            }
+        );
+        assert_eq!(data.to_string(), "Barometer 1013 hPa");
+        assert_eq!(reply, Reply::Ack(1013.0));
+        assert_eq!(state.device_data("hall/barometer"), Some(data));
+        assert!(metrics::render(&state).contains(
+            "devices_reading{device=\"hall/barometer\",kind=\"Barometer\",unit=\"hPa\"} 1013"
+        ));
+    }
+
+    #[test]
+    fn negative_registered_kind_out_of_range() {
+        device::register::<Barometer>();
+        let mut state = State::default();
+
+        let mut reading = Reading::new("Barometer 1200 hPa".parse().unwrap(), None);
+        let reply = state.apply(&mut reading);
+
+        assert_eq!(reply, Reply::Ack(0.0), "Out of range value is not kept");
+        assert_eq!(state.violations("Barometer").map(|v| v.count), Some(1));
+        assert!(state.history().is_empty());
+    }
+
+    #[test]
+    fn positive_set_reading_respects_range() {
+        let mut termometer = Termometer::default().with_id("kitchen");
+
+        termometer.set_reading(21.0);
+        termometer.set_reading(Termometer::range().end() + 1.0);
+
+        assert_eq!(termometer.id(), "kitchen");
+        assert_eq!(termometer.reading(), 21.0, "Out of range value is ignored");
+        assert_eq!(Socket::default().format(), "Socket 0 W off");
+    }
+
+    #[test]
+    fn negative_unknown_kind() {
+        let result = Kinds::default().parse("Barometer 1013 hPa");
+
+        assert!(result.is_err(), "Got an error");
+        let error = result.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<UnknownKind>(),
+            Some(&UnknownKind("Barometer".into()))
+        );
+        assert_eq!(error.to_string(), "unknown message");
+        let error = Kinds::default().parse("Termometer hot").unwrap_err();
+        assert!(!error.is::<UnknownKind>(), "Known kind, bad message");
+    }
+}
+
+#[cfg(test)]
+mod socket_telemetry_test {
+    use otus_tokio_devices::{
+        device::Device,
+        sensor_data::SensorData,
+        socket::{Socket, SocketReading, Telemetry},
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn positive_full_telemetry() {
+        let message = "Socket 1500 W 230 V 6.5 A pf 0.98 50 Hz";
+
+        let socket = Socket::from_str(message).unwrap();
+
+        assert!(socket.is_on(), "Relay is on by default");
+        assert_eq!(
+            *socket.telemetry(),
+            Telemetry {
+                voltage: Some(230.0),
+                current: Some(6.5),
+                power_factor: Some(0.98),
+                frequency: Some(50.0),
+            }
+        );
+        assert_eq!(
+            Socket::from_str(&socket.to_string()).unwrap().data(),
+            socket.data()
+        );
+    }
+
+    #[test]
+    fn positive_off_is_not_zero_watts() {
+        let off = SensorData::from_str("Socket 0 W off").unwrap();
+        let zero = SensorData::from_str("Socket 0 W").unwrap();
+
+        assert_eq!(off, SensorData::Socket(SocketReading::off()));
+        assert!(off.is_valid(), "Switched off socket draws nothing");
+        assert!(!zero.is_valid(), "Switched on socket must draw power");
+    }
+
+    #[test]
+    fn positive_switch_off_drops_power() {
+        let mut socket = Socket::from_str("Socket 1500 W").unwrap();
+
+        socket.switch(false);
+
+        assert_eq!(socket.to_string(), "Socket 0 W off");
+    }
+
+    #[test]
+    fn negative_unknown_unit() {
+        let result = Socket::from_str("Socket 1500 W 230 X");
+
+        assert!(result.is_err(), "Got an error");
+    }
+
+    #[test]
+    fn negative_power_factor_out_of_range() {
+        let data = SensorData::from_str("Socket 1500 W pf 1.5").unwrap();
+
+        assert!(!data.is_valid(), "Power factor above one");
+    }
+}
+
+#[cfg(test)]
+mod calibration_test {
+    use std::{collections::HashMap, time::Duration};
+
+    use otus_tokio_devices::{
+        calibration::Calibration,
+        client::Client,
+        config::{self, ServerConfig},
+        reading::Reading,
+        registry::{DeviceConfig, Registry},
+        reply::Reply,
+        sensor_data::SensorData,
+        state::State,
+    };
+    use tokio::sync::mpsc;
+
+    use crate::support;
+
+    #[test]
+    fn positive_linear() {
+        let calibration = Calibration::Linear {
+            offset: -1.5,
+            scale: 1.0,
+        };
+
+        assert_eq!(calibration.apply(22.0), 20.5);
+    }
+
+    #[test]
+    fn positive_table_interpolates_and_extends() {
+        let calibration = Calibration::table(vec![(50.0, 49.0), (0.0, 1.0), (100.0, 97.0)]);
+
+        let close = |raw: f32, expected: f32| (calibration.apply(raw) - expected).abs() < 1e-4;
+
+        assert!(close(25.0, 25.0), "Between the first points");
+        assert!(close(75.0, 73.0), "Between the last points");
+        assert!(close(-10.0, -8.6), "Below the table");
+        assert!(close(110.0, 106.6), "Above the table");
+    }
+
+    #[test]
+    fn positive_config_forms() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [devices.kitchen]
+            calibration = { offset = -1.5 }
+
+            [devices.hall]
+            calibration = { points = [[100.0, 99.0], [0.0, 1.0]] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.devices["kitchen"].calibration,
+            Some(Calibration::Linear {
+                offset: -1.5,
+                scale: 1.0
+            })
+        );
+        assert_eq!(
+            config.devices["hall"].calibration,
+            Some(Calibration::Table {
+                points: vec![(0.0, 1.0), (100.0, 99.0)]
+            }),
+            "Points are sorted once, when read"
+        );
+    }
+
+    fn devices(offset: f32) -> HashMap<String, DeviceConfig> {
+        HashMap::from([(
+            "kitchen".to_string(),
+            DeviceConfig {
+                key: Some("secret".into()),
+                calibration: Some(Calibration::Linear { offset, scale: 1.0 }),
+                ..DeviceConfig::default()
+            },
+        )])
+    }
+
+    #[tokio::test]
+    async fn positive_calibrated_on_ingestion() {
+        let state = State::default().with_registry(Registry::new(devices(-1.5), false));
+        let (addr, state) = support::start(state).await;
+
+        let mut client = Client::new(addr).with_key("kitchen", "secret");
+        let reply = client.send(&"Termometer 22 C").await.unwrap();
+        assert_eq!(reply, Reply::Ack(20.5), "Corrected value is stored");
+
+        state
+            .lock()
+            .unwrap()
+            .registry_mut()
+            .update(devices(1.0), false);
+        let reply = client.send(&"Termometer 22 C").await.unwrap();
+        assert_eq!(reply, Reply::Ack(23.0), "New calibration applies at once");
+
+        let state = state.lock().unwrap();
+        let reading = state.history().iter().next().unwrap();
+        assert_eq!(reading.raw, Some(22.0), "Raw value is kept");
+    }
+
+    #[test]
+    fn positive_standalone_by_kind() {
+        let devices = HashMap::from([(
+            "Termometer".to_string(),
+            DeviceConfig {
+                calibration: Some(Calibration::Linear {
+                    offset: 1.0,
+                    scale: 1.0,
+                }),
+                ..DeviceConfig::default()
+            },
+        )]);
+        let mut state = State::default().with_registry(Registry::new(devices, false));
+
+        let mut reading = Reading::new(SensorData::Temperature(20.0), None);
+        assert_eq!(state.apply(&mut reading), Reply::Ack(21.0));
+        assert_eq!(reading.raw, Some(20.0));
+    }
+
+    #[tokio::test]
+    async fn positive_config_file_is_watched() {
+        let path = std::env::temp_dir().join(format!("watch-{}.toml", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let watcher = tokio::spawn(config::watch(
+            path.clone(),
+            Duration::from_millis(10),
+            move |config| tx.send(config).unwrap(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(&path, "[devices.kitchen]\ncalibration = { offset = 2.0 }\n").unwrap();
+
+        let config = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        watcher.abort();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(config.devices["kitchen"].calibration.is_some());
+    }
+}
+
+#[cfg(test)]
+mod simulator_test {
+    use std::time::Duration;
+
+    use otus_tokio_devices::{
+        signal::{Signal, SignalModel},
+        simulator::SimulatorConfig,
+        state::State,
+    };
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use crate::support;
+
+    fn config(seed: u64) -> SimulatorConfig {
+        toml::from_str(&format!(
+            r#"
+            seed = {}
+
+            [[devices]]
+            kind = "Termometer"
+            id = "room"
+            count = 3
+            interval_ms = 10
+            signal = {{ model = "random_walk", start = 21.0, step = 0.5, min = 0.0, max = 100.0 }}
+            "#,
+            seed
+        ))
+        .unwrap()
+    }
+
+    fn run(seed: u64) -> Vec<String> {
+        let mut devices = config(seed).devices();
+
+        (0..10)
+            .flat_map(|i| {
+                let t = Duration::from_secs(i);
+                devices
+                    .iter_mut()
+                    .map(move |d| d.message(t))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn positive_same_seed_same_run() {
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8), "Another seed gives another run");
+    }
+
+    #[test]
+    fn positive_count_expands_ids() {
+        let devices = config(0).devices();
+
+        let ids: Vec<&str> = devices.iter().map(|d| d.id()).collect();
+        assert_eq!(ids, ["room-1", "room-2", "room-3"]);
+    }
+
+    #[tokio::test]
+    async fn negative_zero_interval() {
+        let config: SimulatorConfig = toml::from_str(
+            r#"
+            [[devices]]
+            kind = "Termometer"
+            id = "room"
+            interval_ms = 0
+            signal = { model = "step", levels = [20.0], every_s = 1.0 }
+            "#,
+        )
+        .unwrap();
+        let device = config.devices().remove(0);
+
+        assert_eq!(device.interval(), Duration::from_millis(1));
+        // Нулевой период не должен ронять задачу устройства
+        let client = device.client("127.0.0.1:1");
+        let task = tokio::spawn(device.run(client));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!task.is_finished());
+        task.abort();
+    }
+
+    #[test]
+    fn positive_sine_and_step() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sine = Signal::new(SignalModel::Sine {
+            mean: 20.0,
+            amplitude: 5.0,
+            period_s: 100.0,
+            phase_s: 0.0,
+        });
+        let mut step = Signal::new(SignalModel::Step {
+            levels: vec![500.0, 1500.0],
+            every_s: 10.0,
+        });
+
+        assert!((sine.sample(Duration::from_secs(25), &mut rng) - 25.0).abs() < 1e-3);
+        assert_eq!(step.sample(Duration::from_secs(5), &mut rng), 500.0);
+        assert_eq!(step.sample(Duration::from_secs(15), &mut rng), 1500.0);
+        assert_eq!(step.sample(Duration::from_secs(25), &mut rng), 500.0);
+    }
+
+    #[test]
+    fn positive_random_walk_stays_in_bounds() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut walk = Signal::new(SignalModel::RandomWalk {
+            start: 0.5,
+            step: 1.0,
+            min: Some(0.0),
+            max: Some(1.0),
+        });
+
+        for i in 0..1000 {
+            let v = walk.sample(Duration::from_secs(i), &mut rng);
+            assert!((0.0..=1.0).contains(&v), "Got {}", v);
+        }
+    }
+
+    #[tokio::test]
+    async fn positive_devices_report_to_server() {
+        let (addr, state) = support::start(State::default()).await;
+
+        let tasks: Vec<_> = config(0)
+            .devices()
+            .into_iter()
+            .map(|d| {
+                let client = d.client(&addr);
+                tokio::spawn(d.run(client))
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        tasks.iter().for_each(|t| t.abort());
+
+        assert!(state.lock().unwrap().history().len() >= 3);
+    }
+}
+
+mod fault_test {
+    use std::{str::FromStr, time::Duration};
+
+    use otus_tokio_devices::{
+        client::Client,
+        device::Device,
+        fault::{Fault, FaultKind},
+        reply::Reply,
+        sensor_data::SensorData,
+        simulator::{Action, SimulatorConfig, VirtualDevice},
+        state::State,
+        temperature::Temperature,
+    };
+
+    use crate::support;
+
+    fn termometer(faults: &str) -> VirtualDevice {
+        let config: SimulatorConfig = toml::from_str(&format!(
+            r#"
+            [[devices]]
+            kind = "Termometer"
+            id = "room"
+            interval_ms = 10
+            signal = {{ model = "step", levels = [20.0, 30.0], every_s = 1.0 }}
+            faults = {}
+            "#,
+            faults
+        ))
+        .unwrap();
+
+        config.devices().remove(0)
+    }
+
+    fn value(action: &Action) -> f32 {
+        SensorData::from_str(&action.message().message)
+            .unwrap()
+            .value()
+            .unwrap()
+    }
+
+    #[test]
+    fn positive_stuck_in_window() {
+        let mut device = termometer(r#"[{ fault = "stuck", from_s = 1.0, until_s = 3.0 }]"#);
+
+        let values: Vec<f32> = (0..4)
+            .map(|i| value(&device.next(Duration::from_secs(i))))
+            .collect();
+        assert_eq!(values, [20.0, 20.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn positive_spike() {
+        let mut device = termometer(r#"[{ fault = "spike", magnitude = 15.0 }]"#);
+
+        let v = value(&device.next(Duration::ZERO));
+        assert!(v == 5.0 || v == 35.0, "Got {}", v);
+    }
+
+    #[test]
+    fn positive_probability_replays_with_seed() {
+        let faults = r#"[{ fault = "out_of_range", probability = 0.3 }]"#;
+        let run = || {
+            let mut device = termometer(faults);
+            (0..100)
+                .map(|i| value(&device.next(Duration::from_secs(i))))
+                .collect::<Vec<_>>()
+        };
+
+        let values = run();
+        let struck = values
+            .iter()
+            .filter(|&&v| v > Temperature::MAX_TEMPERATURE)
+            .count();
+        assert!((10..60).contains(&struck), "Struck {} times", struck);
+        assert_eq!(values, run());
+    }
+
+    #[test]
+    fn positive_clock_drift() {
+        let mut device = termometer(r#"[{ fault = "clock_drift", rate = 0.5 }]"#);
+
+        let time = device.next(Duration::from_secs(60)).message().time.unwrap();
+        let ahead = time - chrono::Utc::now();
+        assert!((29..=30).contains(&ahead.num_seconds()), "Ahead {}", ahead);
+    }
+
+    #[test]
+    fn positive_transport_faults() {
+        let mut device = termometer("[]").with_faults(vec![
+            Fault::new(FaultKind::SlowWrite {
+                chunk: 3,
+                delay_ms: 5,
+            }),
+            Fault {
+                from_s: 10.0,
+                ..Fault::new(FaultKind::Disconnect)
+            },
+        ]);
+
+        assert!(matches!(
+            device.next(Duration::ZERO),
+            Action::SendSlowly { chunk: 3, .. }
+        ));
+        assert!(matches!(
+            device.next(Duration::from_secs(10)),
+            Action::Disconnect(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn negative_out_of_range_and_malformed_rejected() {
+        let (addr, state) = support::start(State::default()).await;
+        let mut client = Client::new(&addr);
+        let mut device = termometer(r#"[{ fault = "out_of_range", from_s = 1.0 }]"#);
+
+        let ok = client.send(device.next(Duration::ZERO).message()).await;
+        assert!(ok.unwrap().is_ack());
+        let out = client
+            .send(device.next(Duration::from_secs(1)).message())
+            .await;
+        assert_eq!(out.unwrap(), Reply::Ack(20.0), "Keeps the last good value");
+
+        let mut device = termometer(r#"[{ fault = "malformed" }]"#);
+        for i in 0..5 {
+            let reply = client
+                .send(device.next(Duration::from_secs(i)).message())
+                .await;
+            assert!(!reply.unwrap().is_ack());
+        }
+
+        assert_eq!(state.lock().unwrap().history().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn positive_server_survives_slow_writes_and_disconnects() {
+        let (addr, state) = support::start(State::default()).await;
+        let mut client = Client::new(&addr);
+        let message = format!("Termometer {} C", 22.5);
+
+        let reply = client
+            .send_slowly(&message, 2, Duration::from_millis(1))
+            .await;
+        assert_eq!(reply.unwrap(), Reply::Ack(22.5));
+
+        client.abort(&"Termometer 99 C").await.unwrap();
+        let reply = client.send(&"Termometer 23.5 C").await;
+        assert_eq!(reply.unwrap(), Reply::Ack(23.5), "Reconnects after the cut");
+
+        assert_eq!(state.lock().unwrap().termometer().reading(), 23.5);
+    }
+}
+
+mod room_test {
+    use std::{str::FromStr, time::Duration};
+
+    use otus_tokio_devices::{
+        device::Device,
+        room::{Room, RoomConfig},
+        simulator::{SimulatorConfig, VirtualRoom},
+        socket::SocketCommand,
+        state::State,
+    };
+
+    use crate::support;
+
+    fn config() -> RoomConfig {
+        let config: SimulatorConfig = toml::from_str(
+            r#"
+            [[rooms]]
+            termometer = "room"
+            heater = "heater"
+            interval_ms = 10
+            speed = 3600.0
+            thermal_mass = 2.0e5
+            loss = 40.0
+            outdoor = 5.0
+            initial = 18.0
+            heater_power = 1000.0
+            "#,
+        )
+        .unwrap();
+
+        config.rooms[0].clone()
+    }
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn positive_socket_commands() {
+        assert_eq!(SocketCommand::from_str("on").unwrap(), SocketCommand::On);
+        assert_eq!(
+            SocketCommand::from_str("power 1500").unwrap(),
+            SocketCommand::Power(1500.0)
+        );
+        assert_eq!(SocketCommand::Power(1500.0).to_string(), "power 1500");
+        assert!(SocketCommand::from_str("toggle").is_err());
+        assert!(SocketCommand::from_str("off now").is_err());
+    }
+
+    #[test]
+    fn positive_heater_warms_room_to_steady_state() {
+        let mut room = Room::new(config());
+        room.command(SocketCommand::On);
+
+        room.step(HOUR);
+        let after_hour = room.temperature();
+        assert!(after_hour > 18.0 && after_hour < 30.0, "Got {}", after_hour);
+
+        room.step(HOUR * 100);
+        let steady = 5.0 + 1000.0 / 40.0;
+        assert!((room.temperature() - steady).abs() < 1e-3);
+    }
+
+    #[test]
+    fn negative_heater_power_out_of_range() {
+        let config = |power: f32| {
+            toml::from_str::<SimulatorConfig>(&format!(
+                r#"
+                [[rooms]]
+                termometer = "room"
+                heater = "heater"
+                thermal_mass = 2.0e5
+                loss = 40.0
+                outdoor = 5.0
+                initial = 18.0
+                heater_power = {:.1}
+                "#,
+                power
+            ))
+        };
+
+        assert!(config(1000.0).is_ok());
+        let error = config(3000.0).unwrap_err().to_string();
+        assert!(error.contains("heater power 3000 W"), "{}", error);
+        assert!(config(0.0).is_err());
+    }
+
+    #[test]
+    fn negative_non_physical_room() {
+        let config = |thermal_mass: f64, loss: f64| {
+            toml::from_str::<SimulatorConfig>(&format!(
+                r#"
+                [[rooms]]
+                termometer = "room"
+                heater = "heater"
+                thermal_mass = {:.1}
+                loss = {:.1}
+                outdoor = 5.0
+                initial = 18.0
+                heater_power = 1000.0
+                "#,
+                thermal_mass, loss
+            ))
+        };
+
+        assert!(config(2.0e5, 0.0).is_ok(), "A perfectly insulated room");
+        let error = config(0.0, 40.0).unwrap_err().to_string();
+        assert!(error.contains("must be above zero"), "{}", error);
+        assert!(config(-2.0e5, 40.0).is_err());
+        assert!(config(2.0e5, -1.0).is_err());
+    }
+
+    #[test]
+    fn positive_step_size_does_not_matter() {
+        let mut coarse = Room::new(config());
+        let mut fine = Room::new(config());
+        coarse.command(SocketCommand::On);
+        fine.command(SocketCommand::On);
+
+        coarse.step(HOUR);
+        (0..3600).for_each(|_| fine.step(Duration::from_secs(1)));
+        assert!((coarse.temperature() - fine.temperature()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn positive_room_cools_to_outdoor_when_off() {
+        let mut room = Room::new(config());
+        room.command(SocketCommand::On);
+        room.command(SocketCommand::Off);
+        assert!(!room.heater().is_on());
+
+        room.step(HOUR * 100);
+        assert!((room.temperature() - 5.0).abs() < 1e-3);
+
+        room.command(SocketCommand::On);
+        assert_eq!(room.heater().reading(), 1000.0, "Rated power is back");
+    }
+
+    #[tokio::test]
+    async fn positive_commands_change_reported_temperature() {
+        let (addr, state) = support::start(State::default()).await;
+
+        let room = VirtualRoom::new(RoomConfig {
+            speed: 360_000.0,
+            ..config()
+        });
+        let handle = room.room();
+        let (termometer, heater) = room.clients(&addr);
+        let task = tokio::spawn(room.run(termometer, heater));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let cold = state.lock().unwrap().termometer().reading();
+        assert!(cold < 18.0, "Cools down without heating, got {}", cold);
+
+        handle.lock().unwrap().command(SocketCommand::On);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        task.abort();
+
+        let state = state.lock().unwrap();
+        assert!(state.termometer().reading() > cold + 5.0);
+        assert!(state.socket().is_on());
+        assert_eq!(state.socket().reading(), 1000.0);
+    }
+}
+
+mod load_test {
+    use std::time::Duration;
+
+    use otus_tokio_devices::{
+        load::{self, LoadConfig, Percentiles},
+        server,
+        state::State,
+    };
+    use tokio::net::UdpSocket;
+
+    use crate::support;
+
+    /// Serves a default state over both TCP and UDP.
+    async fn start() -> (String, String) {
+        let (tcp, state) = support::start(State::default()).await;
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap().to_string();
+        tokio::spawn(server::serve_udp(udp, state, support::drain()));
+
+        (tcp, addr)
+    }
+
+    #[test]
+    fn positive_percentiles() {
+        let mut latencies: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+
+        let p = Percentiles::new(&mut latencies);
+        assert_eq!((p.p50, p.p90, p.p99, p.max), (50.0, 90.0, 99.0, 100.0));
+        assert_eq!(Percentiles::new(&mut []), Percentiles::default());
+    }
+
+    #[tokio::test]
+    async fn positive_udp_replies_per_line() {
+        let (_, udp) = start().await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(&udp).await.unwrap();
+
+        socket.send(b"Termometer 20.5 C\nnonsense\n").await.unwrap();
+        let mut buf = [0; 256];
+        let len = socket.recv(&mut buf).await.unwrap();
+
+        let replies = String::from_utf8_lossy(&buf[..len]);
+        let replies: Vec<&str> = replies.lines().collect();
+        assert_eq!(replies[0], "Ack 20.5");
+        assert!(replies[1].starts_with("Nack"));
+    }
+
+    #[tokio::test]
+    async fn positive_default_load_within_default_limits() {
+        let (tcp, _) = start().await;
+        let config = LoadConfig {
+            server: tcp,
+            rate: 10.0,
+            duration_s: 0.3,
+            ..LoadConfig::default()
+        };
+
+        let summary = load::run(&config).await.unwrap();
+
+        assert_eq!(summary.tcp.connections, config.tcp_connections);
+        assert_eq!(summary.tcp.acked, summary.tcp.sent, "{}", summary.tcp);
+        assert_eq!(summary.tcp.errors + summary.tcp.nacked, 0);
+    }
+
+    #[tokio::test]
+    async fn positive_load_over_tcp_and_udp() {
+        let (tcp, udp) = start().await;
+        let config = LoadConfig {
+            server: tcp,
+            udp: Some(udp),
+            tcp_connections: 50,
+            udp_connections: 20,
+            rate: 20.0,
+            duration_s: 0.5,
+            ..LoadConfig::default()
+        };
+
+        let summary = load::run(&config).await.unwrap();
+
+        for stats in [&summary.tcp, &summary.udp] {
+            assert!(stats.sent >= stats.connections as u64, "{}", stats);
+            assert_eq!(stats.acked, stats.sent, "{}", stats);
+            assert_eq!(stats.errors + stats.dropped + stats.nacked, 0);
+            assert!(stats.throughput > 0.0);
+            assert!(stats.latency_ms.p99 <= stats.latency_ms.max);
+        }
+        assert_eq!(summary.tcp.connections, 50);
+
+        let json: serde_json::Value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["udp"]["connections"], 20);
+        assert!(json["tcp"]["latency_ms"]["p50"].is_number());
+    }
+
+    #[tokio::test]
+    async fn negative_unreachable_server_counts_errors() {
+        let addr = support::vacant().await;
+
+        let config = LoadConfig {
+            server: addr,
+            tcp_connections: 3,
+            rate: 50.0,
+            duration_s: 0.1,
+            ..LoadConfig::default()
+        };
+        let summary = load::run(&config).await.unwrap();
+
+        assert!(summary.tcp.errors > 0);
+        assert_eq!(summary.tcp.acked, 0);
+    }
+}
+
+mod outbox_test {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{TimeDelta, Utc};
+    use otus_tokio_devices::{
+        client::{Client, Delivery},
+        limits::{LimitsConfig, RateLimit},
+        outbox::{DropPolicy, Outbox},
+        reading::Stamped,
+        reply::Reply,
+        sensor_data::SensorData,
+        state::State,
+    };
+
+    use crate::support;
+
+    fn reading(value: f32, minutes_ago: i64) -> Stamped<SensorData> {
+        let time = Utc::now() - TimeDelta::minutes(minutes_ago);
+        Stamped::new(SensorData::Temperature(value), Some(time))
+    }
+
+    fn values(outbox: &mut Outbox) -> Vec<f32> {
+        outbox
+            .peek(usize::MAX)
+            .unwrap()
+            .iter()
+            .filter_map(|r| r.message.value())
+            .collect()
+    }
+
+    #[test]
+    fn positive_drop_policy() {
+        let mut oldest = Outbox::new(2);
+        let mut newest = Outbox::new(2).with_policy(DropPolicy::Newest);
+        for v in [1.0, 2.0, 3.0] {
+            oldest.push(reading(v, 0)).unwrap();
+            newest.push(reading(v, 0)).unwrap();
+        }
+
+        assert_eq!(values(&mut oldest), [2.0, 3.0]);
+        assert_eq!(values(&mut newest), [1.0, 2.0]);
+        assert_eq!((oldest.dropped(), newest.dropped()), (1, 1));
+    }
+
+    #[test]
+    fn positive_spill_keeps_order_across_restart() {
+        let path = std::env::temp_dir().join(format!("outbox-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut outbox = Outbox::new(10).with_spill(&path, 2).unwrap();
+        for v in 1..=5 {
+            outbox.push(reading(v as f32, 0)).unwrap();
+        }
+        assert_eq!(outbox.len(), 5);
+        let spilled = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(spilled.lines().count(), 3);
+        drop(outbox);
+
+        let mut outbox = Outbox::new(10).with_spill(&path, 2).unwrap();
+        assert_eq!(outbox.len(), 3, "Memory is lost, the spill file is not");
+        assert_eq!(values(&mut outbox), [3.0, 4.0]);
+        outbox.remove(2);
+        outbox.push(reading(6.0, 0)).unwrap();
+        assert_eq!(values(&mut outbox), [5.0, 6.0]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn positive_store_and_forward() {
+        let addr = support::vacant().await;
+
+        let mut client = Client::new(&addr).with_outbox(Outbox::new(10));
+        for (i, v) in [20.0, 21.0, 22.0].into_iter().enumerate() {
+            let delivery = client.report(reading(v, 10 - i as i64)).await.unwrap();
+            assert!(
+                matches!(delivery, Delivery::Queued { queued, .. } if queued == i + 1),
+                "{}",
+                delivery
+            );
+        }
+
+        let state = Arc::new(Mutex::new(State::default()));
+        support::serve(&addr, &state, support::drain()).await;
+
+        let delivery = client.report(reading(23.0, 0)).await.unwrap();
+        assert_eq!(delivery, Delivery::Delivered(Reply::AckBatch(4)));
+        assert!(client.outbox().unwrap().is_empty());
+
+        let state = state.lock().unwrap();
+        let history: Vec<_> = state.history().iter().collect();
+        let sent: Vec<f32> = history.iter().filter_map(|r| r.data.value()).collect();
+        assert_eq!(sent, [20.0, 21.0, 22.0, 23.0]);
+        let age = Utc::now() - history[0].time();
+        assert!(age > TimeDelta::minutes(9), "Original timestamps are kept");
+        assert!(age < TimeDelta::minutes(11));
+    }
+
+    #[tokio::test]
+    async fn positive_full_outbox_within_default_limits() {
+        let addr = support::vacant().await;
+
+        // Отметки времени с наносекундами дают самые длинные кадры
+        let mut client = Client::new(&addr).with_outbox(Outbox::new(1000));
+        for i in 0..250 {
+            let reading = Stamped::new(SensorData::Temperature(20.125), Some(Utc::now()));
+            let delivery = client.report(reading).await.unwrap();
+            assert!(matches!(delivery, Delivery::Queued { queued, .. } if queued == i + 1));
+        }
+
+        let state = Arc::new(Mutex::new(State::default()));
+        support::serve(&addr, &state, support::drain()).await;
+
+        let reply = client.flush().await.unwrap();
+
+        assert!(matches!(reply, Some(Reply::AckBatch(_))), "{:?}", reply);
+        assert!(client.outbox().unwrap().is_empty());
+        assert_eq!(state.lock().unwrap().history().len(), 250);
+    }
+
+    #[tokio::test]
+    async fn negative_refused_reading_is_dropped_alone() {
+        let addr = support::vacant().await;
+
+        let mut client = Client::new(&addr).with_outbox(Outbox::new(10));
+        for (i, v) in [20.0, 21.0, 150.0, 22.0, 23.0].into_iter().enumerate() {
+            client.report(reading(v, 10 - i as i64)).await.unwrap();
+        }
+
+        let state = Arc::new(Mutex::new(State::default()));
+        support::serve(&addr, &state, support::drain()).await;
+
+        client.flush().await.unwrap();
+
+        assert!(client.outbox().unwrap().is_empty());
+        let state = state.lock().unwrap();
+        let sent: Vec<f32> = state
+            .history()
+            .iter()
+            .filter_map(|r| r.data.value())
+            .collect();
+        assert_eq!(
+            sent,
+            [20.0, 21.0, 22.0, 23.0],
+            "Only the refused reading is lost"
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_busy_server_keeps_the_outbox() {
+        let state = State::default().with_limits(LimitsConfig {
+            device_rate: Some(RateLimit {
+                rate: 0.001,
+                burst: 3,
+            }),
+            ..LimitsConfig::default()
+        });
+        let (addr, state) = support::start(state).await;
+
+        let mut client = Client::new(addr).with_outbox(Outbox::new(100));
+        for i in 0..20 {
+            let delivery = client.report(reading(20.0, 20 - i)).await.unwrap();
+            assert_ne!(delivery, Delivery::Dropped);
+        }
+
+        assert_eq!(state.lock().unwrap().history().len(), 3, "The burst only");
+        assert_eq!(
+            client.outbox().unwrap().len(),
+            17,
+            "Readings the server is busy for are kept"
+        );
+        let error = client.flush().await.unwrap_err();
+        assert!(error.to_string().contains("server busy"), "{}", error);
+        assert_eq!(client.outbox().unwrap().len(), 17);
+    }
+
+    #[tokio::test]
+    async fn negative_full_outbox_reports_dropped() {
+        let addr = support::vacant().await;
+
+        let outbox = Outbox::new(1).with_policy(DropPolicy::Newest);
+        let mut client = Client::new(&addr).with_outbox(outbox);
+        let first = client.report(reading(20.0, 1)).await.unwrap();
+        let second = client.report(reading(21.0, 0)).await.unwrap();
+        let mut nothing = Client::new(&addr).with_outbox(Outbox::new(0));
+        let third = nothing.report(reading(22.0, 0)).await.unwrap();
+
+        assert!(
+            matches!(first, Delivery::Queued { queued: 1, .. }),
+            "{}",
+            first
+        );
+        assert_eq!(second, Delivery::Dropped);
+        assert_eq!(third, Delivery::Dropped);
+        assert!(nothing.outbox().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn negative_without_outbox_error_is_returned() {
+        let addr = support::vacant().await;
+
+        let mut client = Client::new(&addr);
+        assert!(client.report(reading(20.0, 0)).await.is_err());
+    }
+}
+
+mod house_test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use otus_tokio_devices::{
+        client::Client,
+        house::{DevicePath, House, HouseError, HouseRoom, SmartDevice},
+        registry::DeviceConfig,
+        reply::Reply,
+        sensor_data::SensorData,
+        state::State,
+    };
+
+    use crate::support;
+
+    fn house() -> House {
+        let bathroom = r#"
+            [[rooms]]
+            name = "bathroom"
+            devices = [{ id = "hygrometer", kind = "Hygrometer" }]
+            "#;
+
+        support::house(&format!(
+            "name = \"Дача\"\n{}{}",
+            support::KITCHEN,
+            bathroom
+        ))
+    }
+
+    async fn start() -> (String, Arc<Mutex<State>>) {
+        support::start(State::default().with_house(house())).await
+    }
+
+    fn value(state: &State, path: &str) -> f32 {
+        let path: DevicePath = path.parse().unwrap();
+        state.house().device(&path).unwrap().data().value().unwrap()
+    }
+
+    #[test]
+    fn positive_rooms_keep_order() {
+        let house = house();
+
+        let rooms: Vec<&str> = house.rooms().map(|r| r.name()).collect();
+        assert_eq!(rooms, ["kitchen", "bathroom"]);
+        let kitchen: Vec<&str> = house
+            .room("kitchen")
+            .unwrap()
+            .devices()
+            .map(|d| d.id())
+            .collect();
+        assert_eq!(kitchen, ["termometer", "kettle"]);
+    }
+
+    #[test]
+    fn negative_duplicates_and_unknowns() {
+        let mut house = house();
+
+        let duplicate = house.add_room(HouseRoom::new("kitchen").unwrap());
+        assert_eq!(duplicate, Err(HouseError::DuplicateRoom("kitchen".into())));
+
+        let kitchen = house.room_mut("kitchen").unwrap();
+        let duplicate = kitchen.add_device(SmartDevice::new("Socket", "kettle").unwrap());
+        assert_eq!(
+            duplicate,
+            Err(HouseError::DuplicateDevice(DevicePath::new(
+                "kitchen", "kettle"
+            )))
+        );
+
+        assert!(matches!(
+            house.room("garage"),
+            Err(HouseError::UnknownRoom(_))
+        ));
+        let missing = DevicePath::new("kitchen", "fridge");
+        assert!(matches!(
+            house.device(&missing),
+            Err(HouseError::UnknownDevice(_))
+        ));
+        assert!(matches!(
+            SmartDevice::new("Toaster", "t"),
+            Err(HouseError::UnknownKind(_))
+        ));
+        assert!(HouseRoom::new("living room").is_err());
+        assert!("kitchen".parse::<DevicePath>().is_err());
+        assert!("a/b/c".parse::<DevicePath>().is_err());
+    }
+
+    #[tokio::test]
+    async fn positive_addressed_readings_update_house() {
+        let (addr, state) = start().await;
+        let mut client = Client::new(&addr);
+
+        let reply = client.send(&"kitchen/termometer Termometer 22.5 C").await;
+        assert_eq!(reply.unwrap(), Reply::Ack(22.5));
+        let mut kettle = Client::new(&addr).with_address("kitchen/kettle");
+        let reply = kettle.send(&"Socket 1800 W").await;
+        assert_eq!(reply.unwrap(), Reply::Ack(1800.0));
+        let reply = client
+            .send(&"Batch bathroom/hygrometer Hygrometer 60 %")
+            .await;
+        assert!(!reply.unwrap().is_ack(), "The address goes before Batch");
+        let reply = client
+            .send(&"bathroom/hygrometer Batch Hygrometer 60 %; Hygrometer 65 %")
+            .await;
+        assert_eq!(reply.unwrap(), Reply::AckBatch(2));
+
+        let state = state.lock().unwrap();
+        assert_eq!(value(&state, "kitchen/termometer"), 22.5);
+        assert_eq!(value(&state, "kitchen/kettle"), 1800.0);
+        assert_eq!(value(&state, "bathroom/hygrometer"), 65.0);
+        assert_eq!(
+            state.termometer().temperature().get(),
+            0.0,
+            "The standalone termometer is left alone"
+        );
+        let last = state.history().iter().last().unwrap();
+        assert_eq!(last.device.as_deref(), Some("bathroom/hygrometer"));
+        assert_eq!(last.data, SensorData::Humidity(65.0));
+    }
+
+    #[tokio::test]
+    async fn negative_unknown_or_mismatched_address() {
+        let (addr, state) = start().await;
+        let mut client = Client::new(&addr);
+
+        for (frame, reason) in [
+            ("garage/termometer Termometer 20 C", "unknown room garage"),
+            (
+                "kitchen/fridge Termometer 20 C",
+                "unknown device kitchen/fridge",
+            ),
+            (
+                "kitchen/kettle Termometer 20 C",
+                "kitchen/kettle is a Socket, not Termometer",
+            ),
+        ] {
+            let reply = client.send(&frame).await.unwrap();
+            match reply {
+                Reply::Nack(nack) => assert!(nack.starts_with(reason), "{}", nack),
+                reply => panic!("{} accepted: {}", frame, reply),
+            }
+        }
+
+        assert!(state.lock().unwrap().history().is_empty());
+    }
+
+    #[tokio::test]
+    async fn negative_unaddressed_reading() {
+        let (addr, state) = start().await;
+        let mut client = Client::new(&addr);
+
+        let reply = client.send(&"Termometer 20 C").await.unwrap();
+
+        assert!(matches!(reply, Reply::Nack(reason) if reason.contains("not addressed")));
+        let state = state.lock().unwrap();
+        assert!(state.history().is_empty());
+        assert!(!state.devices().contains(&"Termometer".to_string()));
+    }
+
+    #[tokio::test]
+    async fn negative_unsigned_frame_for_keyed_device() {
+        let (addr, state) = start().await;
+        let devices = HashMap::from([(
+            "kitchen/termometer".to_string(),
+            DeviceConfig {
+                key: Some("secret".into()),
+                ..Default::default()
+            },
+        )]);
+        state.lock().unwrap().registry_mut().update(devices, false);
+        let mut client = Client::new(&addr);
+
+        let reply = client.send(&"kitchen/termometer Termometer 20 C").await;
+        assert_eq!(
+            reply.unwrap(),
+            Reply::Nack("kitchen/termometer must sign its messages".into())
+        );
+        let reply = client.send(&"kitchen/kettle Socket 1000 W").await;
+        assert_eq!(
+            reply.unwrap(),
+            Reply::Ack(1000.0),
+            "Devices without a key may still send unsigned frames"
+        );
+        let mut signed = Client::new(&addr).with_key("kitchen/termometer", "secret");
+        let reply = signed.send(&"Termometer 20 C").await;
+        assert_eq!(reply.unwrap(), Reply::Ack(20.0));
+    }
+}
+
+mod report_test {
+
+    use chrono::{TimeDelta, Utc};
+    use otus_tokio_devices::{
+        client::Client,
+        reading::Reading,
+        reply::Reply,
+        report::{Format, Report},
+        sensor_data::SensorData,
+        socket::SocketReading,
+        state::State,
+    };
+
+    use crate::support;
+
+    fn state() -> State {
+        let house = support::house(&format!("name = \"Дача\"\n{}", support::KITCHEN));
+        let mut state = State::default().with_house(house);
+
+        let now = Utc::now();
+        let mut apply = |path: &str, data, ago: TimeDelta| {
+            let mut reading =
+                Reading::new(data, Some(now - ago)).with_device(Some(path.to_string()));
+            state.apply(&mut reading)
+        };
+        apply(
+            "kitchen/kettle",
+            SensorData::Socket(SocketReading::on(1000.0)),
+            TimeDelta::hours(3),
+        );
+        apply(
+            "kitchen/kettle",
+            SensorData::Socket(SocketReading::off()),
+            TimeDelta::hours(2),
+        );
+        apply(
+            "kitchen/termometer",
+            SensorData::Temperature(22.5),
+            TimeDelta::zero(),
+        );
+        apply(
+            "kitchen/termometer",
+            SensorData::Temperature(150.0),
+            TimeDelta::zero(),
+        );
+
+        state
+    }
+
+    #[test]
+    fn positive_values_violations_energy() {
+        let report = Report::new(&state(), Utc::now());
+
+        let devices: Vec<_> = report.devices().collect();
+        assert_eq!(devices.len(), 2);
+        let (termometer, kettle) = (devices[0], devices[1]);
+        assert_eq!(termometer.value, 22.5);
+        assert_eq!(termometer.violations, 1);
+        assert_eq!(termometer.last_violation, Some(150.0));
+        assert!(termometer.last_seen.is_some());
+        assert_eq!(kettle.on, Some(false));
+        assert!((kettle.energy_wh.unwrap() - 1000.0).abs() < 0.1);
+        assert!((report.energy_wh() - 1000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn positive_formats() {
+        let report = Report::new(&state(), Utc::now());
+
+        let text = report.render(Format::Text);
+        assert!(text.starts_with("Дача status at"));
+        assert!(text.contains("termometer (Termometer): 22.5 C"));
+        assert!(text.contains("1 out of range (last 150)"));
+        assert!(text.contains("Energy total: 1000.0 Wh"));
+
+        let markdown = report.render(Format::Markdown);
+        assert!(markdown.contains("## kitchen"));
+        assert!(markdown.contains("| kettle | Socket | off |"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.render(Format::Json)).unwrap();
+        assert_eq!(json["rooms"][0]["devices"][0]["value"], 22.5);
+        assert_eq!(json["rooms"][0]["devices"][1]["on"], false);
+    }
+
+    #[test]
+    fn positive_standalone_devices_without_house() {
+        let mut state = State::default();
+        state.apply(&mut Reading::new(SensorData::Humidity(40.0), None));
+
+        let report = Report::new(&state, Utc::now());
+
+        assert_eq!(report.rooms.len(), 1);
+        let kinds: Vec<&str> = report.devices().map(|d| d.kind).collect();
+        assert_eq!(kinds, ["Termometer", "Socket", "Hygrometer"]);
+        assert!(report.devices().nth(2).unwrap().last_seen.is_some());
+        assert!(report.devices().next().unwrap().last_seen.is_none());
+    }
+
+    #[test]
+    fn positive_write_file() {
+        let path = std::env::temp_dir().join(format!("report-{}.md", std::process::id()));
+
+        Report::new(&state(), Utc::now())
+            .write(&path, Format::Markdown)
+            .unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("# Дача status at"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn positive_report_command() {
+        let (addr, _) = support::start(state()).await;
+        let mut client = Client::new(&addr);
+
+        let Reply::Report(report) = client.send(&"Report markdown").await.unwrap() else {
+            panic!("Expected a report");
+        };
+        assert!(report.contains("## kitchen"));
+
+        let reply = client.send(&"Report").await.unwrap();
+        assert!(matches!(reply, Reply::Report(text) if text.contains("## kitchen")));
+        let reply = client.send(&"Report text").await.unwrap();
+        assert!(matches!(reply, Reply::Report(text) if text.contains("kitchen:")));
+        let reply = client.send(&"Report yaml").await.unwrap();
+        assert!(!reply.is_ack());
+        let reply = client.send(&"kitchen/termometer Termometer 23 C").await;
+        assert_eq!(reply.unwrap(), Reply::Ack(23.0), "Stream stays in sync");
+    }
+
+    #[test]
+    fn positive_report_reply_roundtrip() {
+        let reply = Reply::Report("line 1\nline 2\n".into());
+
+        assert_eq!(reply.to_string(), "Report 2\nline 1\nline 2");
+        assert_eq!(
+            reply.to_string().parse::<Reply>().unwrap(),
+            Reply::Report("line 1\nline 2".into())
+        );
+        assert!("Report 3\nline 1".parse::<Reply>().is_err());
+    }
+}
+
+mod presence_test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use chrono::{TimeDelta, Utc};
+    use otus_tokio_devices::{
+        client::Client,
+        event::DeviceEvent,
+        presence::{Presence, PresenceConfig},
+        reading::Reading,
+        registry::{DeviceConfig, Registry},
+        reply::Reply,
+        sensor_data::SensorData,
+        state::State,
+    };
+    use tokio::sync::mpsc;
+
+    use crate::support;
+
+    #[test]
+    fn positive_thresholds() {
+        let config = PresenceConfig {
+            stale_s: 10,
+            offline_s: 30,
+        };
+        let now = Utc::now();
+
+        assert_eq!(config.presence(Some(now), now), Presence::Online);
+        assert_eq!(
+            config.presence(Some(now - TimeDelta::seconds(10)), now),
+            Presence::Online
+        );
+        assert_eq!(
+            config.presence(Some(now - TimeDelta::seconds(11)), now),
+            Presence::Stale
+        );
+        assert_eq!(
+            config.presence(Some(now - TimeDelta::seconds(31)), now),
+            Presence::Offline
+        );
+        assert_eq!(config.presence(None, now), Presence::Offline);
+    }
+
+    #[test]
+    fn positive_presence_changes() {
+        let mut state = State::default()
+            .with_house(support::house(support::KITCHEN))
+            .with_presence(PresenceConfig {
+                stale_s: 10,
+                offline_s: 30,
+            });
+        let now = Utc::now();
+
+        let reply = state.heartbeat(Some("kitchen/termometer"), None);
+        assert!(reply.is_ack());
+        assert!(state.last_seen("kitchen/termometer").is_some());
+
+        let changes = state.presence_changes(now);
+        assert_eq!(changes.len(), 1, "Devices start offline");
+        assert!(matches!(
+            &changes[0],
+            DeviceEvent::Presence { device, presence: Presence::Online, last_seen: Some(_) }
+                if device == "kitchen/termometer"
+        ));
+        assert!(state.presence_changes(now).is_empty());
+
+        let changes = state.presence_changes(now + TimeDelta::seconds(20));
+        assert!(matches!(
+            &changes[..],
+            [DeviceEvent::Presence {
+                presence: Presence::Stale,
+                ..
+            }]
+        ));
+        let changes = state.presence_changes(now + TimeDelta::seconds(40));
+        assert!(matches!(
+            &changes[..],
+            [DeviceEvent::Presence {
+                presence: Presence::Offline,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn positive_readings_count_as_heartbeats() {
+        let mut state = State::default();
+
+        state.apply(&mut Reading::new(SensorData::Humidity(40.0), None));
+
+        assert_eq!(state.presence("Hygrometer", Utc::now()), Presence::Online);
+        assert_eq!(state.presence("Termometer", Utc::now()), Presence::Offline);
+    }
+
+    #[test]
+    fn positive_device_override() {
+        let devices = HashMap::from([(
+            "kitchen/kettle".to_string(),
+            DeviceConfig {
+                presence: Some(PresenceConfig {
+                    stale_s: 1,
+                    offline_s: 2,
+                }),
+                ..DeviceConfig::default()
+            },
+        )]);
+        let mut state = State::default()
+            .with_house(support::house(support::KITCHEN))
+            .with_registry(Registry::new(devices, false));
+        state.heartbeat(Some("kitchen/kettle"), None);
+        state.heartbeat(Some("kitchen/termometer"), None);
+
+        let later = Utc::now() + TimeDelta::seconds(5);
+        assert_eq!(state.presence("kitchen/kettle", later), Presence::Offline);
+        assert_eq!(
+            state.presence("kitchen/termometer", later),
+            Presence::Online
+        );
+    }
+
+    #[test]
+    fn negative_unknown_heartbeat() {
+        let mut state = State::default().with_house(support::house(support::KITCHEN));
+
+        assert!(!state.heartbeat(Some("kitchen/fridge"), None).is_ack());
+        assert!(!state.heartbeat(None, Some("Fridge")).is_ack());
+        assert!(!state.heartbeat(None, None).is_ack());
+        assert!(state.last_seen("kitchen/fridge").is_none());
+    }
+
+    #[tokio::test]
+    async fn positive_heartbeat_over_tcp() {
+        let state = Arc::new(Mutex::new(
+            State::default().with_house(support::house(support::KITCHEN)),
+        ));
+        let (tx, mut rx) = mpsc::channel(32);
+        let addr = support::serve("127.0.0.1:0", &state, tx).await;
+        let mut client = Client::new(&addr);
+
+        let reply = client.send(&"kitchen/termometer Heartbeat").await.unwrap();
+        assert_eq!(reply, Reply::Ack(0.0));
+        let reply = client.send(&"Heartbeat Termometer").await.unwrap();
+        assert!(reply.is_ack());
+
+        assert!(
+            rx.try_recv().is_err(),
+            "Heartbeats are not shown as readings"
+        );
+        let state = state.lock().unwrap();
+        assert!(state.last_seen("kitchen/termometer").is_some());
+        assert!(state.last_seen("Termometer").is_some());
+    }
+}
+
+mod poll_test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use otus_tokio_devices::{
+        client::Client,
+        event::DeviceEvent,
+        poll::{self, PollConfig, Poller},
+        reply::Reply,
+        sensor_data::SensorData,
+        state::State,
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        sync::mpsc,
+    };
+
+    use crate::support;
+
+    /// Device answering every query with `reply`, after dropping the first
+    /// `drop` connections without a word.
+    async fn device(reply: &'static str, drop: usize) -> String {
+        let (listener, addr) = support::listener().await;
+
+        tokio::spawn(async move {
+            let mut dropped = 0;
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                if dropped < drop {
+                    dropped += 1;
+                    continue;
+                }
+                tokio::spawn(async move {
+                    let mut lines = BufReader::new(tcp).lines();
+                    while let Ok(Some(query)) = lines.next_line().await {
+                        let answer = match query.as_str() {
+                            "report" => format!("{}\n", reply),
+                            _ => "unknown command\n".to_string(),
+                        };
+                        let _ = lines.get_mut().write_all(answer.as_bytes()).await;
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    fn config(address: String) -> PollConfig {
+        PollConfig {
+            interval_ms: 50,
+            timeout_ms: 200,
+            ..PollConfig::new("kitchen/termometer", address)
+        }
+    }
+
+    fn state() -> State {
+        State::default().with_house(support::house(support::KITCHEN))
+    }
+
+    #[tokio::test]
+    async fn positive_query() {
+        let mut poller = Poller::new(config(device("Termometer 21.5 C", 0).await));
+
+        let reading = poller.query().await.unwrap();
+        assert_eq!(reading.message, SensorData::Temperature(21.5));
+
+        let reading = poller.query().await.unwrap();
+        assert_eq!(
+            reading.message,
+            SensorData::Temperature(21.5),
+            "Reuses the connection"
+        );
+    }
+
+    #[tokio::test]
+    async fn positive_retry_on_new_connection() {
+        let mut poller = Poller::new(config(device("Socket 1000 W", 1).await));
+
+        let reading = poller.query().await.unwrap();
+
+        assert!(matches!(reading.message, SensorData::Socket(s) if s.on && s.power == 1000.0));
+    }
+
+    #[tokio::test]
+    async fn negative_retries_exhausted() {
+        let address = device("Termometer 21.5 C", 10).await;
+        let mut poller = Poller::new(PollConfig {
+            retries: 1,
+            ..config(address)
+        });
+
+        let error = poller.query().await.unwrap_err();
+        assert!(format!("{:#}", error).contains("kitchen/termometer"));
+    }
+
+    #[tokio::test]
+    async fn negative_bad_reply() {
+        let mut poller = Poller::new(PollConfig {
+            query: "status".into(),
+            retries: 0,
+            ..config(device("Termometer 21.5 C", 0).await)
+        });
+
+        let error = poller.query().await.unwrap_err();
+        assert!(format!("{:#}", error).contains("bad reply \"unknown command\""));
+    }
+
+    #[tokio::test]
+    async fn negative_unreachable() {
+        let address = support::vacant().await;
+
+        let mut poller = Poller::new(PollConfig {
+            retries: 0,
+            ..config(address)
+        });
+        assert!(poller.query().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn positive_polled_and_pushed_together() {
+        let state = Arc::new(Mutex::new(state()));
+        let (tx, mut rx) = mpsc::channel(32);
+
+        let address = device("Termometer 21.5 C", 0).await;
+        tokio::spawn(poll::poll(config(address), Arc::clone(&state), tx.clone()));
+
+        let addr = support::serve("127.0.0.1:0", &state, tx).await;
+        let mut client = Client::new(&addr);
+        let reply = client.send(&"kitchen/kettle Socket 800 W").await.unwrap();
+        assert_eq!(reply, Reply::Ack(800.0));
+
+        let polled = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                if let Some(event) = rx.recv().await
+                    && let DeviceEvent::Reading(reading) = event.as_ref()
+                    && reading.device.as_deref() == Some("kitchen/termometer")
+                {
+                    return reading.data.clone();
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(polled, SensorData::Temperature(21.5));
+
+        let state = state.lock().unwrap();
+        let kitchen = state.house().room("kitchen").unwrap();
+        assert_eq!(kitchen.device("termometer").unwrap().data(), polled);
+        assert!(matches!(
+            kitchen.device("kettle").unwrap().data(),
+            SensorData::Socket(s) if s.power == 800.0
+        ));
+    }
+
+    #[tokio::test]
+    async fn negative_failed_poll_reported() {
+        let address = support::vacant().await;
+        let (tx, mut rx) = mpsc::channel(32);
+
+        tokio::spawn(poll::poll(
+            PollConfig {
+                retries: 0,
+                ..config(address)
+            },
+            Arc::new(Mutex::new(state())),
+            tx,
+        ));
+
+        let event = rx.recv().await.unwrap();
+        assert!(
+            matches!(event.as_ref(), DeviceEvent::Rejected { message, .. } if message == "report")
+        );
+    }
+}
+
+mod emulator_test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use otus_tokio_devices::{
+        device::Device,
+        emulator::SocketEmulator,
+        event::DeviceEvent,
+        message::SocketMessage,
+        poll::{self, PollConfig, Poller},
+        sensor_data::SensorData,
+        socket::{SocketCommand, SocketReading, Telemetry},
+        state::State,
+    };
+    use tokio::sync::mpsc;
+
+    use crate::support;
+
+    #[test]
+    fn positive_answers() {
+        let mut emulator = SocketEmulator::new(1500.0).unwrap();
+
+        assert_eq!(
+            emulator.answer("report power"),
+            SocketMessage::Reading(SocketReading::on(1500.0))
+        );
+        assert_eq!(emulator.answer("report"), emulator.answer("report power"));
+        assert_eq!(
+            emulator.answer("off"),
+            SocketMessage::Reading(SocketReading::off())
+        );
+        assert_eq!(
+            emulator.answer("power 800"),
+            SocketMessage::Reading(SocketReading::off())
+        );
+        assert_eq!(
+            emulator.answer("on"),
+            SocketMessage::Reading(SocketReading::on(800.0)),
+            "Restores the set power"
+        );
+        assert_eq!(emulator.answer("power 1200").to_string(), "Socket 1200 W");
+        assert_eq!(emulator.socket().reading(), 1200.0);
+    }
+
+    #[test]
+    fn negative_answers() {
+        let mut emulator = SocketEmulator::new(1500.0).unwrap();
+
+        assert!(matches!(emulator.answer("explode"), SocketMessage::Nack(_)));
+        assert_eq!(
+            emulator.answer("power 99999"),
+            SocketMessage::Nack("power 99999 W is out of 500..=2000 W".into())
+        );
+        assert_eq!(
+            emulator.answer("report"),
+            SocketMessage::Reading(SocketReading::on(1500.0)),
+            "Out of range power is not taken"
+        );
+    }
+
+    #[test]
+    fn negative_rated_power_out_of_range() {
+        assert!(SocketEmulator::new(99999.0).is_err());
+        assert!(SocketEmulator::new(0.0).is_err());
+    }
+
+    #[test]
+    fn positive_socket_message_round_trip() {
+        for message in [
+            SocketMessage::Reading(SocketReading::off()),
+            SocketMessage::Reading(SocketReading::on(1500.0)),
+            SocketMessage::Reading(SocketReading {
+                telemetry: Telemetry {
+                    voltage: Some(230.0),
+                    current: Some(6.5),
+                    ..Telemetry::default()
+                },
+                ..SocketReading::on(1500.0)
+            }),
+            SocketMessage::Nack("unknown socket command".into()),
+        ] {
+            assert_eq!(
+                message.to_string().parse::<SocketMessage>().unwrap(),
+                message
+            );
+        }
+        assert!("Socket lots W".parse::<SocketMessage>().is_err());
+        assert!("Termometer 21 C".parse::<SocketMessage>().is_err());
+    }
+
+    #[tokio::test]
+    async fn positive_controller() {
+        let (addr, emulator) = support::emulator(1000.0).await;
+        let mut controller = Poller::new(PollConfig {
+            query: "report power".into(),
+            ..PollConfig::new("kitchen/kettle", addr)
+        });
+
+        let state = controller.command(SocketCommand::Off).await.unwrap();
+        assert_eq!(state.message, SensorData::Socket(SocketReading::off()));
+        assert!(!emulator.lock().unwrap().socket().is_on());
+
+        controller
+            .command(SocketCommand::Power(1800.0))
+            .await
+            .unwrap();
+        let state = controller.command(SocketCommand::On).await.unwrap();
+        assert_eq!(state.message, SensorData::Socket(SocketReading::on(1800.0)));
+
+        let state = controller.query().await.unwrap();
+        assert_eq!(state.message, SensorData::Socket(SocketReading::on(1800.0)));
+    }
+
+    #[tokio::test]
+    async fn positive_server_polls_emulator() {
+        let (addr, emulator) = support::emulator(1000.0).await;
+        emulator.lock().unwrap().answer("power 1300");
+        let state = Arc::new(Mutex::new(State::default()));
+        let (tx, mut rx) = mpsc::channel(32);
+
+        // Настройки по умолчанию, как в `[[poll]]` с одним адресом
+        let config: PollConfig =
+            toml::from_str(&format!("device = \"socket\"\naddress = \"{}\"", addr)).unwrap();
+        tokio::spawn(poll::poll(config, Arc::clone(&state), tx));
+
+        let event = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(event.as_ref(), DeviceEvent::Reading(_)));
+        assert_eq!(state.lock().unwrap().socket().reading(), 1300.0);
+    }
+}
+
+mod limits_test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use otus_tokio_devices::{
+        client::Client,
+        limits::{Connections, LimitsConfig, Refusal},
+        reply::Reply,
+        state::State,
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+    };
+
+    use crate::support;
+
+    const LIMITS: LimitsConfig = LimitsConfig {
+        max_connections: 3,
+        max_per_ip: 2,
+        idle_timeout_ms: 300,
+        read_timeout_ms: 200,
+        max_frame: 64,
+        device_rate: None,
+        connection_rate: None,
+    };
+
+    async fn server(limits: LimitsConfig) -> (String, Arc<Mutex<State>>) {
+        support::start(State::default().with_limits(limits)).await
+    }
+
+    async fn read_line(stream: &mut TcpStream) -> String {
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        line
+    }
+
+    #[test]
+    fn positive_connections_counted() {
+        let (a, b) = (
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+        );
+        let mut connections = Connections::default();
+
+        assert!(connections.open(a, &LIMITS).is_ok());
+        assert!(connections.open(a, &LIMITS).is_ok());
+        assert_eq!(connections.open(a, &LIMITS), Err(Refusal::TooManyFromIp(2)));
+        assert!(connections.open(b, &LIMITS).is_ok());
+        assert_eq!(
+            connections.open(b, &LIMITS),
+            Err(Refusal::TooManyConnections(3))
+        );
+
+        connections.close(a);
+        assert_eq!(connections.from_ip(a), 1);
+        assert!(connections.open(b, &LIMITS).is_ok());
+        assert_eq!(connections.total(), 3);
+    }
+
+    #[tokio::test]
+    async fn negative_per_ip_cap() {
+        let (addr, state) = server(LIMITS).await;
+        let mut first = Client::new(&addr);
+        let mut second = Client::new(&addr);
+        assert!(first.send(&"Termometer 20 C").await.unwrap().is_ack());
+        assert!(second.send(&"Termometer 20 C").await.unwrap().is_ack());
+
+        let mut third = TcpStream::connect(&addr).await.unwrap();
+        let reply: Reply = read_line(&mut third).await.parse().unwrap();
+        assert_eq!(
+            reply,
+            Reply::Busy("too many connections from your address (2)".into())
+        );
+        assert_eq!(
+            state.lock().unwrap().metrics().refused["too_many_from_ip"],
+            1
+        );
+
+        first.disconnect();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut fourth = Client::new(&addr);
+        assert!(fourth.send(&"Termometer 20 C").await.unwrap().is_ack());
+        assert_eq!(state.lock().unwrap().connections().total(), 2);
+        assert_eq!(state.lock().unwrap().metrics().connections, 3);
+    }
+
+    #[tokio::test]
+    async fn negative_max_connections() {
+        let (addr, state) = server(LimitsConfig {
+            max_connections: 1,
+            ..LIMITS
+        })
+        .await;
+        let _first = TcpStream::connect(&addr).await.unwrap();
+
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        assert_eq!(
+            read_line(&mut second).await.trim(),
+            "Busy server is full (1 connections)"
+        );
+        assert_eq!(state.lock().unwrap().metrics().refused_total(), 1);
+    }
+
+    #[tokio::test]
+    async fn negative_idle_timeout() {
+        let (addr, state) = server(LIMITS).await;
+        let mut idle = TcpStream::connect(&addr).await.unwrap();
+
+        let line = tokio::time::timeout(Duration::from_secs(2), read_line(&mut idle))
+            .await
+            .unwrap();
+        assert_eq!(line.trim(), "Busy idle for 300 ms");
+        assert_eq!(read_line(&mut idle).await, "", "Connection closed");
+        assert_eq!(state.lock().unwrap().metrics().refused["idle"], 1);
+        assert_eq!(state.lock().unwrap().connections().total(), 0);
+    }
+
+    #[tokio::test]
+    async fn negative_slow_frame() {
+        let (addr, state) = server(LIMITS).await;
+        let mut slow = TcpStream::connect(&addr).await.unwrap();
+
+        slow.write_all(b"Termo").await.unwrap();
+        let line = tokio::time::timeout(Duration::from_secs(2), read_line(&mut slow))
+            .await
+            .unwrap();
+        assert_eq!(line.trim(), "Busy frame not completed within 200 ms");
+        assert_eq!(state.lock().unwrap().metrics().refused["slow_frame"], 1);
+    }
+
+    #[tokio::test]
+    async fn negative_frame_too_large() {
+        let (addr, state) = server(LIMITS).await;
+        let mut client = Client::new(&addr);
+        let reply = client.send(&"x".repeat(64)).await.unwrap();
+        assert_eq!(
+            reply,
+            Reply::Nack("unknown message".into()),
+            "Parsed, not refused"
+        );
+
+        let reply = client.send(&"x".repeat(65)).await.unwrap();
+
+        assert_eq!(reply, Reply::Nack("frame longer than 64 bytes".into()));
+        assert_eq!(
+            state.lock().unwrap().metrics().refused["frame_too_large"],
+            1
+        );
+    }
+}
+
+mod rate_test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use otus_tokio_devices::{
+        client::Client,
+        device::Device,
+        event::DeviceEvent,
+        limits::{LimitsConfig, RateLimit, TokenBucket},
+        reading::Reading,
+        registry::{DeviceConfig, Registry},
+        reply::Reply,
+        sensor_data::SensorData,
+        socket::SocketReading,
+        state::State,
+    };
+    use tokio::sync::mpsc;
+
+    use crate::support;
+
+    const SLOW: RateLimit = RateLimit {
+        rate: 0.001,
+        burst: 2,
+    };
+
+    /// Same as [`support::start`], keeping the events.
+    async fn server(state: State) -> (Client, Arc<Mutex<State>>, mpsc::Receiver<Arc<DeviceEvent>>) {
+        let state = Arc::new(Mutex::new(state));
+        let (tx, rx) = mpsc::channel(32);
+        let addr = support::serve("127.0.0.1:0", &state, tx).await;
+
+        (Client::new(&addr), state, rx)
+    }
+
+    fn reading(data: SensorData) -> Reading {
+        Reading::new(data, None)
+    }
+
+    #[test]
+    fn positive_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(
+            RateLimit {
+                rate: 2.0,
+                burst: 3,
+            },
+            start,
+        );
+
+        assert!((0..3).all(|_| bucket.take(start)));
+        assert!(!bucket.take(start));
+
+        let later = start + Duration::from_secs(1);
+        assert!(bucket.take(later));
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+
+        let much_later = start + Duration::from_secs(60);
+        assert_eq!((0..10).filter(|_| bucket.take(much_later)).count(), 3);
+    }
+
+    #[test]
+    fn positive_deadband_by_graduation() {
+        let mut state = State::default().with_deadband(true);
+        state.apply(&mut reading(SensorData::Temperature(20.0)));
+
+        let filtered = state.filter(&reading(SensorData::Temperature(20.3)));
+        assert_eq!(filtered, Some(Reply::Ack(20.0)));
+        assert_eq!(state.filter(&reading(SensorData::Temperature(20.5))), None);
+        assert_eq!(state.filter(&reading(SensorData::Temperature(19.4))), None);
+        assert_eq!(state.metrics().dropped["deadband"], 1);
+    }
+
+    #[test]
+    fn positive_deadband_configured() {
+        let devices = HashMap::from([(
+            "kitchen".to_string(),
+            DeviceConfig {
+                deadband: Some(2.0),
+                ..DeviceConfig::default()
+            },
+        )]);
+        let mut state = State::default().with_registry(Registry::new(devices, false));
+        let kitchen = |data| reading(data).with_device(Some("kitchen".into()));
+        state.apply(&mut kitchen(SensorData::Temperature(20.0)));
+
+        assert!(
+            state
+                .filter(&kitchen(SensorData::Temperature(21.5)))
+                .is_some()
+        );
+        assert!(
+            state
+                .filter(&kitchen(SensorData::Temperature(22.0)))
+                .is_none()
+        );
+        assert!(
+            state
+                .filter(&reading(SensorData::Temperature(20.1)))
+                .is_none(),
+            "Other devices are not filtered"
+        );
+    }
+
+    #[test]
+    fn negative_deadband_keeps_switching_and_first_reading() {
+        let mut state = State::default().with_deadband(true);
+
+        assert!(
+            state.filter(&reading(SensorData::Humidity(0.0))).is_none(),
+            "Nothing stored yet"
+        );
+
+        state.apply(&mut reading(SensorData::Socket(SocketReading::on(1000.0))));
+        let off = reading(SensorData::Socket(SocketReading::off()));
+        assert!(state.filter(&off).is_none());
+        let same = reading(SensorData::Socket(SocketReading::on(1001.0)));
+        assert!(state.filter(&same).is_some());
+    }
+
+    #[tokio::test]
+    async fn negative_device_rate() {
+        let state = State::default().with_limits(LimitsConfig {
+            device_rate: Some(SLOW),
+            ..LimitsConfig::default()
+        });
+        let (mut client, state, mut rx) = server(state).await;
+
+        assert!(client.send(&"Termometer 20 C").await.unwrap().is_ack());
+        assert!(client.send(&"Termometer 21 C").await.unwrap().is_ack());
+        let reply = client.send(&"Termometer 22 C").await.unwrap();
+        assert_eq!(reply, Reply::Busy("rate limit exceeded".into()));
+        assert!(
+            client.send(&"Hygrometer 40 %").await.unwrap().is_ack(),
+            "Other devices have their own bucket"
+        );
+
+        assert_eq!(
+            state.lock().unwrap().termometer().data(),
+            SensorData::Temperature(21.0)
+        );
+        assert_eq!(state.lock().unwrap().metrics().dropped["device_rate"], 1);
+        let mut events = 0;
+        while rx.try_recv().is_ok() {
+            events += 1;
+        }
+        assert_eq!(events, 3, "Dropped messages do not reach the TUI");
+    }
+
+    #[tokio::test]
+    async fn negative_batch_takes_a_token_per_reading() {
+        let state = State::default().with_limits(LimitsConfig {
+            device_rate: Some(SLOW),
+            ..LimitsConfig::default()
+        });
+        let (mut client, state, _rx) = server(state).await;
+
+        let reply = client
+            .send(&"Batch Termometer 20 C; Termometer 21 C; Termometer 22 C")
+            .await
+            .unwrap();
+        assert_eq!(reply, Reply::Busy("rate limit exceeded".into()));
+        let reply = client
+            .send(&"Batch Termometer 20 C; Termometer 21 C")
+            .await
+            .unwrap();
+        assert_eq!(reply, Reply::AckBatch(2));
+        let reply = client.send(&"Termometer 22 C").await.unwrap();
+        assert_eq!(reply, Reply::Busy("rate limit exceeded".into()));
+        assert_eq!(state.lock().unwrap().history().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn negative_refused_batch_takes_no_tokens() {
+        let state = State::default().with_limits(LimitsConfig {
+            device_rate: Some(SLOW),
+            ..LimitsConfig::default()
+        });
+        let (mut client, state, _rx) = server(state).await;
+
+        let reply = client
+            .send(&"Batch Termometer 20 C; Termometer 21 C; Hygrometer 40 %; Hygrometer 41 %; Hygrometer 42 %")
+            .await
+            .unwrap();
+        assert_eq!(reply, Reply::Busy("rate limit exceeded".into()));
+
+        let reply = client
+            .send(&"Batch Termometer 20 C; Termometer 21 C; Hygrometer 40 %; Hygrometer 41 %")
+            .await
+            .unwrap();
+        assert_eq!(
+            reply,
+            Reply::AckBatch(4),
+            "The refused batch left the termometer bucket full"
+        );
+        assert_eq!(state.lock().unwrap().history().len(), 4);
+    }
+
+    #[test]
+    fn positive_deadband_in_batch() {
+        let mut state = State::default().with_deadband(true);
+        state.apply(&mut reading(SensorData::Temperature(20.0)));
+
+        let mut batch: Vec<Reading> = [20.2, 21.0, 21.2, 19.0]
+            .into_iter()
+            .map(|v| reading(SensorData::Temperature(v)))
+            .collect();
+
+        assert_eq!(state.filter_batch(&mut batch), None);
+        let kept: Vec<f32> = batch.iter().filter_map(|r| r.data.value()).collect();
+        assert_eq!(kept, [21.0, 19.0], "Compared with the value kept before");
+        assert_eq!(state.metrics().dropped["deadband"], 2);
+    }
+
+    #[test]
+    fn negative_unknown_devices_are_not_throttled() {
+        let mut state = State::default().with_limits(LimitsConfig {
+            device_rate: Some(SLOW),
+            ..LimitsConfig::default()
+        });
+        let nowhere = reading(SensorData::Temperature(20.0)).with_device(Some("nowhere/x".into()));
+
+        for _ in 0..5 {
+            assert_eq!(state.throttle(std::slice::from_ref(&nowhere)), None);
+        }
+        assert!(!state.metrics().dropped.contains_key("device_rate"));
+    }
+
+    #[tokio::test]
+    async fn negative_connection_rate() {
+        let state = State::default().with_limits(LimitsConfig {
+            connection_rate: Some(SLOW),
+            ..LimitsConfig::default()
+        });
+        let (mut client, state, _rx) = server(state).await;
+
+        assert!(client.send(&"Termometer 20 C").await.unwrap().is_ack());
+        assert!(client.send(&"Hygrometer 40 %").await.unwrap().is_ack());
+        let reply = client.send(&"Socket 1000 W").await.unwrap();
+
+        assert_eq!(reply, Reply::Busy("connection rate limit exceeded".into()));
+        assert_eq!(state.lock().unwrap().metrics().dropped_total(), 1);
+    }
+}
+
+mod metrics_test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use otus_tokio_devices::{
+        client::Client,
+        limits::{LimitsConfig, RateLimit},
+        metrics::{self, Histogram},
+        state::State,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::support;
+
+    async fn scrape(addr: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn positive_histogram() {
+        let mut histogram = Histogram::new(&[0.001, 0.01]);
+
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(1));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_secs(1));
+
+        let buckets: Vec<_> = histogram.cumulative().collect();
+        assert_eq!(
+            buckets,
+            [
+                ("0.001".to_string(), 2),
+                ("0.01".to_string(), 3),
+                ("+Inf".to_string(), 4)
+            ]
+        );
+        assert_eq!(histogram.count(), 4);
+        assert!((histogram.sum() - 1.0065).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn positive_scrape() {
+        let state = State::default()
+            .with_house(support::house(support::KITCHEN))
+            .with_limits(LimitsConfig {
+                device_rate: Some(RateLimit {
+                    rate: 0.001,
+                    burst: 2,
+                }),
+                ..LimitsConfig::default()
+            });
+        let (addr, state) = support::start(state).await;
+        let (listener, metrics_addr) = support::listener().await;
+        tokio::spawn(metrics::serve(listener, Arc::clone(&state)));
+
+        let mut client = Client::new(&addr);
+        for message in [
+            "kitchen/termometer Termometer 21.5 C",
+            "kitchen/kettle Socket 1200 W",
+            "Fridge 4 C",
+            "Termometer hot",
+            "kitchen/termometer Termometer 22 C",
+            "kitchen/termometer Termometer 23 C",
+        ] {
+            client.send(&message).await.unwrap();
+        }
+
+        let response = scrape(&metrics_addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("content-type: text/plain; version=0.0.4"));
+        for line in [
+            "# TYPE devices_temperature_celsius gauge",
+            "devices_temperature_celsius{device=\"kitchen/termometer\"} 22",
+            "devices_power_watts{device=\"kitchen/kettle\"} 1200",
+            "devices_socket_on{device=\"kitchen/kettle\"} 1",
+            "devices_messages_total 6",
+            "devices_parse_errors_total 1",
+            "devices_unknown_messages_total 1",
+            "devices_connections_total 1",
+            "devices_connections_open 1",
+            "devices_dropped_total{reason=\"device_rate\"} 1",
+            "# TYPE devices_processing_seconds histogram",
+            "devices_processing_seconds_bucket{le=\"+Inf\"} 6",
+            "devices_processing_seconds_count 6",
+        ] {
+            assert!(
+                response.contains(line),
+                "{} missing from\n{}",
+                line,
+                response
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn negative_unknown_path() {
+        let (listener, addr) = support::listener().await;
+        tokio::spawn(metrics::serve(
+            listener,
+            Arc::new(Mutex::new(State::default())),
+        ));
+
+        let response = scrape(&addr, "/status").await;
+
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
+
+mod logging_test {
+    use otus_tokio_devices::logging::LogPane;
+    use tracing::Level;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn capture(pane: &LogPane, f: impl FnOnce()) {
+        let subscriber = tracing_subscriber::registry().with(pane.clone());
+        tracing::subscriber::with_default(subscriber, f);
+    }
+
+    #[test]
+    fn positive_warnings_and_errors() {
+        let pane = LogPane::default();
+
+        capture(&pane, || {
+            tracing::warn!(reason = "idle for 10 ms", "connection cut off");
+            tracing::error!("server stopped");
+        });
+
+        let lines = pane.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0]
+                .text
+                .ends_with(" WARN connection cut off reason=idle for 10 ms")
+        );
+        assert!(lines[1].text.ends_with("ERROR server stopped"));
+        assert_eq!(
+            (lines[0].level, lines[1].level),
+            (Level::WARN, Level::ERROR)
+        );
+    }
+
+    #[test]
+    fn positive_connection_span() {
+        let pane = LogPane::default();
+
+        capture(&pane, || {
+            let span = tracing::info_span!(
+                "connection",
+                peer = "127.0.0.1:50000",
+                device = tracing::field::Empty
+            );
+            let _entered = span.enter();
+            span.record("device", "hall");
+            span.record("device", "kitchen");
+            tracing::warn!("authentication failed");
+        });
+
+        let lines = pane.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(
+            lines[0]
+                .text
+                .ends_with("authentication failed peer=127.0.0.1:50000 device=kitchen"),
+            "Recorded again, the field is replaced: {}",
+            lines[0].text
+        );
+    }
+
+    #[test]
+    fn positive_oldest_forgotten() {
+        let pane = LogPane::default();
+
+        capture(&pane, || {
+            for i in 0..LogPane::CAPACITY + 5 {
+                tracing::warn!("warning {}", i);
+            }
+        });
+
+        let lines = pane.lines();
+        assert_eq!(lines.len(), LogPane::CAPACITY);
+        assert!(lines[0].text.ends_with("warning 5"));
+    }
+
+    #[test]
+    fn negative_info_skipped() {
+        let pane = LogPane::default();
+
+        capture(&pane, || {
+            tracing::info!("server started");
+            tracing::debug!("connected");
+        });
+
+        assert!(pane.lines().is_empty());
+    }
+}
+
+mod api_test {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{TimeZone, Utc};
+    use otus_tokio_devices::{
+        api::{self, Api},
+        event::DeviceEvent,
+        poll::PollConfig,
+        reading::Reading,
+        sensor_data::SensorData,
+        socket::SocketReading,
+        state::State,
+    };
+    use serde_json::{Value, json};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::mpsc,
+    };
+
+    use crate::support;
+
+    fn reading(device: &str, data: SensorData, minute: u32) -> Reading {
+        let time = Utc.with_ymd_and_hms(2025, 4, 1, 10, minute, 0).unwrap();
+        Reading::new(data, Some(time)).with_device(Some(device.to_string()))
+    }
+
+    /// Serves the API over the state, the kettle polled at `kettle` if given.
+    async fn api(
+        state: State,
+        kettle: Option<String>,
+    ) -> (String, mpsc::Receiver<Arc<DeviceEvent>>) {
+        let (listener, addr) = support::listener().await;
+        let (tx, rx) = mpsc::channel(32);
+        let poll = kettle
+            .map(|address| PollConfig::new("kitchen/kettle", address))
+            .into_iter()
+            .collect();
+
+        let api = Api::new(Arc::new(Mutex::new(state.with_poll(poll))), tx);
+        tokio::spawn(api::serve(listener, api));
+
+        (addr, rx)
+    }
+
+    /// Status code and JSON body of the response.
+    async fn request(addr: &str, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+        let body = body.map(|b| b.to_string()).unwrap_or_default();
+        send(addr, method, path, "", &body).await
+    }
+
+    /// Same as [`request`] with extra header lines and a raw body.
+    async fn send(addr: &str, method: &str, path: &str, headers: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\
+             Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn positive_devices() {
+        let mut state = State::default().with_house(support::house(support::KITCHEN));
+        state.apply(&mut reading(
+            "kitchen/termometer",
+            SensorData::Temperature(22.0),
+            0,
+        ));
+        let (addr, _rx) = api(state, None).await;
+
+        let (status, body) = request(&addr, "GET", "/devices", None).await;
+
+        assert_eq!(status, 200);
+        let devices = body.as_array().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0]["device"], "kitchen/termometer");
+        assert_eq!(devices[0]["kind"], "Termometer");
+        assert_eq!(devices[0]["value"], 22.0);
+        assert_eq!(devices[0]["unit"], "C");
+        assert_eq!(devices[0]["presence"], "online");
+        assert_eq!(devices[1]["device"], "kitchen/kettle");
+        assert_eq!(devices[1]["presence"], "offline");
+    }
+
+    #[tokio::test]
+    async fn positive_device_by_path() {
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            None,
+        )
+        .await;
+
+        let (status, body) = request(&addr, "GET", "/devices/kitchen/kettle", None).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["kind"], "Socket");
+        assert_eq!(body["unit"], "W");
+        assert_eq!(body["on"], false, "Not heard from yet, so off");
+    }
+
+    #[tokio::test]
+    async fn positive_standalone_device() {
+        let (addr, _rx) = api(State::default(), None).await;
+
+        let (status, body) = request(&addr, "GET", "/devices/Termometer", None).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["device"], "Termometer");
+        assert_eq!(body["on"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn negative_unknown_device() {
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            None,
+        )
+        .await;
+
+        let (status, body) = request(&addr, "GET", "/devices/kitchen/oven", None).await;
+
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "unknown device kitchen/oven");
+    }
+
+    #[tokio::test]
+    async fn positive_history_range() {
+        let mut state = State::default().with_house(support::house(support::KITCHEN));
+        for (minute, t) in [(0, 20.0), (10, 21.0), (20, 22.0), (30, 23.0)] {
+            state.apply(&mut reading(
+                "kitchen/termometer",
+                SensorData::Temperature(t),
+                minute,
+            ));
+        }
+        state.apply(&mut reading(
+            "kitchen/kettle",
+            SensorData::Socket(SocketReading::on(1000.0)),
+            15,
+        ));
+        let (addr, _rx) = api(state, None).await;
+
+        let path = "/history?device=kitchen/termometer\
+                    &from=2025-04-01T10:05:00Z&to=2025-04-01T10:20:00Z";
+        let (status, body) = request(&addr, "GET", path, None).await;
+
+        assert_eq!(status, 200);
+        let values: Vec<f64> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["value"].as_f64().unwrap())
+            .collect();
+        assert_eq!(values, [21.0, 22.0]);
+        assert_eq!(body[0]["time"], "2025-04-01T10:10:00Z");
+
+        let (_, body) = request(&addr, "GET", "/history", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn positive_command() {
+        let (kettle, _) = support::emulator(1000.0).await;
+        let (addr, mut rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            Some(kettle),
+        )
+        .await;
+
+        let command = json!({ "device": "kitchen/kettle", "command": "off" });
+        let (status, body) = request(&addr, "POST", "/commands", Some(command)).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["on"], false);
+        assert_eq!(body["value"], 0.0);
+        assert!(matches!(
+            &*rx.recv().await.unwrap(),
+            DeviceEvent::Command(command) if command == "kitchen/kettle off"
+        ));
+        assert!(matches!(
+            &*rx.recv().await.unwrap(),
+            DeviceEvent::Reading(r) if r.data == SensorData::Socket(SocketReading::off())
+        ));
+
+        let command = json!({ "device": "kitchen/kettle", "command": "power 1500" });
+        let (_, body) = request(&addr, "POST", "/commands", Some(command)).await;
+        assert_eq!(
+            body["on"], false,
+            "Switched off socket only remembers power"
+        );
+
+        let command = json!({ "device": "kitchen/kettle", "command": "on" });
+        let (_, body) = request(&addr, "POST", "/commands", Some(command)).await;
+        assert_eq!(body["on"], true);
+        assert_eq!(body["value"], 1500.0);
+    }
+
+    #[tokio::test]
+    async fn negative_command_not_polled() {
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            None,
+        )
+        .await;
+
+        let command = json!({ "device": "kitchen/kettle", "command": "on" });
+        let (status, body) = request(&addr, "POST", "/commands", Some(command)).await;
+
+        assert_eq!(status, 409);
+        assert_eq!(
+            body["error"],
+            "kitchen/kettle is not polled, the server cannot reach it"
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_command_to_termometer() {
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            None,
+        )
+        .await;
+
+        let command = json!({ "device": "kitchen/termometer", "command": "on" });
+        let (status, body) = request(&addr, "POST", "/commands", Some(command)).await;
+
+        assert_eq!(status, 400);
+        assert_eq!(
+            body["error"],
+            "kitchen/termometer is a Termometer, not a socket"
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_unknown_command() {
+        let (kettle, _) = support::emulator(1000.0).await;
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            Some(kettle),
+        )
+        .await;
+
+        let command = json!({ "device": "kitchen/kettle", "command": "boil" });
+        let (status, body) = request(&addr, "POST", "/commands", Some(command)).await;
+
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "unknown socket command \"boil\"");
+    }
+
+    #[tokio::test]
+    async fn negative_malformed_command() {
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            None,
+        )
+        .await;
+
+        let (status, body) = send(&addr, "POST", "/commands", "", "{\"device\":").await;
+
+        assert_eq!(status, 400);
+        assert!(body["error"].is_string(), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn positive_command_with_token() {
+        let (kettle, _) = support::emulator(1000.0).await;
+        let (listener, addr) = support::listener().await;
+        let state = Arc::new(Mutex::new(
+            State::default().with_house(support::house(support::KITCHEN)),
+        ));
+        let api = Api::new(Arc::clone(&state), support::drain()).with_token(Some("secret".into()));
+        tokio::spawn(api::serve(listener, api));
+        let command = json!({ "device": "kitchen/kettle", "command": "off" }).to_string();
+
+        let (status, body) = send(&addr, "POST", "/commands", "", &command).await;
+        assert_eq!(status, 401, "Even local clients need the token");
+        assert_eq!(body["error"], "commands need the API token");
+        let wrong = "Authorization: Bearer other\r\n";
+        let (status, _) = send(&addr, "POST", "/commands", wrong, &command).await;
+        assert_eq!(status, 401);
+
+        let token = "Authorization: Bearer secret\r\n";
+        let (status, _) = send(&addr, "POST", "/commands", token, &command).await;
+        assert_eq!(status, 409, "Not polled yet");
+        state
+            .lock()
+            .unwrap()
+            .set_poll(vec![PollConfig::new("kitchen/kettle", kettle)]);
+        let (status, body) = send(&addr, "POST", "/commands", token, &command).await;
+        assert_eq!(status, 200, "The reloaded poll list is used");
+        assert_eq!(body["on"], false);
+    }
+
+    #[tokio::test]
+    async fn negative_device_unreachable() {
+        let kettle = support::vacant().await;
+        let (addr, _rx) = api(
+            State::default().with_house(support::house(support::KITCHEN)),
+            Some(kettle),
+        )
+        .await;
+
+        let command = json!({ "device": "kitchen/kettle", "command": "on" });
+        let (status, body) = request(&addr, "POST", "/commands", Some(command)).await;
+
+        assert_eq!(status, 502);
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("kitchen/kettle at 127.0.0.1:"),
+            "{}",
+            body
+        );
+    }
+}
+
+mod stream_test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use futures::StreamExt;
+    use otus_tokio_devices::{
+        api::{self, Api},
+        event::DeviceEvent,
+        house::House,
+        reading::Reading,
+        sensor_data::SensorData,
+        socket::SocketReading,
+        state::State,
+        stream::{self, Filter, StreamConfig},
+    };
+    use serde_json::Value;
+    use tokio::{
+        net::TcpStream,
+        sync::{broadcast, mpsc},
+    };
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
+
+    use crate::support;
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    fn house() -> House {
+        let hall = r#"
+            [[rooms]]
+            name = "hall"
+            devices = [{ id = "termometer", kind = "Termometer" }]
+            "#;
+
+        support::house(&[support::KITCHEN, hall].concat())
+    }
+
+    fn reading(device: &str, data: SensorData) -> Reading {
+        Reading::new(data, None).with_device(Some(device.to_string()))
+    }
+
+    fn event(device: &str, data: SensorData) -> Arc<DeviceEvent> {
+        Arc::new(DeviceEvent::Reading(reading(device, data)))
+    }
+
+    /// Serves the API with the stream, returning its address and the sender
+    /// events are published on.
+    async fn api(
+        state: State,
+        config: StreamConfig,
+    ) -> (String, broadcast::Sender<Arc<DeviceEvent>>) {
+        let (listener, addr) = support::listener().await;
+        let (events, _) = broadcast::channel(config.capacity);
+
+        let api = Api::new(Arc::new(Mutex::new(state)), support::drain())
+            .with_stream(events.clone(), config);
+        tokio::spawn(api::serve(listener, api));
+
+        (addr, events)
+    }
+
+    async fn connect(addr: &str, query: &str) -> Client {
+        let url = format!("ws://{}/stream{}", addr, query);
+        let (client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        client
+    }
+
+    /// Next JSON message, skipping pings.
+    async fn next(client: &mut Client) -> Value {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(1), client.next())
+                .await
+                .expect("no message in time")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn positive_filter() {
+        let filter = Filter {
+            kind: Some("Termometer,Hygrometer".into()),
+            room: Some("kitchen".into()),
+            ..Filter::default()
+        };
+
+        assert!(filter.matches("kitchen/termometer", "Termometer"));
+        assert!(!filter.matches("kitchen/kettle", "Socket"));
+        assert!(!filter.matches("hall/termometer", "Termometer"));
+        assert!(Filter::default().matches("Socket", "Socket"));
+
+        let filter = Filter {
+            device: Some("hall/termometer, Socket".into()),
+            ..Filter::default()
+        };
+        assert!(filter.matches("hall/termometer", "Termometer"));
+        assert!(filter.matches("Socket", "Socket"));
+        assert!(!filter.matches("kitchen/termometer", "Termometer"));
+    }
+
+    #[test]
+    fn negative_standalone_in_no_room() {
+        let filter = Filter {
+            room: Some("kitchen".into()),
+            ..Filter::default()
+        };
+
+        assert!(!filter.matches("Termometer", "Termometer"));
+    }
+
+    #[tokio::test]
+    async fn positive_fan_out() {
+        let (tx, rx) = mpsc::channel(32);
+        let (ui_tx, mut ui_rx) = mpsc::channel(32);
+        let (events, mut subscriber) = broadcast::channel(32);
+        let state = Arc::new(Mutex::new(State::default()));
+        tokio::spawn(stream::fan_out(rx, ui_tx, events, state));
+
+        tx.send(event("kitchen/termometer", SensorData::Temperature(21.0)))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            &*ui_rx.recv().await.unwrap(),
+            DeviceEvent::Reading(_)
+        ));
+        assert!(matches!(
+            &*subscriber.recv().await.unwrap(),
+            DeviceEvent::Reading(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn negative_slow_ui_does_not_hold_up_devices() {
+        let (tx, rx) = mpsc::channel(32);
+        let (ui_tx, mut ui_rx) = mpsc::channel(1);
+        let (events, mut subscriber) = broadcast::channel(32);
+        let state = Arc::new(Mutex::new(State::default()));
+        let fan_out = tokio::spawn(stream::fan_out(rx, ui_tx, events, Arc::clone(&state)));
+
+        for value in [21.0, 22.0, 23.0] {
+            tx.send(event("kitchen/termometer", SensorData::Temperature(value)))
+                .await
+                .unwrap();
+        }
+        drop(tx);
+        tokio::time::timeout(Duration::from_secs(1), fan_out)
+            .await
+            .unwrap()
+            .unwrap();
+
+        for _ in 0..3 {
+            assert!(
+                subscriber.recv().await.is_ok(),
+                "Stream clients get every event"
+            );
+        }
+        assert!(ui_rx.recv().await.is_some());
+        assert!(ui_rx.try_recv().is_err(), "The UI missed the rest");
+        assert_eq!(state.lock().unwrap().metrics().ui_dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn positive_snapshot_then_readings() {
+        let mut state = State::default().with_house(house());
+        state.apply(&mut reading(
+            "kitchen/termometer",
+            SensorData::Temperature(21.0),
+        ));
+        let (addr, events) = api(state, StreamConfig::default()).await;
+
+        let mut client = connect(&addr, "?room=kitchen&kind=Termometer").await;
+
+        let snapshot = next(&mut client).await;
+        assert_eq!(snapshot["type"], "snapshot");
+        let devices = snapshot["devices"].as_array().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0]["device"], "kitchen/termometer");
+        assert_eq!(devices[0]["value"], 21.0);
+
+        events
+            .send(event(
+                "kitchen/kettle",
+                SensorData::Socket(SocketReading::on(1000.0)),
+            ))
+            .unwrap();
+        events
+            .send(event("hall/termometer", SensorData::Temperature(19.0)))
+            .unwrap();
+        events
+            .send(event("kitchen/termometer", SensorData::Temperature(22.0)))
+            .unwrap();
+
+        let update = next(&mut client).await;
+        assert_eq!(update["type"], "reading");
+        assert_eq!(update["device"], "kitchen/termometer");
+        assert_eq!(update["kind"], "Termometer");
+        assert_eq!(update["value"], 22.0);
+        assert_eq!(update["unit"], "C");
+    }
+
+    #[tokio::test]
+    async fn positive_batch_reading_by_reading() {
+        let (addr, events) = api(
+            State::default().with_house(house()),
+            StreamConfig::default(),
+        )
+        .await;
+        let mut client = connect(&addr, "").await;
+        next(&mut client).await;
+
+        events
+            .send(Arc::new(DeviceEvent::Batch(vec![
+                reading("hall/termometer", SensorData::Temperature(18.0)),
+                reading("hall/termometer", SensorData::Temperature(19.0)),
+            ])))
+            .unwrap();
+
+        assert_eq!(next(&mut client).await["value"], 18.0);
+        assert_eq!(next(&mut client).await["value"], 19.0);
+    }
+
+    #[tokio::test]
+    async fn positive_lagged() {
+        let config = StreamConfig {
+            capacity: 2,
+            ..StreamConfig::default()
+        };
+        let (addr, events) = api(State::default().with_house(house()), config).await;
+        let mut client = connect(&addr, "").await;
+        next(&mut client).await;
+
+        // Без await между отправками поток клиента не успевает их забрать
+        for t in [18.0, 19.0, 20.0, 21.0, 22.0] {
+            events
+                .send(event("hall/termometer", SensorData::Temperature(t)))
+                .unwrap();
+        }
+
+        let lagged = next(&mut client).await;
+        assert_eq!(lagged["type"], "lagged");
+        assert_eq!(lagged["skipped"], 3);
+        assert_eq!(next(&mut client).await["value"], 21.0);
+        assert_eq!(next(&mut client).await["value"], 22.0);
+    }
+
+    #[tokio::test]
+    async fn positive_heartbeat() {
+        let config = StreamConfig {
+            heartbeat_ms: 50,
+            ..StreamConfig::default()
+        };
+        let (addr, _events) = api(State::default(), config).await;
+        let mut client = connect(&addr, "").await;
+        next(&mut client).await;
+
+        // Клиент отвечает на каждый ping, соединение остается открытым
+        let mut pings = 0;
+        while pings < 4 {
+            let message = tokio::time::timeout(Duration::from_secs(1), client.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            if let Message::Ping(_) = message {
+                pings += 1;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn negative_silent_client_disconnected() {
+        let config = StreamConfig {
+            heartbeat_ms: 50,
+            ..StreamConfig::default()
+        };
+        let (addr, _events) = api(State::default(), config).await;
+        let mut client = connect(&addr, "").await;
+        next(&mut client).await;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let closed = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                match client.next().await {
+                    Some(Ok(Message::Ping(_))) => {}
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(message)) => panic!("unexpected {:?}", message),
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "Connection is still open");
+    }
+
+    #[tokio::test]
+    async fn negative_stream_off() {
+        let (listener, addr) = support::listener().await;
+        let api = Api::new(Arc::new(Mutex::new(State::default())), support::drain());
+        tokio::spawn(api::serve(listener, api));
+
+        let url = format!("ws://{}/stream", addr);
+        let result = tokio_tungstenite::connect_async(url).await;
+
+        assert!(result.is_err());
     }
 }